@@ -0,0 +1,320 @@
+//! `mayl-sendmail`: a stand-in for `/usr/sbin/sendmail` that hands each
+//! message to a mayl server's `POST /email/raw` instead of a local MTA, for
+//! PHP's `mail()`, cron and anything else that pipes mail to sendmail.
+//!
+//! Settings come from `/etc/mayl-sendmail.conf` (or `MAYL_SENDMAIL_CONFIG`),
+//! one `key = value` per line, overridden by environment variables:
+//!
+//! | Key     | Variable             | Meaning                                   |
+//! |---------|----------------------|-------------------------------------------|
+//! | `url`   | `MAYL_URL`           | Base URL of the mayl server               |
+//! | `token` | `MAYL_TOKEN`         | Domain token to send with                 |
+//! | `from`  | `MAYL_SENDMAIL_FROM` | Sender when neither `-f` nor `From:` says |
+
+use std::{io::Read, process::ExitCode, time::Duration};
+
+use mail_parser::MessageParser;
+
+// sysexits.h, which callers of sendmail interpret
+const EX_USAGE: u8 = 64;
+const EX_DATAERR: u8 = 65;
+const EX_NOUSER: u8 = 67;
+const EX_SOFTWARE: u8 = 70;
+const EX_TEMPFAIL: u8 = 75;
+const EX_NOPERM: u8 = 77;
+const EX_CONFIG: u8 = 78;
+
+const DEFAULT_CONFIG: &str = "/etc/mayl-sendmail.conf";
+const DEFAULT_URL: &str = "http://localhost:8080";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Flags that take a value, which sendmail accepts and mayl has no use for.
+const IGNORED_WITH_VALUE: &[char] = &['A', 'B', 'C', 'L', 'N', 'O', 'R', 'V', 'X', 'h'];
+
+// ── Arguments ───────────────────────────────────────────────────────────────
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    /// `-t`: also send to everyone in `To`, `Cc` and `Bcc`.
+    read_recipients: bool,
+    /// `-i` / `-oi`: a line with a single `.` does not end the message.
+    ignore_dots: bool,
+    /// `-f` / `-r`: envelope sender.
+    from: Option<String>,
+    /// `-F`: full name for a generated `From` header.
+    full_name: Option<String>,
+    recipients: Vec<String>,
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut opts = Options::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            opts.recipients.extend(args.by_ref());
+            break;
+        }
+        let Some(flag) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+            opts.recipients.push(arg);
+            continue;
+        };
+
+        let mut chars = flag.chars();
+        let letter = chars.next().unwrap_or_default();
+        let attached = chars.as_str();
+        let mut value = || -> Result<String, String> {
+            if attached.is_empty() {
+                args.next().ok_or_else(|| format!("option -{letter} requires a value"))
+            } else {
+                Ok(attached.to_string())
+            }
+        };
+        match letter {
+            't' => opts.read_recipients = true,
+            'i' => opts.ignore_dots = true,
+            'f' | 'r' => opts.from = Some(value()?),
+            'F' => opts.full_name = Some(value()?),
+            'o' if attached == "i" => opts.ignore_dots = true,
+            // -oem, -odi, -bm, -v and friends change nothing here
+            'o' | 'v' | 'U' => {}
+            'b' if attached.is_empty() || attached == "m" => {}
+            'b' => return Err(format!("mode -b{attached} is not supported")),
+            c if IGNORED_WITH_VALUE.contains(&c) => {
+                value()?;
+            }
+            _ => return Err(format!("unknown option -{flag}")),
+        }
+    }
+    Ok(opts)
+}
+
+// ── Config ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Default, PartialEq)]
+struct Config {
+    url: Option<String>,
+    token: Option<String>,
+    from: Option<String>,
+}
+
+fn parse_config(text: &str) -> Result<Config, String> {
+    let mut config = Config::default();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected key = value", n + 1))?;
+        let value = Some(value.trim().trim_matches('"').to_string());
+        match key.trim() {
+            "url" => config.url = value,
+            "token" => config.token = value,
+            "from" => config.from = value,
+            other => return Err(format!("line {}: unknown key '{other}'", n + 1)),
+        }
+    }
+    Ok(config)
+}
+
+fn load_config() -> Result<Config, String> {
+    let path = std::env::var("MAYL_SENDMAIL_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG.into());
+    let mut config = match std::fs::read_to_string(&path) {
+        Ok(text) => parse_config(&text).map_err(|e| format!("{path}: {e}"))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Config::default(),
+        Err(e) => return Err(format!("{path}: {e}")),
+    };
+    let env = |key: &str| std::env::var(key).ok().filter(|v| !v.is_empty());
+    config.url = env("MAYL_URL").or(config.url);
+    config.token = env("MAYL_TOKEN").or(config.token);
+    config.from = env("MAYL_SENDMAIL_FROM").or(config.from);
+    Ok(config)
+}
+
+// ── Message ─────────────────────────────────────────────────────────────────
+
+/// Reads the message with CRLF line endings. Unless `ignore_dots`, a line
+/// holding only `.` ends it, as with classic sendmail.
+fn read_message(input: &[u8], ignore_dots: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() + input.len() / 32);
+    for line in input.split_inclusive(|&b| b == b'\n') {
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if !ignore_dots && content == b"." {
+            break;
+        }
+        out.extend_from_slice(content);
+        out.extend_from_slice(b"\r\n");
+    }
+    out
+}
+
+/// Whoever the message should be sent to, and the message with a `From`
+/// header added when it has none and a sender is known.
+fn complete(opts: &Options, from: Option<&str>, mut message: Vec<u8>) -> Result<(Vec<String>, Vec<u8>), String> {
+    let parsed = MessageParser::default()
+        .parse_headers(&message)
+        .ok_or("message could not be parsed")?;
+
+    let mut recipients = opts.recipients.clone();
+    if opts.read_recipients {
+        for list in [parsed.to(), parsed.cc(), parsed.bcc()].into_iter().flatten() {
+            recipients.extend(list.iter().filter_map(|a| a.address()).map(str::to_string));
+        }
+    }
+
+    if parsed.from().is_none()
+        && let Some(from) = from
+    {
+        let header = match &opts.full_name {
+            Some(name) => format!("From: \"{}\" <{from}>\r\n", name.replace(['"', '\\'], "")),
+            None => format!("From: {from}\r\n"),
+        };
+        message.splice(0..0, header.into_bytes());
+    }
+    Ok((recipients, message))
+}
+
+// ── Submission ──────────────────────────────────────────────────────────────
+
+fn exit_code(status: u16) -> u8 {
+    match status {
+        401 | 403 => EX_NOPERM,
+        422 => EX_NOUSER,
+        400..=499 => EX_DATAERR,
+        _ => EX_TEMPFAIL,
+    }
+}
+
+async fn submit(config: &Config, from: Option<&str>, to: &[String], message: Vec<u8>) -> Result<String, (u8, String)> {
+    let base = config.url.as_deref().unwrap_or(DEFAULT_URL).trim_end_matches('/');
+    let mut url = reqwest::Url::parse(&format!("{base}/email/raw")).map_err(|e| (EX_CONFIG, format!("url: {e}")))?;
+    {
+        let mut query = url.query_pairs_mut();
+        if let Some(from) = from {
+            query.append_pair("from", from);
+        }
+        query.append_pair("to", &to.join(","));
+    }
+    let token = config.token.as_deref().ok_or((EX_CONFIG, "no token configured".to_string()))?;
+
+    let response = reqwest::Client::new()
+        .post(url)
+        .timeout(REQUEST_TIMEOUT)
+        .bearer_auth(token)
+        .header("Content-Type", "message/rfc822")
+        .body(message)
+        .send()
+        .await
+        .map_err(|e| (EX_TEMPFAIL, format!("cannot reach {base}: {e}")))?;
+
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    let body: serde_json::Value = serde_json::from_str(&text).unwrap_or_default();
+    if status.is_success() {
+        Ok(body["id"].as_str().unwrap_or_default().to_string())
+    } else {
+        let error = body["error"].as_str().map_or_else(|| status.to_string(), str::to_string);
+        let rejected = body["rejected"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|r| format!("\n  {}: {}", r["address"].as_str().unwrap_or_default(), r["reason"].as_str().unwrap_or_default()))
+            .collect::<String>();
+        Err((exit_code(status.as_u16()), format!("{error}{rejected}")))
+    }
+}
+
+fn main() -> ExitCode {
+    let fail = |code: u8, message: &str| {
+        eprintln!("mayl-sendmail: {message}");
+        ExitCode::from(code)
+    };
+
+    let opts = match parse_args(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => return fail(EX_USAGE, &e),
+    };
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => return fail(EX_CONFIG, &e),
+    };
+
+    let mut input = Vec::new();
+    if let Err(e) = std::io::stdin().read_to_end(&mut input) {
+        return fail(EX_SOFTWARE, &format!("reading stdin: {e}"));
+    }
+    let from = opts.from.as_deref().or(config.from.as_deref());
+    let (to, message) = match complete(&opts, from, read_message(&input, opts.ignore_dots)) {
+        Ok(completed) => completed,
+        Err(e) => return fail(EX_DATAERR, &e),
+    };
+    if to.is_empty() {
+        return fail(EX_USAGE, "no recipients given (pass them as arguments or use -t)");
+    }
+
+    let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(rt) => rt,
+        Err(e) => return fail(EX_SOFTWARE, &e.to_string()),
+    };
+    match runtime.block_on(submit(&config, from, &to, message)) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err((code, e)) => fail(code, &e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Options, String> {
+        parse_args(line.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            args("-t -i -f cron@example.com -Fcron -odi -oem ops@example.org").unwrap(),
+            Options {
+                read_recipients: true,
+                ignore_dots: true,
+                from: Some("cron@example.com".into()),
+                full_name: Some("cron".into()),
+                recipients: vec!["ops@example.org".into()],
+            }
+        );
+        assert!(args("-oi -N never -- -weird@example.org").unwrap().ignore_dots);
+        assert_eq!(args("-- -weird@example.org").unwrap().recipients, vec!["-weird@example.org"]);
+        assert!(args("-bs").is_err());
+        assert!(args("-f").is_err());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = parse_config("# mayl\nurl = http://mayl:8080\ntoken = \"abc\"\n\n").unwrap();
+        assert_eq!(config.url.as_deref(), Some("http://mayl:8080"));
+        assert_eq!(config.token.as_deref(), Some("abc"));
+        assert!(parse_config("tokn = abc").is_err());
+    }
+
+    #[test]
+    fn test_message_handling() {
+        let input = b"To: ada@example.org\nBcc: audit@example.com\nSubject: hi\n\nline\n.\nafter\n";
+        assert_eq!(
+            read_message(input, false),
+            b"To: ada@example.org\r\nBcc: audit@example.com\r\nSubject: hi\r\n\r\nline\r\n".to_vec()
+        );
+        assert!(read_message(input, true).ends_with(b".\r\nafter\r\n"));
+
+        let opts = Options {
+            read_recipients: true,
+            full_name: Some("Cron Daemon".into()),
+            recipients: vec!["ops@example.org".into()],
+            ..Default::default()
+        };
+        let (to, message) = complete(&opts, Some("cron@example.com"), read_message(input, false)).unwrap();
+        assert_eq!(to, vec!["ops@example.org", "ada@example.org", "audit@example.com"]);
+        assert!(message.starts_with(b"From: \"Cron Daemon\" <cron@example.com>\r\nTo: "));
+    }
+}
@@ -0,0 +1,511 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use mail_parser::{MessageParser, MimeHeaders, PartType};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+
+use crate::{AppState, events, imap, normalize_address, now_millis, suppressions, webhooks};
+
+/// Sent messages are remembered this long; later DSNs are ignored.
+const RETENTION_MS: i64 = 14 * 24 * 3_600_000;
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+/// One recipient a delivery status notification reports as failed.
+#[derive(Debug, Clone, PartialEq)]
+struct Failure {
+    address: String,
+    /// RFC 3463 status code, e.g. `5.1.1`.
+    status: String,
+    diagnostic: Option<String>,
+}
+
+impl Failure {
+    fn permanent(&self) -> bool {
+        self.status.starts_with('5')
+    }
+
+    fn reason(&self) -> String {
+        match &self.diagnostic {
+            Some(d) => format!("{} {d}", self.status),
+            None => self.status.clone(),
+        }
+    }
+}
+
+/// The parts of a DSN that matter: which message bounced, and for whom.
+#[derive(Debug, Clone, PartialEq)]
+struct Dsn {
+    /// Message-ID of the original message, without angle brackets.
+    message_id: String,
+    failures: Vec<Failure>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BounceEntry {
+    id: String,
+    to: Vec<String>,
+    sent_at: i64,
+    bounced_at: i64,
+    reason: String,
+}
+
+// ── Database ────────────────────────────────────────────────────────────────
+
+pub(crate) fn init_db(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sent_messages (
+            message_id TEXT PRIMARY KEY,
+            queue_id TEXT NOT NULL,
+            domain TEXT NOT NULL,
+            from_addr TEXT NOT NULL,
+            to_addrs TEXT NOT NULL,
+            sent_at INTEGER NOT NULL,
+            status TEXT NOT NULL DEFAULT 'sent',
+            bounced_at INTEGER,
+            bounce_reason TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_sent_messages_domain ON sent_messages(domain, status);",
+    )
+    .expect("failed to initialize sent_messages table");
+}
+
+/// Forgets a deleted domain's sent mail, so late bounces are not matched
+/// to a domain re-created under the same name.
+pub(crate) fn delete_domain(conn: &Connection, domain: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM sent_messages WHERE domain = ?1", [domain]).map(|_| ())
+}
+
+/// Remembers a sent message so a later DSN can be traced back to it.
+pub(crate) fn record_sent(conn: &Connection, message_id: &str, queue_id: &str, from: &str, to: &[String]) {
+    let domain = crate::extract_domain_from_addr(from).unwrap_or_default();
+    if let Err(e) = conn.execute(
+        "INSERT OR REPLACE INTO sent_messages (message_id, queue_id, domain, from_addr, to_addrs, sent_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![
+            message_id.trim_matches(['<', '>']),
+            queue_id,
+            domain,
+            from,
+            serde_json::to_string(to).unwrap(),
+            now_millis()
+        ],
+    ) {
+        warn!(queue_id, "failed to record sent message: {e}");
+    }
+}
+
+// ── DSN parsing (RFC 3464) ──────────────────────────────────────────────────
+
+/// Splits a `message/delivery-status` body into its field groups: the
+/// per-message fields first, then one group per recipient. Folded lines are
+/// joined and field names lowercased.
+fn field_groups(body: &str) -> Vec<Vec<(String, String)>> {
+    let mut groups = Vec::new();
+    let mut current: Vec<(String, String)> = Vec::new();
+    for line in body.lines() {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                groups.push(std::mem::take(&mut current));
+            }
+        } else if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = current.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            current.push((name.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+    if !current.is_empty() {
+        groups.push(current);
+    }
+    groups
+}
+
+/// `rfc822; user@example.org` → `user@example.org`
+fn strip_address_type(value: &str) -> &str {
+    value.split_once(';').map_or(value, |(_, a)| a).trim()
+}
+
+fn parse_dsn(raw: &[u8]) -> Option<Dsn> {
+    let message = MessageParser::default().parse(raw)?;
+    let report = message.content_type()?;
+    if !report.ctype().eq_ignore_ascii_case("multipart")
+        || !report.subtype().is_some_and(|s| s.eq_ignore_ascii_case("report"))
+    {
+        return None;
+    }
+
+    let mut failures = Vec::new();
+    let mut message_id = None;
+    for part in &message.parts {
+        let Some(ct) = part.content_type() else { continue };
+        let kind = format!(
+            "{}/{}",
+            ct.ctype().to_lowercase(),
+            ct.subtype().unwrap_or_default().to_lowercase()
+        );
+        match kind.as_str() {
+            "message/delivery-status" | "message/global-delivery-status" => {
+                let body = String::from_utf8_lossy(part.contents());
+                for group in field_groups(&body).into_iter().skip(1) {
+                    let field = |name: &str| {
+                        group.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone())
+                    };
+                    if !field("action").is_some_and(|a| a.eq_ignore_ascii_case("failed")) {
+                        continue;
+                    }
+                    let Some(recipient) = field("final-recipient").or_else(|| field("original-recipient")) else {
+                        continue;
+                    };
+                    failures.push(Failure {
+                        address: strip_address_type(&recipient).to_lowercase(),
+                        status: field("status").unwrap_or_else(|| "5.0.0".into()),
+                        diagnostic: field("diagnostic-code").map(|d| strip_address_type(&d).to_string()),
+                    });
+                }
+            }
+            "message/rfc822" | "message/global" => {
+                if let PartType::Message(original) = &part.body {
+                    message_id = message_id.or(original.message_id().map(str::to_string));
+                }
+            }
+            "text/rfc822-headers" | "message/rfc822-headers" => {
+                let headers = MessageParser::default().parse_headers(part.contents());
+                message_id = message_id.or(headers.and_then(|h| h.message_id().map(str::to_string)));
+            }
+            _ => {}
+        }
+    }
+
+    Some(Dsn {
+        message_id: message_id?.trim_matches(['<', '>']).to_string(),
+        failures,
+    })
+}
+
+// ── Processing ──────────────────────────────────────────────────────────────
+
+/// Marks the message a DSN refers to as bounced, suppresses permanently
+/// failed recipients and emits a `bounced` event. Returns whether the DSN
+/// matched a message mayl sent.
+fn apply(conn: &Connection, tx: &events::Sender, dsn: &Dsn) -> bool {
+    let sent: Option<(String, String, String, String)> = conn
+        .query_row(
+            "SELECT queue_id, domain, from_addr, to_addrs FROM sent_messages WHERE message_id = ?1",
+            [&dsn.message_id],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+        )
+        .optional()
+        .unwrap_or_else(|e| {
+            error!("sent_messages lookup: {e}");
+            None
+        });
+    let Some((queue_id, domain, from, to_json)) = sent else {
+        debug!(message_id = dsn.message_id, "DSN for a message mayl did not send");
+        return false;
+    };
+
+    // Only trust failures for addresses the message actually went to
+    let to: Vec<String> = serde_json::from_str(&to_json).unwrap_or_default();
+    let recipients: Vec<String> = to.iter().filter_map(|a| normalize_address(a).ok()).collect();
+    let failures: Vec<&Failure> = dsn
+        .failures
+        .iter()
+        .filter(|f| recipients.contains(&f.address))
+        .collect();
+    if failures.is_empty() {
+        return false;
+    }
+
+    let reason = failures
+        .iter()
+        .map(|f| format!("{}: {}", f.address, f.reason()))
+        .collect::<Vec<_>>()
+        .join("; ");
+    let _ = conn.execute(
+        "UPDATE sent_messages SET status = 'bounced', bounced_at = ?2, bounce_reason = ?3 WHERE message_id = ?1",
+        rusqlite::params![&dsn.message_id, now_millis(), &reason],
+    );
+
+    for failure in failures.iter().filter(|f| f.permanent()) {
+        suppressions::suppress_bounced(conn, &domain, &failure.address, &failure.reason());
+    }
+
+    let bounced: Vec<String> = failures.iter().map(|f| f.address.clone()).collect();
+    let event = webhooks::EmailEvent {
+        id: &queue_id,
+        from: &from,
+        to: &bounced,
+        error: Some(&reason),
+    };
+    webhooks::emit(conn, &domain, webhooks::Event::Bounced, &event);
+    events::publish(tx, events::Kind::Bounced, &domain, &event);
+    info!(domain, queue_id, reason, "bounce processed");
+    true
+}
+
+/// Config key holding the bounce poller's [`imap::checkpoint`].
+const CHECKPOINT_KEY: &str = "imap_checkpoint";
+
+/// Fetches messages that arrived since the last poll and applies any DSNs
+/// among them. Returns how many matched a sent message.
+async fn poll_once(
+    db: &Mutex<Connection>,
+    tx: &events::Sender,
+    settings: &imap::Settings,
+    user: &str,
+    pass: &str,
+) -> Result<usize, String> {
+    let mut client = imap::Client::connect(settings).await?;
+    client.login(user, pass).await?;
+    let mailbox = client.examine(&settings.mailbox).await?;
+
+    let start = {
+        let db = db.lock().await;
+        match imap::checkpoint(&db, CHECKPOINT_KEY) {
+            Some((validity, uid)) if validity == mailbox.uid_validity => uid + 1,
+            // First run, or the mailbox was rebuilt: only look at new mail
+            _ => {
+                imap::save_checkpoint(&db, CHECKPOINT_KEY, mailbox.uid_validity, mailbox.uid_next.saturating_sub(1));
+                client.logout().await;
+                return Ok(0);
+            }
+        }
+    };
+
+    let mut matched = 0;
+    for uid in client.uids_from(start).await? {
+        let raw = client.fetch(uid).await?;
+        let db = db.lock().await;
+        if let Some(dsn) = parse_dsn(&raw)
+            && apply(&db, tx, &dsn)
+        {
+            matched += 1;
+        }
+        imap::save_checkpoint(&db, CHECKPOINT_KEY, mailbox.uid_validity, uid);
+    }
+    client.logout().await;
+    Ok(matched)
+}
+
+pub(crate) async fn worker(state: Arc<AppState>, settings: imap::Settings) {
+    let poll_interval = Duration::from_secs(settings.poll_seconds);
+    info!(host = settings.host, port = settings.port, mailbox = settings.mailbox, "bounce processing enabled");
+
+    loop {
+        tokio::time::sleep(poll_interval).await;
+
+        let (user, pass) = {
+            let creds = state.smtp_creds.read().await;
+            (creds.user.clone(), creds.pass.clone())
+        };
+        if user.is_empty() {
+            continue;
+        }
+        if let Err(e) = poll_once(&state.db, &state.events, &settings, &user, &pass).await {
+            warn!("bounce poll failed: {e}");
+        }
+
+        let db = state.db.lock().await;
+        let _ = db.execute(
+            "DELETE FROM sent_messages WHERE sent_at < ?1",
+            [now_millis() - RETENTION_MS],
+        );
+    }
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+pub(crate) async fn list_bounces_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Json<Vec<BounceEntry>> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let mut stmt = db
+        .prepare(
+            "SELECT queue_id, to_addrs, sent_at, bounced_at, bounce_reason FROM sent_messages
+             WHERE domain = ?1 AND status = 'bounced' ORDER BY bounced_at DESC LIMIT 100",
+        )
+        .unwrap();
+    let entries: Vec<BounceEntry> = stmt
+        .query_map([&domain], |row| {
+            Ok(BounceEntry {
+                id: row.get(0)?,
+                to: serde_json::from_str(&row.get::<_, String>(1)?).unwrap_or_default(),
+                sent_at: row.get(2)?,
+                bounced_at: row.get(3)?,
+                reason: row.get(4)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Json(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dsn(message_id: &str, blocks: &str) -> Vec<u8> {
+        format!(
+            "From: Mail Delivery System <MAILER-DAEMON@proton.me>\r\n\
+             To: app@example.com\r\n\
+             Subject: Undelivered Mail Returned to Sender\r\n\
+             MIME-Version: 1.0\r\n\
+             Content-Type: multipart/report; report-type=delivery-status; boundary=\"b1\"\r\n\
+             \r\n\
+             --b1\r\n\
+             Content-Type: text/plain\r\n\
+             \r\n\
+             Your message could not be delivered.\r\n\
+             --b1\r\n\
+             Content-Type: message/delivery-status\r\n\
+             \r\n\
+             Reporting-MTA: dns; mail.proton.me\r\n\
+             \r\n\
+             {blocks}\r\n\
+             --b1\r\n\
+             Content-Type: text/rfc822-headers\r\n\
+             \r\n\
+             From: app@example.com\r\n\
+             Message-ID: <{message_id}>\r\n\
+             Subject: hello\r\n\
+             --b1--\r\n"
+        )
+        .into_bytes()
+    }
+
+    const BLOCKS: &str = "Final-Recipient: rfc822; Gone@example.org\r\n\
+         Action: failed\r\n\
+         Status: 5.1.1\r\n\
+         Diagnostic-Code: smtp; 550 5.1.1 user unknown\r\n\
+         \r\n\
+         Final-Recipient: rfc822; full@example.org\r\n\
+         Action: failed\r\n\
+         Status: 4.2.2\r\n\
+         \r\n\
+         Final-Recipient: rfc822; ok@example.org\r\n\
+         Action: delivered\r\n\
+         Status: 2.0.0";
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+        record_sent(
+            &conn,
+            "<q1@example.com>",
+            "q1",
+            "app@example.com",
+            &[
+                "gone@example.org".into(),
+                "Full <full@example.org>".into(),
+                "ok@example.org".into(),
+            ],
+        );
+        conn
+    }
+
+    #[test]
+    fn test_parse_dsn() {
+        let parsed = parse_dsn(&dsn("q1@example.com", BLOCKS)).unwrap();
+        assert_eq!(parsed.message_id, "q1@example.com");
+        assert_eq!(
+            parsed.failures,
+            vec![
+                Failure {
+                    address: "gone@example.org".into(),
+                    status: "5.1.1".into(),
+                    diagnostic: Some("550 5.1.1 user unknown".into()),
+                },
+                Failure {
+                    address: "full@example.org".into(),
+                    status: "4.2.2".into(),
+                    diagnostic: None,
+                },
+            ]
+        );
+
+        assert_eq!(parse_dsn(b"Subject: hi\r\n\r\nnot a report\r\n"), None);
+    }
+
+    #[test]
+    fn test_apply_suppresses_permanent_failures() {
+        let conn = setup();
+        let tx = events::channel();
+        let mut rx = tx.subscribe();
+
+        assert!(apply(&conn, &tx, &parse_dsn(&dsn("q1@example.com", BLOCKS)).unwrap()));
+        let (status, reason): (String, String) = conn
+            .query_row(
+                "SELECT status, bounce_reason FROM sent_messages WHERE queue_id = 'q1'",
+                [],
+                |r| Ok((r.get(0)?, r.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(status, "bounced");
+        assert!(reason.contains("gone@example.org: 5.1.1"), "{reason}");
+
+        // Only the 5.x.x failure is suppressed; 4.x.x may succeed later
+        let (allowed, suppressed) = suppressions::partition(
+            &conn,
+            "example.com",
+            &["gone@example.org".into(), "full@example.org".into()],
+        )
+        .unwrap();
+        assert_eq!(suppressed, vec!["gone@example.org"]);
+        assert_eq!(allowed, vec!["full@example.org"]);
+        assert!(rx.try_recv().is_ok());
+
+        // A DSN naming an address the message never went to changes nothing
+        let forged = dsn(
+            "q1@example.com",
+            "Final-Recipient: rfc822; victim@example.org\r\nAction: failed\r\nStatus: 5.1.1",
+        );
+        assert!(!apply(&conn, &tx, &parse_dsn(&forged).unwrap()));
+        assert!(!apply(&conn, &tx, &parse_dsn(&dsn("unknown@example.com", BLOCKS)).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_poll_against_imap_stand_in() {
+        let (settings, _) = imap::tests::stand_in(
+            9,
+            vec![
+                (1, b"Subject: before mayl started\r\n\r\nhi\r\n".to_vec()),
+                (2, dsn("q1@example.com", BLOCKS)),
+            ],
+        )
+        .await;
+        let db = Mutex::new(setup());
+        let tx = events::channel();
+
+        // The first poll only records where the mailbox stands
+        assert_eq!(poll_once(&db, &tx, &settings, "u", "p").await.unwrap(), 0);
+        assert_eq!(imap::checkpoint(&*db.lock().await, CHECKPOINT_KEY), Some((9, 2)));
+
+        // Pretend the DSN arrived after that
+        imap::save_checkpoint(&*db.lock().await, CHECKPOINT_KEY, 9, 1);
+        assert_eq!(poll_once(&db, &tx, &settings, "u", "p").await.unwrap(), 1);
+        assert_eq!(imap::checkpoint(&*db.lock().await, CHECKPOINT_KEY), Some((9, 2)));
+        assert_eq!(poll_once(&db, &tx, &settings, "u", "p").await.unwrap(), 0);
+
+        let bounced: i64 = db
+            .lock()
+            .await
+            .query_row("SELECT COUNT(*) FROM suppressions WHERE address = 'gone@example.org'", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(bounced, 1);
+    }
+}
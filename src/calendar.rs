@@ -0,0 +1,425 @@
+use serde::Deserialize;
+
+/// A calendar invitation attached to an email, either as a structured event
+/// or as a complete `.ics` document supplied by the caller.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum CalendarInput {
+    Raw(String),
+    Event(CalendarEvent),
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CalendarEvent {
+    #[serde(default)]
+    method: Method,
+    uid: Option<String>,
+    #[serde(default)]
+    sequence: u32,
+    summary: String,
+    description: Option<String>,
+    location: Option<String>,
+    start: String,
+    end: String,
+    organizer: Option<String>,
+    attendees: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub(crate) enum Method {
+    #[default]
+    Request,
+    Cancel,
+}
+
+impl Method {
+    fn as_str(self) -> &'static str {
+        match self {
+            Method::Request => "REQUEST",
+            Method::Cancel => "CANCEL",
+        }
+    }
+}
+
+/// A rendered iCalendar document and the UID it describes.
+#[derive(Debug)]
+pub(crate) struct Invitation {
+    pub(crate) ics: String,
+    pub(crate) uid: String,
+}
+
+/// The iTIP methods (RFC 5546 §1.4) a raw `.ics` may declare.
+const METHODS: [&str; 8] = [
+    "PUBLISH", "REQUEST", "REPLY", "ADD", "CANCEL", "REFRESH", "COUNTER", "DECLINECOUNTER",
+];
+
+/// Builds the `.ics` body for a request. `from` and `to` are used as the
+/// organizer and attendees when the event does not name them.
+pub(crate) fn build(input: &CalendarInput, from: &str, to: &[String]) -> Result<Invitation, String> {
+    match input {
+        CalendarInput::Raw(ics) => {
+            let ics = normalize_line_endings(ics);
+            let method = method(&ics).ok_or("calendar must contain a METHOD property")?;
+            if !METHODS.contains(&method.as_str()) {
+                return Err(format!("calendar METHOD must be one of {}", METHODS.join(", ")));
+            }
+            let uid = property(&ics, "UID").ok_or("calendar must contain a UID property")?;
+            Ok(Invitation { ics, uid })
+        }
+        CalendarInput::Event(event) => build_event(event, from, to),
+    }
+}
+
+fn build_event(event: &CalendarEvent, from: &str, to: &[String]) -> Result<Invitation, String> {
+    let uid = match (&event.uid, event.method) {
+        (Some(uid), _) => uid.clone(),
+        (None, Method::Cancel) => return Err("calendar CANCEL requires the uid of the original invitation".into()),
+        (None, Method::Request) => format!("{}@mayl", uuid::Uuid::new_v4()),
+    };
+
+    let start = ical_time(&event.start).map_err(|e| format!("calendar start: {e}"))?;
+    let end = ical_time(&event.end).map_err(|e| format!("calendar end: {e}"))?;
+    // Both are fixed-width (all-day dates or UTC date-times), so of the same
+    // kind they compare as strings.
+    if start.starts_with(';') != end.starts_with(';') {
+        return Err("calendar start and end must both be dates or both be timestamps".into());
+    }
+    if end <= start {
+        return Err("calendar end must be after start".into());
+    }
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "PRODID:-//mayl//EN".to_string(),
+        "VERSION:2.0".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        format!("METHOD:{}", event.method.as_str()),
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}", escape_text(&uid)),
+        format!("SEQUENCE:{}", event.sequence),
+        format!("DTSTAMP:{}", format_utc(crate::now_millis() / 1000)),
+        format!("DTSTART{start}"),
+        format!("DTEND{end}"),
+        format!("SUMMARY:{}", escape_text(&event.summary)),
+    ];
+    if let Some(d) = &event.description {
+        lines.push(format!("DESCRIPTION:{}", escape_text(d)));
+    }
+    if let Some(l) = &event.location {
+        lines.push(format!("LOCATION:{}", escape_text(l)));
+    }
+
+    lines.push(format!("ORGANIZER{}", cal_address(event.organizer.as_deref().unwrap_or(from))?));
+    let attendees = event.attendees.as_deref().unwrap_or(to);
+    // A cancellation expects no reply.
+    let rsvp = match event.method {
+        Method::Request => ";RSVP=TRUE",
+        Method::Cancel => "",
+    };
+    for attendee in attendees {
+        lines.push(format!(
+            "ATTENDEE;ROLE=REQ-PARTICIPANT;PARTSTAT=NEEDS-ACTION{rsvp}{}",
+            cal_address(attendee)?
+        ));
+    }
+
+    lines.push(match event.method {
+        Method::Request => "STATUS:CONFIRMED".to_string(),
+        Method::Cancel => "STATUS:CANCELLED".to_string(),
+    });
+    lines.push("END:VEVENT".to_string());
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut ics = String::new();
+    for line in &lines {
+        fold_line(&mut ics, line);
+    }
+    Ok(Invitation { ics, uid })
+}
+
+/// Returns the value of the `METHOD` property, which becomes the `method`
+/// parameter of the `text/calendar` part.
+pub(crate) fn method(ics: &str) -> Option<String> {
+    property(ics, "METHOD").map(|m| m.to_ascii_uppercase())
+}
+
+fn property(ics: &str, name: &str) -> Option<String> {
+    unfold(ics).lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        let key = key.split(';').next()?;
+        key.eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+            .filter(|v| !v.is_empty())
+    })
+}
+
+/// Joins folded continuation lines back into single content lines.
+fn unfold(ics: &str) -> String {
+    ics.replace("\r\n ", "")
+        .replace("\r\n\t", "")
+        .replace("\n ", "")
+        .replace("\n\t", "")
+}
+
+fn normalize_line_endings(ics: &str) -> String {
+    let mut out = String::with_capacity(ics.len() + 64);
+    for line in ics.lines() {
+        out.push_str(line);
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// `;CN=Name:mailto:addr` for an address in either `Name <addr>` or bare form.
+fn cal_address(addr: &str) -> Result<String, String> {
+    let mbox: lettre::message::Mailbox = addr
+        .parse()
+        .map_err(|e| format!("bad calendar address '{addr}': {e}"))?;
+    Ok(match &mbox.name {
+        Some(name) => format!(";CN=\"{}\":mailto:{}", name.replace('"', "'"), mbox.email),
+        None => format!(":mailto:{}", mbox.email),
+    })
+}
+
+fn escape_text(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ';' => out.push_str("\\;"),
+            ',' => out.push_str("\\,"),
+            '\n' => out.push_str("\\n"),
+            '\r' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Writes a content line folded at 75 octets as RFC 5545 requires, without
+/// splitting a UTF-8 sequence.
+fn fold_line(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        let len = c.len_utf8();
+        if width + len > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += len;
+    }
+    out.push_str("\r\n");
+}
+
+// ── Date handling ───────────────────────────────────────────────────────────
+
+/// Converts `YYYY-MM-DD` into an all-day value and an RFC 3339 timestamp
+/// (`2026-10-20T10:00:00+02:00`) into a UTC date-time, returning the property
+/// suffix including the leading `;` or `:`.
+fn ical_time(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    let invalid = || format!("'{s}' is not YYYY-MM-DD or an RFC 3339 timestamp");
+
+    let (date, time) = match s.split_once(['T', 't', ' ']) {
+        Some((d, t)) => (d, Some(t)),
+        None => (s, None),
+    };
+    let (y, m, d) = parse_date(date).ok_or_else(invalid)?;
+
+    let Some(time) = time else {
+        return Ok(format!(";VALUE=DATE:{y:04}{m:02}{d:02}"));
+    };
+
+    let (clock, offset_secs) = if let Some(c) = time.strip_suffix(['Z', 'z']) {
+        (c, 0)
+    } else {
+        let idx = time.rfind(['+', '-']).ok_or_else(invalid)?;
+        let (c, off) = time.split_at(idx);
+        let sign = if off.starts_with('-') { -1 } else { 1 };
+        let (oh, om) = off[1..].split_once(':').ok_or_else(invalid)?;
+        let oh: i64 = oh.parse().map_err(|_| invalid())?;
+        let om: i64 = om.parse().map_err(|_| invalid())?;
+        (c, sign * (oh * 3600 + om * 60))
+    };
+
+    let clock = clock.split('.').next().unwrap_or(clock);
+    let mut parts = clock.split(':').map(|p| p.parse::<i64>());
+    let (Some(Ok(hh)), Some(Ok(mm)), ss) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    let ss = match ss {
+        Some(Ok(v)) => v,
+        None => 0,
+        Some(Err(_)) => return Err(invalid()),
+    };
+    if hh > 23 || mm > 59 || ss > 60 {
+        return Err(invalid());
+    }
+
+    let secs = days_from_civil(y, m, d) * 86_400 + hh * 3600 + mm * 60 + ss - offset_secs;
+    Ok(format!(":{}", format_utc(secs)))
+}
+
+fn parse_date(s: &str) -> Option<(i64, u32, u32)> {
+    let mut parts = s.split('-');
+    let y: i64 = parts.next()?.parse().ok()?;
+    let m: u32 = parts.next()?.parse().ok()?;
+    let d: u32 = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || !(1..=12).contains(&m) || !(1..=days_in_month(y, m)).contains(&d) {
+        return None;
+    }
+    Some((y, m, d))
+}
+
+fn days_in_month(y: i64, m: u32) -> u32 {
+    match m {
+        2 if y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Formats Unix seconds as an iCalendar UTC date-time (`20261020T080000Z`).
+fn format_utc(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    let rem = secs.rem_euclid(86_400);
+    let (y, m, d) = civil_from_days(days);
+    format!(
+        "{y:04}{m:02}{d:02}T{:02}{:02}{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+// Howard Hinnant's proleptic Gregorian conversions.
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = m as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ical_time() {
+        assert_eq!(ical_time("2026-10-20").unwrap(), ";VALUE=DATE:20261020");
+        assert_eq!(ical_time("2026-10-20T10:00:00Z").unwrap(), ":20261020T100000Z");
+        assert_eq!(ical_time("2026-10-20T01:30:00+02:00").unwrap(), ":20261019T233000Z");
+        assert_eq!(ical_time("2026-12-31T23:00-05:00").unwrap(), ":20270101T040000Z");
+        assert!(ical_time("tomorrow").is_err());
+        assert!(ical_time("2026-10-20T10:00:00").is_err());
+
+        assert!(ical_time("2028-02-29").is_ok());
+        assert!(ical_time("2000-02-29").is_ok());
+        for date in ["2026-02-29", "1900-02-29", "2026-04-31", "2026-11-31T10:00:00Z", "2026-01-32"] {
+            assert!(ical_time(date).is_err(), "{date}");
+        }
+    }
+
+    #[test]
+    fn test_build_request_and_cancel() {
+        let to = vec!["Bob <bob@example.org>".to_string()];
+        let request: CalendarInput = serde_json::from_value(serde_json::json!({
+            "summary": "Planning, round 2",
+            "start": "2026-10-20T10:00:00Z",
+            "end": "2026-10-20T11:00:00Z",
+        }))
+        .unwrap();
+        let inv = build(&request, "Ada <ada@example.com>", &to).unwrap();
+        assert!(inv.ics.lines().all(|l| l.len() <= 76));
+        let ics = unfold(&inv.ics);
+        assert!(ics.contains("METHOD:REQUEST\r\n"));
+        assert!(ics.contains("SUMMARY:Planning\\, round 2\r\n"));
+        assert!(ics.contains("ORGANIZER;CN=\"Ada\":mailto:ada@example.com\r\n"));
+        assert!(ics.contains("RSVP=TRUE;CN=\"Bob\":mailto:bob@example.org\r\n"));
+        assert!(ics.contains(&format!("UID:{}\r\n", inv.uid)));
+        assert_eq!(method(&inv.ics).as_deref(), Some("REQUEST"));
+
+        let cancel: CalendarInput = serde_json::from_value(serde_json::json!({
+            "method": "CANCEL",
+            "uid": inv.uid,
+            "sequence": 1,
+            "summary": "Planning, round 2",
+            "start": "2026-10-20T10:00:00Z",
+            "end": "2026-10-20T11:00:00Z",
+        }))
+        .unwrap();
+        let cancelled = build(&cancel, "ada@example.com", &to).unwrap();
+        assert_eq!(cancelled.uid, inv.uid);
+        assert!(cancelled.ics.contains("STATUS:CANCELLED\r\n"));
+        assert!(!cancelled.ics.contains("RSVP"));
+        assert_eq!(method(&cancelled.ics).as_deref(), Some("CANCEL"));
+
+        let missing_uid: CalendarInput = serde_json::from_value(serde_json::json!({
+            "method": "CANCEL",
+            "summary": "x",
+            "start": "2026-10-20",
+            "end": "2026-10-21",
+        }))
+        .unwrap();
+        assert!(build(&missing_uid, "ada@example.com", &to).is_err());
+    }
+
+    #[test]
+    fn test_event_times() {
+        let event = |start: &str, end: &str| -> CalendarInput {
+            serde_json::from_value(serde_json::json!({"summary": "x", "start": start, "end": end})).unwrap()
+        };
+        let to = vec!["bob@example.org".to_string()];
+        let build = |start, end| build(&event(start, end), "ada@example.com", &to).map(|_| ());
+
+        // 08:30Z is after 10:00+02:00
+        assert!(build("2026-10-20T10:00:00+02:00", "2026-10-20T08:30:00Z").is_ok());
+        assert!(build("2026-10-20", "2026-10-21").is_ok());
+        for (start, end) in [
+            ("2026-10-20T10:00:00Z", "2026-10-20T10:00:00Z"),
+            ("2026-10-20T10:00:00Z", "2026-10-20T09:00:00Z"),
+            ("2026-10-20", "2026-10-20"),
+        ] {
+            assert_eq!(build(start, end).unwrap_err(), "calendar end must be after start");
+        }
+        assert!(build("2026-10-20", "2026-10-20T11:00:00Z").unwrap_err().contains("both be dates"));
+    }
+
+    #[test]
+    fn test_raw_ics_and_folding() {
+        let raw = CalendarInput::Raw("BEGIN:VCALENDAR\nMETHOD:request\nUID:abc\nEND:VCALENDAR\n".into());
+        let inv = build(&raw, "a@b.com", &[]).unwrap();
+        assert_eq!(inv.uid, "abc");
+        assert!(inv.ics.contains("METHOD:request\r\n"));
+        assert_eq!(method(&inv.ics).as_deref(), Some("REQUEST"));
+
+        assert!(build(&CalendarInput::Raw("BEGIN:VCALENDAR\n".into()), "a@b.com", &[]).is_err());
+        for method in ["INVITE", "REQUEST; charset=x", "\"REQUEST\""] {
+            let raw = CalendarInput::Raw(format!("BEGIN:VCALENDAR\nMETHOD:{method}\nUID:abc\nEND:VCALENDAR\n"));
+            assert!(build(&raw, "a@b.com", &[]).unwrap_err().contains("METHOD must be one of"), "{method}");
+        }
+
+        let mut out = String::new();
+        fold_line(&mut out, &"x".repeat(100));
+        assert_eq!(out, format!("{}\r\n {}\r\n", "x".repeat(75), "x".repeat(25)));
+    }
+}
@@ -0,0 +1,643 @@
+//! Administrative subcommands of the `mayl` binary, for provisioning from
+//! scripts. Each one is a call to the HTTP API, served in-process against
+//! the database at `MAYL_DB_PATH`, or sent to a running server when
+//! `--server` or `MAYL_URL` is given.
+
+use std::{io::BufRead, process::ExitCode};
+
+use axum::{
+    Router,
+    body::Body,
+    http::{Method, Request},
+};
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+const USAGE: &str = "\
+usage: mayl [--server URL] [--json] <command>
+
+  serve                                 run the server (the default)
+  config check [<file>]                 validate and print the settings
+  domain add <domain> [--verify|--no-verify]
+  domain list
+  domain rm <domain>
+  domain rotate <domain>                issue a new API token
+  queue list [--status S] [--domain D]
+  queue retry <id>... | --failed
+  queue purge [--status S]              default status: failed
+  smtp set <user> [<pass>] [--no-verify]
+                                        pass is read from stdin if omitted
+  smtp test <user> [<pass>]             try a login without saving it
+  send --from A --to B [--to C] [--subject S]
+       (--body T | --html H | --markdown M | --template ID [--data JSON])
+       [--token T] [--sync]             '-' as a value reads stdin
+
+Without --server (or MAYL_URL), commands work on the database at
+MAYL_DB_PATH directly. A running server keeps its SMTP credentials in
+memory, so use --server to change them without a restart.";
+
+// ── Arguments ───────────────────────────────────────────────────────────────
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    DomainAdd { domain: String, verify: Option<bool> },
+    DomainList,
+    DomainRm { domain: String },
+    DomainRotate { domain: String },
+    QueueList { status: Option<String>, domain: Option<String> },
+    QueueRetry { ids: Vec<String>, failed: bool },
+    QueuePurge { status: Option<String> },
+    SmtpSet { user: String, pass: Option<String>, verify: bool },
+    SmtpTest { user: String, pass: Option<String> },
+    Send { token: Option<String>, request: Value, sync: bool },
+    ConfigCheck { path: Option<String> },
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    server: Option<String>,
+    json: bool,
+    command: Command,
+}
+
+/// `--name value` pairs in the order given; switches have an empty value.
+type Flags = Vec<(String, String)>;
+
+/// Splits `--name value` flags from positional arguments. `--name=value` is
+/// accepted too; `switches` take no value.
+fn split(args: &[String], switches: &[&str]) -> Result<(Vec<String>, Flags), String> {
+    let mut positional = Vec::new();
+    let mut flags = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let Some(name) = arg.strip_prefix("--") else {
+            positional.push(arg.clone());
+            continue;
+        };
+        if let Some((name, value)) = name.split_once('=') {
+            flags.push((name.to_string(), value.to_string()));
+        } else if switches.contains(&name) {
+            flags.push((name.to_string(), String::new()));
+        } else {
+            let value = args.next().ok_or_else(|| format!("--{name} needs a value"))?;
+            flags.push((name.to_string(), value.clone()));
+        }
+    }
+    Ok((positional, flags))
+}
+
+fn parse(args: &[String]) -> Result<Options, String> {
+    let mut server = None;
+    let mut json = false;
+    let mut args = args;
+    while let Some(arg) = args.first().filter(|a| a.starts_with("--")) {
+        if arg == "--json" {
+            json = true;
+            args = &args[1..];
+        } else if let Some(url) = arg.strip_prefix("--server=") {
+            server = Some(url.to_string());
+            args = &args[1..];
+        } else if arg == "--server" {
+            server = Some(args.get(1).ok_or("--server needs a value")?.clone());
+            args = &args[2..];
+        } else {
+            return Err(format!("unknown option {arg}"));
+        }
+    }
+
+    if args.first().is_some_and(|a| a == "send") {
+        // `send` has no action word; everything after it is a flag.
+        let (positional, flags) = split(&args[1..], &["sync"])?;
+        if let Some(arg) = positional.first() {
+            return Err(format!("unexpected argument '{arg}'"));
+        }
+        let command = send_command(&flags)?;
+        return Ok(Options { server, json, command });
+    }
+
+    let (group, action) = match args {
+        [group, action, ..] => (group.as_str(), action.as_str()),
+        [group] => (group.as_str(), ""),
+        [] => return Err("missing command".into()),
+    };
+    let rest = args.get(2..).unwrap_or_default();
+    let (positional, flags) = split(rest, &["verify", "no-verify", "failed"])?;
+    let flag = |name: &str| flags.iter().rev().find(|(n, _)| n == name).map(|(_, v)| v.clone());
+    let has = |name: &str| flags.iter().any(|(n, _)| n == name);
+    if let Some((name, _)) = flags.iter().find(|(n, _)| !allowed(group, action).contains(&n.as_str())) {
+        return Err(format!("unknown option --{name} for '{group} {action}'"));
+    }
+    let one = |what: &str| match positional.as_slice() {
+        [value] => Ok(value.clone()),
+        _ => Err(format!("'{group} {action}' takes one {what}")),
+    };
+
+    let command = match (group, action) {
+        ("domain", "add") => Command::DomainAdd {
+            domain: one("domain")?,
+            verify: if has("no-verify") {
+                Some(false)
+            } else {
+                has("verify").then_some(true)
+            },
+        },
+        ("domain", "list") => Command::DomainList,
+        ("domain", "rm") => Command::DomainRm { domain: one("domain")? },
+        ("domain", "rotate") => Command::DomainRotate { domain: one("domain")? },
+        ("queue", "list") => Command::QueueList {
+            status: flag("status"),
+            domain: flag("domain"),
+        },
+        ("queue", "retry") => {
+            let failed = has("failed");
+            if failed != positional.is_empty() {
+                return Err("'queue retry' takes message ids or --failed".into());
+            }
+            Command::QueueRetry { ids: positional, failed }
+        }
+        ("queue", "purge") => Command::QueuePurge { status: flag("status") },
+        ("config", "check") => match positional.as_slice() {
+            [] => Command::ConfigCheck { path: None },
+            [path] => Command::ConfigCheck { path: Some(path.clone()) },
+            _ => return Err("'config check' takes at most one file".into()),
+        },
+        ("smtp", "set" | "test") => {
+            let (user, pass) = match positional.as_slice() {
+                [user] => (user.clone(), None),
+                [user, pass] => (user.clone(), Some(pass.clone())),
+                _ => return Err(format!("'smtp {action}' takes a user and an optional password")),
+            };
+            if action == "set" {
+                Command::SmtpSet { user, pass, verify: !has("no-verify") }
+            } else {
+                Command::SmtpTest { user, pass }
+            }
+        }
+        _ => return Err(format!("unknown command '{}'", args.join(" "))),
+    };
+
+    Ok(Options { server, json, command })
+}
+
+fn allowed(group: &str, action: &str) -> &'static [&'static str] {
+    match (group, action) {
+        ("domain", "add") => &["verify", "no-verify"],
+        ("queue", "list") => &["status", "domain"],
+        ("queue", "retry") => &["failed"],
+        ("queue", "purge") => &["status"],
+        ("smtp", "set") => &["no-verify"],
+        _ => &[],
+    }
+}
+
+fn send_command(flags: &Flags) -> Result<Command, String> {
+    let mut request = serde_json::Map::new();
+    let mut to = Vec::new();
+    let mut token = None;
+    let mut sync = false;
+    for (name, value) in flags {
+        let value = if value == "-" { read_stdin()? } else { value.clone() };
+        match name.as_str() {
+            "to" => to.extend(value.split(',').map(|a| a.trim().to_string()).filter(|a| !a.is_empty())),
+            "token" => token = Some(value),
+            "sync" => sync = true,
+            "data" => {
+                let data: Value = serde_json::from_str(&value).map_err(|e| format!("--data is not JSON: {e}"))?;
+                request.insert("data".into(), data);
+            }
+            "from" | "subject" | "body" | "html" | "markdown" | "template" => {
+                request.insert(name.clone(), Value::String(value));
+            }
+            _ => return Err(format!("unknown option --{name} for 'send'")),
+        }
+    }
+    if !request.contains_key("from") || to.is_empty() {
+        return Err("'send' needs --from and --to".into());
+    }
+    request.insert("to".into(), json!(to));
+    Ok(Command::Send {
+        token,
+        request: Value::Object(request),
+        sync,
+    })
+}
+
+fn read_stdin() -> Result<String, String> {
+    std::io::read_to_string(std::io::stdin()).map_err(|e| format!("reading stdin: {e}"))
+}
+
+/// The password given, or the first line of stdin.
+fn password(pass: Option<String>) -> Result<String, String> {
+    match pass {
+        Some(pass) => Ok(pass),
+        None => {
+            let mut line = String::new();
+            std::io::stdin().lock().read_line(&mut line).map_err(|e| format!("reading stdin: {e}"))?;
+            Ok(line.trim_end_matches(['\r', '\n']).to_string())
+        }
+    }
+}
+
+// ── Client ──────────────────────────────────────────────────────────────────
+
+enum Client {
+    /// The API served in-process, on the local database.
+    Local(Router),
+    Remote { http: reqwest::Client, base: String },
+}
+
+impl Client {
+    /// Makes one API call and returns the JSON response, or the API's error
+    /// message for anything but a 2xx.
+    async fn call(&self, method: Method, path: &str, token: Option<&str>, body: Option<&Value>) -> Result<Value, String> {
+        let (status, text) = match self {
+            Client::Local(router) => {
+                let mut request = Request::builder().method(method).uri(path);
+                if let Some(token) = token {
+                    request = request.header("authorization", format!("Bearer {token}"));
+                }
+                let request = match body {
+                    Some(body) => request
+                        .header("content-type", "application/json")
+                        .body(Body::from(body.to_string())),
+                    None => request.body(Body::empty()),
+                }
+                .map_err(|e| e.to_string())?;
+                let response = router.clone().oneshot(request).await.map_err(|e| e.to_string())?;
+                let status = response.status();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                (status, String::from_utf8_lossy(&bytes).into_owned())
+            }
+            Client::Remote { http, base } => {
+                let mut request = http.request(method, format!("{base}{path}"));
+                if let Some(token) = token {
+                    request = request.bearer_auth(token);
+                }
+                if let Some(body) = body {
+                    request = request
+                        .header("content-type", "application/json")
+                        .body(body.to_string());
+                }
+                let response = request.send().await.map_err(|e| format!("{base}: {e}"))?;
+                let status = response.status();
+                (status, response.text().await.map_err(|e| e.to_string())?)
+            }
+        };
+
+        let value: Value = if text.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_str(&text).unwrap_or(Value::String(text))
+        };
+        if status.is_success() {
+            return Ok(value);
+        }
+        let mut message = value["error"].as_str().map_or_else(|| status.to_string(), str::to_string);
+        for rejected in value["rejected"].as_array().into_iter().flatten() {
+            message.push_str(&format!("\n  {}: {}", rejected["address"].as_str().unwrap_or_default(), rejected["reason"].as_str().unwrap_or_default()));
+        }
+        Err(message)
+    }
+}
+
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn query(pairs: &[(&str, &Option<String>)]) -> String {
+    let pairs: Vec<String> = pairs
+        .iter()
+        .filter_map(|(name, value)| value.as_ref().map(|v| format!("{name}={}", encode(v))))
+        .collect();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", pairs.join("&"))
+    }
+}
+
+// ── Commands ────────────────────────────────────────────────────────────────
+
+/// Runs `command`, printing results to stdout: plain lines for scripts, or
+/// the API's JSON with `json`.
+async fn execute(client: &Client, command: Command, json: bool) -> Result<(), String> {
+    let print = |value: &Value, plain: &dyn Fn(&Value)| {
+        if json {
+            println!("{value}");
+        } else {
+            plain(value);
+        }
+    };
+
+    match command {
+        Command::DomainAdd { domain, verify } => {
+            let body = json!({ "domain": domain, "verify": verify });
+            let value = client.call(Method::POST, "/domains", None, Some(&body)).await?;
+            print(&value, &|v| {
+                println!("{}", v["token"].as_str().unwrap_or_default());
+                if let Some(challenge) = v.get("verification") {
+                    eprintln!(
+                        "publish TXT {} \"{}\" to verify the domain",
+                        challenge["name"].as_str().unwrap_or_default(),
+                        challenge["value"].as_str().unwrap_or_default()
+                    );
+                }
+            });
+        }
+        Command::DomainList => {
+            let value = client.call(Method::GET, "/domains", None, None).await?;
+            print(&value, &|v| {
+                for domain in v.as_array().into_iter().flatten() {
+                    println!(
+                        "{}\t{}",
+                        domain["domain"].as_str().unwrap_or_default(),
+                        domain["status"].as_str().unwrap_or_default()
+                    );
+                }
+            });
+        }
+        Command::DomainRm { domain } => {
+            client.call(Method::DELETE, &format!("/domains/{}", encode(&domain)), None, None).await?;
+        }
+        Command::DomainRotate { domain } => {
+            let path = format!("/domains/{}/token", encode(&domain));
+            let value = client.call(Method::POST, &path, None, None).await?;
+            print(&value, &|v| println!("{}", v["token"].as_str().unwrap_or_default()));
+        }
+        Command::QueueList { status, domain } => {
+            let path = format!("/queue{}", query(&[("status", &status), ("domain", &domain)]));
+            let value = client.call(Method::GET, &path, None, None).await?;
+            print(&value, &|v| {
+                for entry in v.as_array().into_iter().flatten() {
+                    let to: Vec<&str> = entry["to"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                        entry["id"].as_str().unwrap_or_default(),
+                        entry["status"].as_str().unwrap_or_default(),
+                        entry["attempts"],
+                        entry["from"].as_str().unwrap_or_default(),
+                        to.join(","),
+                        entry["subject"].as_str().unwrap_or_default(),
+                        entry["last_error"].as_str().unwrap_or_default(),
+                    );
+                }
+            });
+        }
+        Command::QueueRetry { mut ids, failed } => {
+            if failed {
+                let value = client.call(Method::GET, "/queue?status=failed&limit=10000", None, None).await?;
+                ids = value
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|e| e["id"].as_str().map(str::to_string))
+                    .collect();
+            }
+            for id in ids {
+                let path = format!("/queue/{}/retry", encode(&id));
+                let value = client.call(Method::POST, &path, None, None).await?;
+                print(&value, &|v| println!("{}", v["id"].as_str().unwrap_or_default()));
+            }
+        }
+        Command::QueuePurge { status } => {
+            let path = format!("/queue{}", query(&[("status", &status)]));
+            let value = client.call(Method::DELETE, &path, None, None).await?;
+            print(&value, &|v| println!("{}", v["purged"]));
+        }
+        Command::SmtpSet { user, pass, verify } => {
+            let body = json!({ "user": user, "pass": password(pass)? });
+            let path = if verify { "/smtp" } else { "/smtp?verify=false" };
+            client.call(Method::POST, path, None, Some(&body)).await?;
+            if matches!(client, Client::Local(_)) {
+                eprintln!("saved; a running server uses the new credentials after a restart");
+            }
+        }
+        Command::SmtpTest { user, pass } => {
+            let body = json!({ "user": user, "pass": password(pass)? });
+            let value = client.call(Method::POST, "/smtp/test", None, Some(&body)).await?;
+            print(&value, &|_| println!("login accepted"));
+        }
+        Command::Send { token, request, sync } => {
+            let token = token
+                .or_else(|| std::env::var("MAYL_TOKEN").ok())
+                .ok_or("'send' needs --token or MAYL_TOKEN")?;
+            let path = if sync { "/email?sync=true" } else { "/email" };
+            let value = client.call(Method::POST, path, Some(&token), Some(&request)).await?;
+            print(&value, &|v| {
+                match v["ids"].as_array() {
+                    Some(ids) => ids.iter().filter_map(Value::as_str).for_each(|id| println!("{id}")),
+                    None => println!("{}", v["id"].as_str().unwrap_or_default()),
+                }
+                for address in v["suppressed"].as_array().into_iter().flatten() {
+                    eprintln!("suppressed: {}", address.as_str().unwrap_or_default());
+                }
+            });
+        }
+        Command::ConfigCheck { .. } => unreachable!("checked without a client"),
+    }
+    Ok(())
+}
+
+/// Prints the effective settings, merged from `path` (or `MAYL_CONFIG`) and
+/// the environment, and whether they are valid.
+fn check_config(path: Option<&str>) -> ExitCode {
+    let source = crate::config::Source::load(path);
+    let result = crate::Config::from_source(&source);
+    println!("{}", source.report());
+    match result {
+        Ok(_) => {
+            eprintln!("configuration OK");
+            ExitCode::SUCCESS
+        }
+        Err(errors) => crate::config::report_errors(&errors),
+    }
+}
+
+/// Entry point for `mayl <command>`: exits 0 on success, 1 when the API
+/// refuses the call and 2 on a usage error.
+pub(crate) async fn run(args: &[String]) -> ExitCode {
+    if matches!(args.first().map(String::as_str), Some("help" | "--help" | "-h")) {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+    let options = match parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("mayl: {e}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    if let Command::ConfigCheck { path } = &options.command {
+        return check_config(path.as_deref());
+    }
+
+    let server = options
+        .server
+        .or_else(|| std::env::var("MAYL_URL").ok())
+        .filter(|s| !s.is_empty());
+    let client = match server {
+        Some(base) => Client::Remote {
+            http: reqwest::Client::new(),
+            base: base.trim_end_matches('/').to_string(),
+        },
+        None => {
+            let config = match crate::Config::load() {
+                Ok(config) => config,
+                Err(errors) => return crate::config::report_errors(&errors),
+            };
+            let conn = crate::open_db(&config);
+            Client::Local(crate::router(crate::app_state(config, conn)))
+        }
+    };
+
+    match execute(&client, options.command, options.json).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("mayl: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rusqlite::Connection;
+
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let options = parse(&args("--server http://mayl:8080 --json domain add example.com --no-verify")).unwrap();
+        assert_eq!(options.server.as_deref(), Some("http://mayl:8080"));
+        assert!(options.json);
+        assert_eq!(
+            options.command,
+            Command::DomainAdd {
+                domain: "example.com".into(),
+                verify: Some(false)
+            }
+        );
+
+        assert_eq!(
+            parse(&args("queue list --status=failed")).unwrap().command,
+            Command::QueueList {
+                status: Some("failed".into()),
+                domain: None
+            }
+        );
+        assert_eq!(
+            parse(&args("send --from a@example.com --to b@example.org,c@example.org --subject Hi --body x --sync"))
+                .unwrap()
+                .command,
+            Command::Send {
+                token: None,
+                request: json!({
+                    "from": "a@example.com",
+                    "subject": "Hi",
+                    "body": "x",
+                    "to": ["b@example.org", "c@example.org"],
+                }),
+                sync: true,
+            }
+        );
+
+        assert_eq!(
+            parse(&args("config check /etc/mayl.toml")).unwrap().command,
+            Command::ConfigCheck {
+                path: Some("/etc/mayl.toml".into())
+            }
+        );
+        assert_eq!(
+            parse(&args("smtp set user@proton.me secret --no-verify")).unwrap().command,
+            Command::SmtpSet {
+                user: "user@proton.me".into(),
+                pass: Some("secret".into()),
+                verify: false
+            }
+        );
+
+        assert!(parse(&args("queue retry")).is_err());
+        assert!(parse(&args("queue retry q1 --failed")).is_err());
+        assert!(parse(&args("domain add example.com --status x")).is_err());
+        assert!(parse(&args("domain rm")).is_err());
+        assert!(parse(&args("smtp test user@proton.me --no-verify")).is_err());
+        assert!(parse(&args("send --to b@example.org")).is_err());
+        assert!(parse(&args("frobnicate")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_local_commands() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+        let mut config = crate::Config::defaults();
+        config.require_domain_verification = false;
+        let state = crate::app_state(config, conn);
+        let client = Client::Local(crate::router(Arc::clone(&state)));
+
+        let added = client
+            .call(Method::POST, "/domains", None, Some(&json!({ "domain": "example.com" })))
+            .await
+            .unwrap();
+        let rotated = client.call(Method::POST, "/domains/example.com/token", None, None).await.unwrap();
+        assert_ne!(added["token"], rotated["token"]);
+        let err = client.call(Method::POST, "/domains/missing.com/token", None, None).await.unwrap_err();
+        assert_eq!(err, "domain not found");
+
+        // The old token no longer sends; the new one queues.
+        let email = json!({ "from": "a@example.com", "to": ["b@example.org"], "subject": "Hi", "body": "x" });
+        let old = added["token"].as_str().unwrap();
+        assert!(client.call(Method::POST, "/email", Some(old), Some(&email)).await.is_err());
+        let new = rotated["token"].as_str().unwrap();
+        let queued = client.call(Method::POST, "/email", Some(new), Some(&email)).await.unwrap();
+        let id = queued["id"].as_str().unwrap();
+
+        state
+            .db
+            .lock()
+            .await
+            .execute(
+                "UPDATE email_queue SET status = 'failed', attempts = 3, last_error = 'boom' WHERE id = ?1",
+                [id],
+            )
+            .unwrap();
+        let failed = client.call(Method::GET, "/queue?status=failed", None, None).await.unwrap();
+        assert_eq!(failed[0]["id"], id);
+        assert_eq!(failed[0]["last_error"], "boom");
+        let none = client.call(Method::GET, "/queue?domain=other.com", None, None).await.unwrap();
+        assert_eq!(none, json!([]));
+        assert!(client.call(Method::GET, "/queue?status=lost", None, None).await.is_err());
+
+        let retried = client.call(Method::POST, &format!("/queue/{id}/retry"), None, None).await.unwrap();
+        assert_eq!(retried["status"], "pending");
+        assert_eq!(retried["attempts"], 0);
+
+        let purged = client.call(Method::DELETE, "/queue", None, None).await.unwrap();
+        assert_eq!(purged["purged"], 0);
+        let purged = client.call(Method::DELETE, "/queue?status=pending", None, None).await.unwrap();
+        assert_eq!(purged["purged"], 1);
+        assert!(client.call(Method::DELETE, "/queue?status=sending", None, None).await.is_err());
+
+        execute(&client, Command::DomainRm { domain: "example.com".into() }, false)
+            .await
+            .unwrap();
+        let err = execute(&client, Command::DomainRm { domain: "example.com".into() }, false)
+            .await
+            .unwrap_err();
+        assert_eq!(err, "domain not found");
+    }
+}
@@ -0,0 +1,523 @@
+//! Where settings come from: `MAYL_*` environment variables and, when
+//! `MAYL_CONFIG` names one, a TOML file. The file key `smtp.port` is the
+//! variable `MAYL_SMTP_PORT`, and a variable that is set wins over the file.
+//!
+//! Invalid values and unknown keys are collected rather than replaced by a
+//! default, so startup can report every mistake at once.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+};
+
+use crate::DomainOptionsRequest;
+
+/// Every setting, by its file key.
+const KEYS: &[&str] = &[
+    "db_path",
+    "domains",
+    "public_url",
+    "markdown_layout",
+    "require_domain_verification",
+    "dns_resolver",
+    "blocked_tlds",
+    "disposable_domains_file",
+    "server.host",
+    "server.port",
+    "smtp.host",
+    "smtp.port",
+    "smtp.user",
+    "smtp.pass",
+    "failover.threshold",
+    "failover.cooldown_seconds",
+    "queue.poll_seconds",
+    "archive.max_rows",
+    "archive.cull_interval_seconds",
+    "relay.spf_include",
+    "relay.dkim_selectors",
+    "imap.enabled",
+    "imap.host",
+    "imap.port",
+    "imap.security",
+    "imap.mailbox",
+    "imap.poll_seconds",
+    "inbound.move_to",
+    "submission.enabled",
+    "submission.host",
+    "submission.port",
+    "submission.hostname",
+    "submission.tls_cert",
+    "submission.tls_key",
+    "submission.require_tls",
+    "submission.max_size",
+];
+
+/// Variables read by the command line clients rather than the server.
+const CLIENT_VARS: &[&str] = &[
+    "MAYL_CONFIG",
+    "MAYL_URL",
+    "MAYL_TOKEN",
+    "MAYL_SENDMAIL_FROM",
+    "MAYL_SENDMAIL_CONFIG",
+];
+
+/// Shown masked by `mayl config check`.
+const SECRETS: &[&str] = &["MAYL_SMTP_PASS"];
+
+/// Shortest token a domain may be pinned to.
+const MIN_TOKEN_LEN: usize = 16;
+
+fn env_name(key: &str) -> String {
+    format!("MAYL_{}", key.replace('.', "_").to_uppercase())
+}
+
+fn file_key(env: &str) -> String {
+    KEYS.iter()
+        .find(|k| env_name(k) == env)
+        .map_or_else(|| env.to_string(), |k| k.to_string())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin {
+    Default,
+    File,
+    Env,
+}
+
+/// A domain registered on startup, from `MAYL_DOMAINS` or the file.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DomainSeed {
+    pub(crate) domain: String,
+    /// A fixed API token. It is restored on every start, undoing rotations.
+    pub(crate) token: Option<String>,
+    pub(crate) options: DomainOptionsRequest,
+}
+
+impl From<&str> for DomainSeed {
+    fn from(domain: &str) -> Self {
+        Self {
+            domain: domain.trim().to_lowercase(),
+            ..Default::default()
+        }
+    }
+}
+
+// ── Source ──────────────────────────────────────────────────────────────────
+
+pub(crate) struct Source {
+    path: Option<String>,
+    env: HashMap<String, String>,
+    /// File values by variable name; lists are joined with commas.
+    file: HashMap<String, String>,
+    file_domains: Vec<DomainSeed>,
+    /// Every setting looked up, with the value used and where it came from.
+    read: RefCell<BTreeMap<String, (String, Origin)>>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl Source {
+    /// Reads the environment and the file at `path`, or at `MAYL_CONFIG`.
+    pub(crate) fn load(path: Option<&str>) -> Self {
+        let env: HashMap<String, String> = std::env::vars().filter(|(k, _)| k.starts_with("MAYL_")).collect();
+        let path = path
+            .map(str::to_string)
+            .or_else(|| env.get("MAYL_CONFIG").filter(|p| !p.is_empty()).cloned());
+        let text = path
+            .as_ref()
+            .map(|p| std::fs::read_to_string(p).map_err(|e| format!("failed to read {p}: {e}")));
+        Self::new(path, text, env)
+    }
+
+    /// No environment and no file, so every setting takes its default.
+    #[cfg(test)]
+    pub(crate) fn empty() -> Self {
+        Self::new(None, None, HashMap::new())
+    }
+
+    fn new(path: Option<String>, text: Option<Result<String, String>>, env: HashMap<String, String>) -> Self {
+        let mut source = Self {
+            path,
+            env,
+            file: HashMap::new(),
+            file_domains: Vec::new(),
+            read: RefCell::default(),
+            errors: RefCell::default(),
+        };
+        match text {
+            Some(Ok(text)) => source.read_file(&text),
+            Some(Err(e)) => source.error(e),
+            None => {}
+        }
+
+        let known: HashSet<String> = KEYS.iter().map(|k| env_name(k)).collect();
+        let mut unknown: Vec<&String> = source
+            .env
+            .keys()
+            .filter(|k| !known.contains(*k) && !CLIENT_VARS.contains(&k.as_str()))
+            .collect();
+        unknown.sort();
+        for key in unknown {
+            source.error(format!("unknown variable {key}"));
+        }
+        source
+    }
+
+    fn file_name(&self) -> &str {
+        self.path.as_deref().unwrap_or("config file")
+    }
+
+    fn error(&self, error: String) {
+        self.errors.borrow_mut().push(error);
+    }
+
+    fn read_file(&mut self, text: &str) {
+        let table: toml::Table = match text.parse() {
+            Ok(table) => table,
+            Err(e) => return self.error(format!("{}: {e}", self.file_name())),
+        };
+        for (name, value) in table {
+            match value {
+                value if name == "domains" => self.read_domains(value),
+                toml::Value::Table(section) => {
+                    for (key, value) in section {
+                        self.set(&format!("{name}.{key}"), &value);
+                    }
+                }
+                value => self.set(&name, &value),
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &toml::Value) {
+        if !KEYS.contains(&key) {
+            return self.error(format!("{}: unknown key `{key}`", self.file_name()));
+        }
+        match scalar(value, true) {
+            Some(value) => {
+                self.file.insert(env_name(key), value);
+            }
+            None => self.error(format!(
+                "{}: `{key}` must be a string, number, boolean or list of them",
+                self.file_name()
+            )),
+        }
+    }
+
+    /// `domains` is either a list of names or a table of per-domain settings.
+    fn read_domains(&mut self, value: toml::Value) {
+        match value {
+            toml::Value::Array(names) => {
+                for name in names {
+                    match name.as_str() {
+                        Some(name) => self.file_domains.push(name.into()),
+                        None => self.error(format!("{}: `domains` must list domain names", self.file_name())),
+                    }
+                }
+            }
+            toml::Value::Table(domains) => {
+                for (name, settings) in domains {
+                    let mut seed = DomainSeed::from(name.as_str());
+                    let Some(settings) = settings.as_table() else {
+                        self.error(format!("{}: `domains.\"{name}\"` must be a table", self.file_name()));
+                        continue;
+                    };
+                    for (key, value) in settings {
+                        let flag = match key.as_str() {
+                            "token" => {
+                                match value.as_str() {
+                                    Some(token) => seed.token = Some(token.to_string()),
+                                    None => self.error(format!(
+                                        "{}: `domains.\"{name}\".token` must be a string",
+                                        self.file_name()
+                                    )),
+                                }
+                                continue;
+                            }
+                            "inline_css" => &mut seed.options.inline_css,
+                            "sanitize_html" => &mut seed.options.sanitize_html,
+                            "smime_sign" => &mut seed.options.smime_sign,
+                            _ => {
+                                self.error(format!("{}: unknown key `domains.\"{name}\".{key}`", self.file_name()));
+                                continue;
+                            }
+                        };
+                        match value.as_bool() {
+                            Some(v) => *flag = Some(v),
+                            None => self.error(format!(
+                                "{}: `domains.\"{name}\".{key}` must be true or false",
+                                self.file_name()
+                            )),
+                        }
+                    }
+                    self.file_domains.push(seed);
+                }
+            }
+            _ => self.error(format!(
+                "{}: `domains` must be a list of names or a table",
+                self.file_name()
+            )),
+        }
+    }
+
+    // ── Lookups ─────────────────────────────────────────────────────────────
+
+    fn lookup(&self, key: &str) -> Option<(String, Origin)> {
+        if let Some(value) = self.env.get(key).filter(|v| !v.is_empty()) {
+            return Some((value.clone(), Origin::Env));
+        }
+        self.file.get(key).map(|v| (v.clone(), Origin::File))
+    }
+
+    fn record(&self, key: &str, value: String, origin: Origin) {
+        self.read.borrow_mut().insert(key.to_string(), (value, origin));
+    }
+
+    /// The value of `key`, if set and not empty.
+    pub(crate) fn opt(&self, key: &str) -> Option<String> {
+        let found = self.lookup(key);
+        match &found {
+            Some((value, origin)) => self.record(key, value.clone(), *origin),
+            None => self.record(key, String::new(), Origin::Default),
+        }
+        found.map(|(value, _)| value)
+    }
+
+    pub(crate) fn or(&self, key: &str, default: &str) -> String {
+        self.opt(key).unwrap_or_else(|| {
+            self.record(key, default.to_string(), Origin::Default);
+            default.to_string()
+        })
+    }
+
+    /// The parsed value of `key`, or `default` when unset. A value that does
+    /// not parse is reported, and `default` returned so loading can go on.
+    pub(crate) fn parse<T: FromStr + Display>(&self, key: &str, default: T) -> T {
+        let Some((value, origin)) = self.lookup(key) else {
+            self.record(key, default.to_string(), Origin::Default);
+            return default;
+        };
+        self.record(key, value.clone(), origin);
+        value.parse().unwrap_or_else(|_| {
+            let expected = match std::any::type_name::<T>() {
+                "bool" => "true or false".to_string(),
+                name => format!("a valid {name}"),
+            };
+            self.invalid(key, format!("\"{value}\" is not {expected}"));
+            default
+        })
+    }
+
+    /// Reports a problem with the value of `key`, naming where it was set.
+    pub(crate) fn invalid(&self, key: &str, problem: impl Display) {
+        let label = match self.lookup(key) {
+            Some((_, Origin::File)) => format!("{} in {}", file_key(key), self.file_name()),
+            _ => key.to_string(),
+        };
+        self.error(format!("{label}: {problem}"));
+    }
+
+    /// The domains to register on startup. `MAYL_DOMAINS` replaces the file's
+    /// list; a domain named in both keeps its settings from the file.
+    pub(crate) fn domains(&self) -> Vec<DomainSeed> {
+        let seeds: Vec<DomainSeed> = match self.env.get("MAYL_DOMAINS").filter(|v| !v.is_empty()) {
+            Some(list) => {
+                self.record("MAYL_DOMAINS", list.clone(), Origin::Env);
+                list.split(',')
+                    .map(DomainSeed::from)
+                    .filter(|s| !s.domain.is_empty())
+                    .map(|s| self.file_domains.iter().find(|f| f.domain == s.domain).cloned().unwrap_or(s))
+                    .collect()
+            }
+            None => {
+                let names: Vec<&str> = self.file_domains.iter().map(|s| s.domain.as_str()).collect();
+                let origin = if names.is_empty() { Origin::Default } else { Origin::File };
+                self.record("MAYL_DOMAINS", names.join(","), origin);
+                self.file_domains.clone()
+            }
+        };
+
+        let mut tokens = HashSet::new();
+        for seed in &seeds {
+            if !seed.domain.contains('.') {
+                let label = if self.env.contains_key("MAYL_DOMAINS") { "MAYL_DOMAINS" } else { self.file_name() };
+                self.error(format!("{label}: \"{}\" is not a domain", seed.domain));
+            }
+            if let Some(token) = &seed.token {
+                if token.len() < MIN_TOKEN_LEN {
+                    self.error(format!(
+                        "{}: the token for {} must be at least {MIN_TOKEN_LEN} characters",
+                        self.file_name(),
+                        seed.domain
+                    ));
+                }
+                if !tokens.insert(token) {
+                    self.error(format!(
+                        "{}: the token for {} is used by another domain",
+                        self.file_name(),
+                        seed.domain
+                    ));
+                }
+            }
+        }
+        seeds
+    }
+
+    /// Every problem found so far.
+    pub(crate) fn errors(&self) -> Vec<String> {
+        self.errors.borrow().clone()
+    }
+
+    /// The settings looked up, one `key = value` line each with where the
+    /// value came from. Secrets and pinned tokens are masked.
+    pub(crate) fn report(&self) -> String {
+        let read = self.read.borrow();
+        let mut lines = Vec::new();
+        for key in KEYS {
+            let name = env_name(key);
+            let Some((value, origin)) = read.get(&name) else {
+                continue;
+            };
+            let value = if value.is_empty() {
+                "(unset)"
+            } else if SECRETS.contains(&name.as_str()) {
+                "********"
+            } else {
+                value
+            };
+            let origin = match origin {
+                Origin::Default => "default",
+                Origin::File => self.file_name(),
+                Origin::Env => &name,
+            };
+            lines.push(format!("{:<48} # {origin}", format!("{key} = {value}")));
+        }
+        for seed in &self.file_domains {
+            if seed.token.is_some() {
+                lines.push(format!("{:<48} # {}", format!("domains.\"{}\".token = ********", seed.domain), self.file_name()));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+/// A file value as the text its variable would hold.
+fn scalar(value: &toml::Value, allow_list: bool) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) => Some(f.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        toml::Value::Array(items) if allow_list => items
+            .iter()
+            .map(|v| scalar(v, false))
+            .collect::<Option<Vec<_>>>()
+            .map(|v| v.join(",")),
+        _ => None,
+    }
+}
+
+/// Prints `errors` for an operator and returns the exit code for them.
+pub(crate) fn report_errors(errors: &[String]) -> std::process::ExitCode {
+    eprintln!("mayl: invalid configuration:");
+    for error in errors {
+        eprintln!("  {error}");
+    }
+    std::process::ExitCode::FAILURE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(file: &str, env: &[(&str, &str)]) -> Source {
+        let env = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Source::new(Some("mayl.toml".into()), Some(Ok(file.into())), env)
+    }
+
+    #[test]
+    fn test_file_and_env() {
+        let source = source(
+            r#"
+            db_path = "/data/mayl.db"
+            blocked_tlds = ["zip", "mov"]
+
+            [smtp]
+            host = "bridge"
+            port = 1026
+            pass = "hunter2"
+
+            [domains."Example.com"]
+            token = "0123456789abcdef"
+            inline_css = true
+            "#,
+            &[("MAYL_SMTP_PORT", "2525"), ("MAYL_URL", "http://mayl:8080")],
+        );
+
+        assert_eq!(source.or("MAYL_DB_PATH", "mayl.db"), "/data/mayl.db");
+        assert_eq!(source.or("MAYL_SMTP_HOST", "localhost"), "bridge");
+        assert_eq!(source.parse("MAYL_SMTP_PORT", 1025u16), 2525);
+        assert_eq!(source.parse("MAYL_SERVER_PORT", 8080u16), 8080);
+        assert_eq!(source.opt("MAYL_BLOCKED_TLDS").as_deref(), Some("zip,mov"));
+        assert_eq!(source.opt("MAYL_SMTP_PASS").as_deref(), Some("hunter2"));
+
+        let domains = source.domains();
+        assert_eq!(domains.len(), 1);
+        assert_eq!(domains[0].domain, "example.com");
+        assert_eq!(domains[0].options.inline_css, Some(true));
+        assert!(source.errors().is_empty(), "{:?}", source.errors());
+
+        let report = source.report();
+        assert!(report.contains("smtp.port = 2525"), "{report}");
+        assert!(report.contains("# MAYL_SMTP_PORT"), "{report}");
+        assert!(report.contains("server.port = 8080"), "{report}");
+        assert!(report.contains("smtp.pass = ********"), "{report}");
+        assert!(!report.contains("hunter2") && !report.contains("0123456789abcdef"), "{report}");
+
+        // The variable replaces the file's list but keeps its settings.
+        let source = super::tests::source(
+            "[domains.\"example.com\"]\ninline_css = true\n",
+            &[("MAYL_DOMAINS", "other.com, example.com")],
+        );
+        let domains = source.domains();
+        assert_eq!(domains[0].domain, "other.com");
+        assert_eq!(domains[1].options.inline_css, Some(true));
+    }
+
+    #[test]
+    fn test_every_error_is_reported() {
+        let source = source(
+            r#"
+            queue = { poll_second = 5 }
+
+            [smtp]
+            port = "abc"
+
+            [domains.localhost]
+            token = "short"
+            colour = "blue"
+            "#,
+            &[("MAYL_SERVER_PORT", "99999"), ("MAYL_SMPT_HOST", "x")],
+        );
+        assert_eq!(source.parse("MAYL_SMTP_PORT", 1025u16), 1025);
+        assert_eq!(source.parse("MAYL_SERVER_PORT", 8080u16), 8080);
+        source.domains();
+
+        assert_eq!(
+            source.errors(),
+            vec![
+                "mayl.toml: unknown key `domains.\"localhost\".colour`",
+                "mayl.toml: unknown key `queue.poll_second`",
+                "unknown variable MAYL_SMPT_HOST",
+                "smtp.port in mayl.toml: \"abc\" is not a valid u16",
+                "MAYL_SERVER_PORT: \"99999\" is not a valid u16",
+                "mayl.toml: \"localhost\" is not a domain",
+                "mayl.toml: the token for localhost must be at least 16 characters",
+            ]
+        );
+
+        let source = super::tests::source("smtp = [", &[]);
+        assert!(source.errors()[0].starts_with("mayl.toml: TOML parse error"), "{:?}", source.errors());
+    }
+}
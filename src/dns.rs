@@ -0,0 +1,363 @@
+//! A minimal DNS stub resolver: one question per query, UDP with a TCP retry
+//! for truncated answers. Enough for TXT and MX lookups against the resolver
+//! in `MAYL_DNS_RESOLVER`.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::timeout,
+};
+
+use crate::config;
+
+pub(crate) const TYPE_MX: u16 = 15;
+pub(crate) const TYPE_TXT: u16 = 16;
+
+const CLASS_IN: u16 = 1;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const UDP_ATTEMPTS: usize = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Record {
+    /// The character-strings of one TXT record, concatenated.
+    Txt(String),
+    Mx { preference: u16, exchange: String },
+}
+
+/// Reads `MAYL_DNS_RESOLVER` (`ip` or `ip:port`), falling back to the first
+/// nameserver in `/etc/resolv.conf`, then to 1.1.1.1.
+pub(crate) fn configured_resolver(source: &config::Source) -> SocketAddr {
+    if let Some(v) = source.opt("MAYL_DNS_RESOLVER") {
+        if let Some(addr) = parse_resolver(&v) {
+            return addr;
+        }
+        source.invalid("MAYL_DNS_RESOLVER", format!("\"{v}\" is not an ip or ip:port"));
+    }
+
+    std::fs::read_to_string("/etc/resolv.conf")
+        .ok()
+        .and_then(|conf| {
+            conf.lines().find_map(|line| {
+                let mut words = line.split_whitespace();
+                (words.next() == Some("nameserver"))
+                    .then(|| words.next().and_then(parse_resolver))
+                    .flatten()
+            })
+        })
+        .unwrap_or(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 53))
+}
+
+fn parse_resolver(s: &str) -> Option<SocketAddr> {
+    s.parse::<SocketAddr>()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53)))
+}
+
+/// Looks up records of `qtype` for `name`. A name that does not exist
+/// yields no records rather than an error.
+pub(crate) async fn lookup(
+    resolver: SocketAddr,
+    name: &str,
+    qtype: u16,
+) -> Result<Vec<Record>, String> {
+    let id: u16 = rand::random();
+    let query = build_query(id, name, qtype)?;
+
+    let mut response = None;
+    let mut last_error = String::new();
+    for _ in 0..UDP_ATTEMPTS {
+        match query_udp(resolver, &query).await {
+            Ok(r) => {
+                response = Some(r);
+                break;
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    let mut response =
+        response.ok_or_else(|| format!("dns query for {name} via {resolver}: {last_error}"))?;
+
+    // TC bit: the answer did not fit in a datagram
+    if response.len() > 2 && response[2] & 0x02 != 0 {
+        response = query_tcp(resolver, &query)
+            .await
+            .map_err(|e| format!("dns query for {name} via {resolver} (tcp): {e}"))?;
+    }
+
+    parse_response(&response, id, qtype).map_err(|e| format!("dns answer for {name}: {e}"))
+}
+
+async fn query_udp(resolver: SocketAddr, query: &[u8]) -> Result<Vec<u8>, String> {
+    let bind: SocketAddr = if resolver.is_ipv4() {
+        "0.0.0.0:0".parse().unwrap()
+    } else {
+        "[::]:0".parse().unwrap()
+    };
+    let socket = UdpSocket::bind(bind).await.map_err(|e| e.to_string())?;
+    socket.connect(resolver).await.map_err(|e| e.to_string())?;
+    socket.send(query).await.map_err(|e| e.to_string())?;
+
+    let mut buf = vec![0u8; 4096];
+    let n = timeout(QUERY_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .map_err(|_| "timed out".to_string())?
+        .map_err(|e| e.to_string())?;
+    buf.truncate(n);
+    Ok(buf)
+}
+
+async fn query_tcp(resolver: SocketAddr, query: &[u8]) -> Result<Vec<u8>, String> {
+    let exchange = async {
+        let mut stream = TcpStream::connect(resolver).await?;
+        let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(query);
+        stream.write_all(&framed).await?;
+
+        let len = stream.read_u16().await? as usize;
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf).await?;
+        Ok::<_, std::io::Error>(buf)
+    };
+    timeout(QUERY_TIMEOUT, exchange)
+        .await
+        .map_err(|_| "timed out".to_string())?
+        .map_err(|e| e.to_string())
+}
+
+// ── Wire format (RFC 1035) ──────────────────────────────────────────────────
+
+fn build_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>, String> {
+    let mut q = Vec::with_capacity(18 + name.len());
+    q.extend_from_slice(&id.to_be_bytes());
+    q.extend_from_slice(&0x0100u16.to_be_bytes()); // recursion desired
+    q.extend_from_slice(&1u16.to_be_bytes()); // QDCOUNT
+    q.extend_from_slice(&[0; 6]); // ANCOUNT, NSCOUNT, ARCOUNT
+
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("invalid name '{name}'"));
+        }
+        q.push(label.len() as u8);
+        q.extend_from_slice(label.as_bytes());
+    }
+    q.push(0);
+    q.extend_from_slice(&qtype.to_be_bytes());
+    q.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(q)
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Result<u16, String> {
+    msg.get(pos..pos + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or_else(|| "truncated message".to_string())
+}
+
+/// Reads a possibly compressed name at `pos`, returning it and the offset
+/// just past it in the original position.
+fn read_name(msg: &[u8], mut pos: usize) -> Result<(String, usize), String> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *msg.get(pos).ok_or("truncated name")? as usize;
+        match len {
+            0 => {
+                end.get_or_insert(pos + 1);
+                break;
+            }
+            l if l & 0xC0 == 0xC0 => {
+                let target = (read_u16(msg, pos)? & 0x3FFF) as usize;
+                end.get_or_insert(pos + 2);
+                jumps += 1;
+                if jumps > 32 {
+                    return Err("compression loop".into());
+                }
+                pos = target;
+            }
+            l if l <= 63 => {
+                let label = msg.get(pos + 1..pos + 1 + l).ok_or("truncated label")?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + l;
+            }
+            _ => return Err("bad label".into()),
+        }
+    }
+    Ok((labels.join("."), end.unwrap_or(pos)))
+}
+
+fn parse_response(msg: &[u8], id: u16, qtype: u16) -> Result<Vec<Record>, String> {
+    if msg.len() < 12 {
+        return Err("truncated message".into());
+    }
+    if read_u16(msg, 0)? != id {
+        return Err("response id does not match query".into());
+    }
+    let flags = read_u16(msg, 2)?;
+    if flags & 0x8000 == 0 {
+        return Err("not a response".into());
+    }
+    match flags & 0x000F {
+        0 => {}
+        3 => return Ok(Vec::new()), // NXDOMAIN
+        2 => return Err("server failure".into()),
+        5 => return Err("query refused".into()),
+        rcode => return Err(format!("error code {rcode}")),
+    }
+
+    let qdcount = read_u16(msg, 4)?;
+    let ancount = read_u16(msg, 6)?;
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        pos = read_name(msg, pos)?.1 + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..ancount {
+        pos = read_name(msg, pos)?.1;
+        let rtype = read_u16(msg, pos)?;
+        let rdlength = read_u16(msg, pos + 8)? as usize;
+        let rdata_start = pos + 10;
+        let rdata = msg
+            .get(rdata_start..rdata_start + rdlength)
+            .ok_or("truncated record")?;
+        pos = rdata_start + rdlength;
+
+        // Answers may include the CNAME chain; keep only what was asked for
+        if rtype != qtype {
+            continue;
+        }
+        match rtype {
+            TYPE_TXT => {
+                let mut text = String::new();
+                let mut i = 0;
+                while i < rdata.len() {
+                    let len = rdata[i] as usize;
+                    let chunk = rdata.get(i + 1..i + 1 + len).ok_or("truncated TXT")?;
+                    text.push_str(&String::from_utf8_lossy(chunk));
+                    i += 1 + len;
+                }
+                records.push(Record::Txt(text));
+            }
+            TYPE_MX => {
+                let preference = read_u16(msg, rdata_start)?;
+                let (exchange, _) = read_name(msg, rdata_start + 2)?;
+                records.push(Record::Mx {
+                    preference,
+                    exchange,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds an answer to `query` with the given (type, rdata) records, all
+    /// owned by the question name via a compression pointer.
+    pub(crate) fn answer(query: &[u8], records: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut r = query.to_vec();
+        r[2] = 0x81; // QR, RD
+        r[3] = 0x80; // RA
+        r[6..8].copy_from_slice(&(records.len() as u16).to_be_bytes());
+        for (rtype, rdata) in records {
+            r.extend_from_slice(&[0xC0, 12]);
+            r.extend_from_slice(&rtype.to_be_bytes());
+            r.extend_from_slice(&CLASS_IN.to_be_bytes());
+            r.extend_from_slice(&300u32.to_be_bytes());
+            r.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            r.extend_from_slice(rdata);
+        }
+        r
+    }
+
+    pub(crate) fn txt_rdata(strings: &[&str]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for s in strings {
+            out.push(s.len() as u8);
+            out.extend_from_slice(s.as_bytes());
+        }
+        out
+    }
+
+    /// The question name of a query, for stub servers.
+    pub(crate) fn question(query: &[u8]) -> (String, u16) {
+        let (name, end) = read_name(query, 12).unwrap();
+        (name, read_u16(query, end).unwrap())
+    }
+
+    /// A UDP DNS server on localhost answering from `zone`, a list of
+    /// (name, type, rdata).
+    pub(crate) fn stub_server(zone: Vec<(String, u16, Vec<u8>)>) -> SocketAddr {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((n, peer)) = socket.recv_from(&mut buf) {
+                let query = &buf[..n];
+                let (name, qtype) = question(query);
+                let records: Vec<(u16, Vec<u8>)> = zone
+                    .iter()
+                    .filter(|(n, t, _)| n.eq_ignore_ascii_case(&name) && *t == qtype)
+                    .map(|(_, t, d)| (*t, d.clone()))
+                    .collect();
+                let _ = socket.send_to(&answer(query, &records), peer);
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_parse_txt_and_mx() {
+        let query = build_query(7, "example.com", TYPE_TXT).unwrap();
+        let response = answer(
+            &query,
+            &[
+                (TYPE_TXT, txt_rdata(&["v=spf1 ", "-all"])),
+                (5, vec![0xC0, 12]), // CNAME is skipped
+            ],
+        );
+        assert_eq!(
+            parse_response(&response, 7, TYPE_TXT).unwrap(),
+            vec![Record::Txt("v=spf1 -all".into())]
+        );
+        assert!(parse_response(&response, 8, TYPE_TXT).is_err());
+
+        let query = build_query(9, "example.com", TYPE_MX).unwrap();
+        let mut rdata = 10u16.to_be_bytes().to_vec();
+        rdata.extend_from_slice(&[2, b'm', b'x', 0xC0, 12]);
+        let response = answer(&query, &[(TYPE_MX, rdata)]);
+        assert_eq!(
+            parse_response(&response, 9, TYPE_MX).unwrap(),
+            vec![Record::Mx {
+                preference: 10,
+                exchange: "mx.example.com".into()
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lookup_against_stub() {
+        let resolver = stub_server(vec![(
+            "_mayl-challenge.example.com".into(),
+            TYPE_TXT,
+            txt_rdata(&["mayl-verification=abc"]),
+        )]);
+        let records = lookup(resolver, "_mayl-challenge.example.com", TYPE_TXT)
+            .await
+            .unwrap();
+        assert_eq!(records, vec![Record::Txt("mayl-verification=abc".into())]);
+
+        let none = lookup(resolver, "other.example.com", TYPE_TXT).await.unwrap();
+        assert!(none.is_empty());
+    }
+}
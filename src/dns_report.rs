@@ -0,0 +1,514 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{ApiError, AppState, api_error, config, dkim, dns, now_millis};
+
+/// RFC 7208 caps the DNS lookups one SPF evaluation may cause.
+const SPF_LOOKUP_LIMIT: usize = 10;
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+/// The relay mayl hands mail to, which receivers must see authorized.
+#[derive(Debug, Clone)]
+pub(crate) struct Relay {
+    /// SPF domain the relay publishes, e.g. `_spf.protonmail.ch`.
+    pub(crate) spf_include: String,
+    /// DKIM selectors the relay signs with when mayl has no key of its own.
+    pub(crate) dkim_selectors: Vec<String>,
+}
+
+impl Relay {
+    /// Reads `MAYL_RELAY_SPF_INCLUDE` and `MAYL_RELAY_DKIM_SELECTORS`
+    /// (comma separated); the defaults describe Proton Mail.
+    pub(crate) fn load(source: &config::Source) -> Self {
+        let spf_include = source.or("MAYL_RELAY_SPF_INCLUDE", "_spf.protonmail.ch");
+        let dkim_selectors = source
+            .or("MAYL_RELAY_DKIM_SELECTORS", "protonmail,protonmail2,protonmail3")
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        Self {
+            spf_include,
+            dkim_selectors,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Check {
+    pub(crate) name: String,
+    pub(crate) status: Status,
+    /// The records found, verbatim.
+    records: Vec<String>,
+    pub(crate) detail: String,
+    /// What to change in DNS, when anything needs changing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) fix: Option<String>,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, records: Vec<String>, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            records,
+            detail: detail.into(),
+            fix: None,
+        }
+    }
+
+    fn fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+
+    fn lookup_failed(name: &str, error: String) -> Self {
+        Self::new(name, Status::Warn, Vec::new(), format!("lookup failed: {error}"))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Report {
+    domain: String,
+    /// The worst status among the checks.
+    pub(crate) status: Status,
+    checked_at: i64,
+    pub(crate) checks: Vec<Check>,
+}
+
+// ── Database ────────────────────────────────────────────────────────────────
+
+pub(crate) fn init_db(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS dns_reports (
+            domain TEXT PRIMARY KEY,
+            status TEXT NOT NULL,
+            report TEXT NOT NULL,
+            checked_at INTEGER NOT NULL
+        );",
+    )
+    .expect("failed to initialize dns_reports table");
+}
+
+pub(crate) fn delete_domain(conn: &Connection, domain: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM dns_reports WHERE domain = ?1", [domain]).map(|_| ())
+}
+
+fn store(conn: &Connection, report: &Report) -> rusqlite::Result<usize> {
+    let json = serde_json::to_string(report).unwrap_or_default();
+    conn.execute(
+        "INSERT INTO dns_reports (domain, status, report, checked_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(domain) DO UPDATE SET status = ?2, report = ?3, checked_at = ?4",
+        rusqlite::params![&report.domain, report.status.as_str(), json, report.checked_at],
+    )
+}
+
+/// The last report for every domain that has been checked, for the dashboard.
+pub(crate) fn cached(conn: &Connection) -> HashMap<String, Report> {
+    let Ok(mut stmt) = conn.prepare("SELECT domain, report FROM dns_reports") else {
+        return HashMap::new();
+    };
+    stmt.query_map([], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))
+        .map(|rows| {
+            rows.filter_map(|r| r.ok())
+                .filter_map(|(domain, json)| Some((domain, serde_json::from_str(&json).ok()?)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// ── Checks ──────────────────────────────────────────────────────────────────
+
+async fn txt(resolver: SocketAddr, name: &str) -> Result<Vec<String>, String> {
+    Ok(dns::lookup(resolver, name, dns::TYPE_TXT)
+        .await?
+        .into_iter()
+        .filter_map(|r| match r {
+            dns::Record::Txt(t) => Some(t),
+            _ => None,
+        })
+        .collect())
+}
+
+fn has_prefix(record: &str, prefix: &str) -> bool {
+    record
+        .get(..prefix.len())
+        .is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+}
+
+/// Domains an SPF record delegates to through `include:` and `redirect=`.
+fn spf_delegations(record: &str) -> Vec<String> {
+    record
+        .split_whitespace()
+        .filter_map(|term| {
+            let term = term.trim_start_matches(['+', '-', '~', '?']);
+            let lower = term.to_lowercase();
+            lower
+                .strip_prefix("include:")
+                .or_else(|| lower.strip_prefix("redirect="))
+                .map(str::to_string)
+        })
+        .collect()
+}
+
+/// The qualifier of the record's `all` mechanism, if it has one.
+fn spf_all(record: &str) -> Option<char> {
+    record.split_whitespace().find_map(|term| match term.to_lowercase().as_str() {
+        "all" | "+all" => Some('+'),
+        "-all" => Some('-'),
+        "~all" => Some('~'),
+        "?all" => Some('?'),
+        _ => None,
+    })
+}
+
+/// Whether `record` reaches `include` through its delegations, following
+/// them up to the RFC 7208 lookup limit.
+async fn spf_authorizes(resolver: SocketAddr, record: &str, include: &str) -> Result<bool, String> {
+    let mut pending = vec![record.to_string()];
+    let mut lookups = 0;
+    while let Some(record) = pending.pop() {
+        for target in spf_delegations(&record) {
+            if target.eq_ignore_ascii_case(include) {
+                return Ok(true);
+            }
+            if lookups == SPF_LOOKUP_LIMIT {
+                return Ok(false);
+            }
+            lookups += 1;
+            pending.extend(
+                txt(resolver, &target)
+                    .await?
+                    .into_iter()
+                    .filter(|r| has_prefix(r, "v=spf1")),
+            );
+        }
+    }
+    Ok(false)
+}
+
+async fn check_spf(resolver: SocketAddr, domain: &str, relay: &Relay) -> Check {
+    let include = &relay.spf_include;
+    let records = match txt(resolver, domain).await {
+        Ok(r) => r.into_iter().filter(|r| has_prefix(r, "v=spf1")).collect::<Vec<_>>(),
+        Err(e) => return Check::lookup_failed("spf", e),
+    };
+
+    let [record] = records.as_slice() else {
+        return if records.is_empty() {
+            Check::new("spf", Status::Fail, records, "no SPF record")
+                .fix(format!("Publish TXT {domain}: \"v=spf1 include:{include} ~all\""))
+        } else {
+            Check::new("spf", Status::Fail, records, "multiple SPF records; receivers treat this as an error")
+                .fix("Merge them into a single v=spf1 record")
+        };
+    };
+
+    let all = spf_all(record);
+    if all == Some('+') {
+        return Check::new("spf", Status::Fail, records.clone(), "record authorizes every host")
+            .fix("Replace +all with ~all or -all");
+    }
+    match spf_authorizes(resolver, record, include).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return Check::new(
+                "spf",
+                Status::Fail,
+                records.clone(),
+                format!("relay {include} is not authorized"),
+            )
+            .fix(format!("Add include:{include} before the all mechanism"));
+        }
+        Err(e) => return Check::lookup_failed("spf", e),
+    }
+    if !matches!(all, Some('-' | '~')) {
+        return Check::new("spf", Status::Warn, records.clone(), "record does not end in -all or ~all")
+            .fix("End the record with ~all so unlisted hosts fail SPF");
+    }
+    Check::new("spf", Status::Pass, records.clone(), format!("authorizes {include}"))
+}
+
+/// The `tag=value` pairs of a DMARC record.
+fn dmarc_tags(record: &str) -> HashMap<String, String> {
+    record
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+        .collect()
+}
+
+async fn check_dmarc(resolver: SocketAddr, domain: &str) -> Check {
+    let name = format!("_dmarc.{domain}");
+    let records = match txt(resolver, &name).await {
+        Ok(r) => r.into_iter().filter(|r| has_prefix(r, "v=DMARC1")).collect::<Vec<_>>(),
+        Err(e) => return Check::lookup_failed("dmarc", e),
+    };
+
+    let [record] = records.as_slice() else {
+        return if records.is_empty() {
+            Check::new("dmarc", Status::Fail, records, "no DMARC record").fix(format!(
+                "Publish TXT {name}: \"v=DMARC1; p=quarantine; rua=mailto:dmarc@{domain}\""
+            ))
+        } else {
+            Check::new("dmarc", Status::Fail, records, "multiple DMARC records; receivers ignore all of them")
+                .fix("Keep a single v=DMARC1 record")
+        };
+    };
+
+    let tags = dmarc_tags(record);
+    match tags.get("p").map(|p| p.to_lowercase()).as_deref() {
+        Some("quarantine" | "reject") => {
+            Check::new("dmarc", Status::Pass, records.clone(), format!("policy p={}", tags["p"]))
+        }
+        Some("none") => Check::new("dmarc", Status::Warn, records.clone(), "policy p=none only monitors")
+            .fix("Raise to p=quarantine once aggregate reports look clean"),
+        _ => Check::new("dmarc", Status::Fail, records.clone(), "record has no valid p= policy")
+            .fix("Add p=quarantine to the record"),
+    }
+}
+
+async fn check_mx(resolver: SocketAddr, domain: &str) -> Check {
+    let mut hosts = match dns::lookup(resolver, domain, dns::TYPE_MX).await {
+        Ok(r) => r
+            .into_iter()
+            .filter_map(|r| match r {
+                dns::Record::Mx {
+                    preference,
+                    exchange,
+                } => Some((preference, exchange)),
+                _ => None,
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => return Check::lookup_failed("mx", e),
+    };
+    hosts.sort();
+    let records: Vec<String> = hosts.iter().map(|(p, h)| format!("{p} {h}")).collect();
+
+    if records.is_empty() {
+        Check::new("mx", Status::Warn, records, "no MX records; bounces and replies cannot be delivered")
+            .fix(format!("Publish MX records for {domain} pointing at your mailbox provider"))
+    } else {
+        Check::new("mx", Status::Pass, records, "domain accepts mail")
+    }
+}
+
+/// The base64 `p=` value of a DKIM record, without folding whitespace.
+fn dkim_public_key(record: &str) -> Option<String> {
+    dmarc_tags(record)
+        .get("p")
+        .map(|p| p.split_whitespace().collect())
+}
+
+async fn check_dkim(
+    resolver: SocketAddr,
+    domain: &str,
+    relay: &Relay,
+    expected: Option<(String, String)>,
+) -> Check {
+    // mayl signs itself: its key must be published as issued
+    if let Some((name, record)) = expected {
+        let records = match txt(resolver, &name).await {
+            Ok(r) => r.into_iter().filter(|r| r.contains("p=")).collect::<Vec<_>>(),
+            Err(e) => return Check::lookup_failed("dkim", e),
+        };
+        let want = dkim_public_key(&record);
+        return if records.is_empty() {
+            Check::new("dkim", Status::Fail, records, format!("no key published at {name}"))
+                .fix(format!("Publish TXT {name}: \"{record}\""))
+        } else if records.iter().any(|r| dkim_public_key(r) == want) {
+            Check::new("dkim", Status::Pass, records, format!("mayl key published at {name}"))
+        } else {
+            Check::new("dkim", Status::Fail, records, format!("key at {name} does not match mayl's"))
+                .fix(format!("Replace the TXT record at {name} with \"{record}\""))
+        };
+    }
+
+    let mut records = Vec::new();
+    let mut found = Vec::new();
+    for selector in &relay.dkim_selectors {
+        let name = format!("{selector}._domainkey.{domain}");
+        match txt(resolver, &name).await {
+            Ok(r) => {
+                let keys: Vec<String> = r.into_iter().filter(|r| r.contains("p=")).collect();
+                if !keys.is_empty() {
+                    found.push(selector.as_str());
+                }
+                records.extend(keys);
+            }
+            Err(e) => return Check::lookup_failed("dkim", e),
+        }
+    }
+
+    if found.is_empty() {
+        Check::new("dkim", Status::Fail, records, "no DKIM key for the relay's selectors").fix(format!(
+            "Publish the relay's DKIM records ({}) or generate one with PUT /domains/{domain}/dkim",
+            relay
+                .dkim_selectors
+                .iter()
+                .map(|s| format!("{s}._domainkey.{domain}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    } else {
+        Check::new("dkim", Status::Pass, records, format!("relay selectors published: {}", found.join(", ")))
+    }
+}
+
+async fn run(
+    resolver: SocketAddr,
+    domain: &str,
+    relay: &Relay,
+    dkim_expected: Option<(String, String)>,
+) -> Report {
+    let checks = vec![
+        check_spf(resolver, domain, relay).await,
+        check_dkim(resolver, domain, relay, dkim_expected).await,
+        check_dmarc(resolver, domain).await,
+        check_mx(resolver, domain).await,
+    ];
+    Report {
+        domain: domain.to_string(),
+        status: checks.iter().map(|c| c.status).max().unwrap_or(Status::Pass),
+        checked_at: now_millis(),
+        checks,
+    }
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+/// Checks the domain's DNS live and caches the result for the dashboard.
+pub(crate) async fn dns_report_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Result<Json<Report>, ApiError> {
+    let domain = domain.to_lowercase();
+    let dkim_expected = {
+        let db = state.db.lock().await;
+        db.query_row("SELECT 1 FROM domains WHERE domain = ?1", [&domain], |_| Ok(()))
+            .optional()
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?
+            .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "domain not found"))?;
+        dkim::expected_record(&db, &domain)
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+    };
+
+    let report = run(state.config.dns_resolver, &domain, &state.config.relay, dkim_expected).await;
+
+    let db = state.db.lock().await;
+    store(&db, &report)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+    info!(domain, status = report.status.as_str(), "dns report");
+    Ok(Json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::tests::{stub_server, txt_rdata};
+
+    fn relay() -> Relay {
+        Relay {
+            spf_include: "_spf.relay.example".into(),
+            dkim_selectors: vec!["relay1".into(), "relay2".into()],
+        }
+    }
+
+    fn mx_rdata(preference: u16, host: &str) -> Vec<u8> {
+        let mut out = preference.to_be_bytes().to_vec();
+        for label in host.split('.') {
+            out.push(label.len() as u8);
+            out.extend_from_slice(label.as_bytes());
+        }
+        out.push(0);
+        out
+    }
+
+    fn by_name(report: &Report) -> HashMap<&str, &Check> {
+        report.checks.iter().map(|c| (c.name.as_str(), c)).collect()
+    }
+
+    #[test]
+    fn test_spf_terms() {
+        let record = "v=spf1 ip4:192.0.2.1 include:_spf.Google.com ~include:x.example redirect=y.example -all";
+        assert_eq!(
+            spf_delegations(record),
+            vec!["_spf.google.com", "x.example", "y.example"]
+        );
+        assert_eq!(spf_all(record), Some('-'));
+        assert_eq!(spf_all("v=spf1 a mx"), None);
+        assert_eq!(spf_all("v=spf1 +all"), Some('+'));
+    }
+
+    #[tokio::test]
+    async fn test_report_against_stub() {
+        let txt = |name: &str, value: &str| (name.to_string(), dns::TYPE_TXT, txt_rdata(&[value]));
+        let resolver = stub_server(vec![
+            // The relay is reached through a nested include
+            txt("example.com", "v=spf1 include:_spf.example.com ~all"),
+            txt("example.com", "site-verification=abc"),
+            txt("_spf.example.com", "v=spf1 include:_spf.relay.example -all"),
+            txt("_dmarc.example.com", "v=DMARC1; p=none; rua=mailto:d@example.com"),
+            txt("relay2._domainkey.example.com", "v=DKIM1; k=rsa; p=MIIB"),
+            ("example.com".into(), dns::TYPE_MX, mx_rdata(10, "mx.example.com")),
+        ]);
+        let report = run(resolver, "example.com", &relay(), None).await;
+        let checks = by_name(&report);
+        assert_eq!(checks["spf"].status, Status::Pass, "{:?}", checks["spf"]);
+        assert_eq!(checks["dkim"].status, Status::Pass);
+        assert_eq!(checks["dmarc"].status, Status::Warn);
+        assert!(checks["dmarc"].fix.is_some());
+        assert_eq!(checks["mx"].records, vec!["10 mx.example.com"]);
+        assert_eq!(report.status, Status::Warn);
+
+        // mayl's own key takes precedence over the relay selectors
+        let expected = Some((
+            "mayl._domainkey.example.com".to_string(),
+            "v=DKIM1; k=rsa; p=MIIB".to_string(),
+        ));
+        let report = run(resolver, "example.com", &relay(), expected).await;
+        let dkim = by_name(&report)["dkim"].clone();
+        assert_eq!(dkim.status, Status::Fail);
+        assert_eq!(
+            dkim.fix.as_deref(),
+            Some("Publish TXT mayl._domainkey.example.com: \"v=DKIM1; k=rsa; p=MIIB\"")
+        );
+
+        let report = run(resolver, "unknown.example", &relay(), None).await;
+        assert_eq!(report.status, Status::Fail);
+        assert!(
+            report
+                .checks
+                .iter()
+                .all(|c| c.status != Status::Pass && c.fix.is_some()),
+            "{report:?}"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio_stream::{Stream, StreamExt, wrappers::BroadcastStream};
+
+use crate::{ApiError, AppState, api_error, extract_token, now_millis, webhooks::EmailEvent};
+
+/// Events buffered per subscriber; a subscriber that falls further behind
+/// skips the missed ones.
+const CHANNEL_CAPACITY: usize = 256;
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Kind {
+    Queued,
+    Sent,
+    Retrying,
+    Failed,
+    Bounced,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Queued => "queued",
+            Kind::Sent => "sent",
+            Kind::Retrying => "retrying",
+            Kind::Failed => "failed",
+            Kind::Bounced => "bounced",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct QueueEvent {
+    kind: Kind,
+    id: String,
+    domain: String,
+    to: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    timestamp: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct EventsQuery {
+    /// Domain token, for clients like `EventSource` that cannot set headers.
+    token: Option<String>,
+}
+
+pub(crate) type Sender = broadcast::Sender<QueueEvent>;
+
+pub(crate) fn channel() -> Sender {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
+
+/// Broadcasts to current subscribers; nobody listening is not an error.
+pub(crate) fn publish(tx: &Sender, kind: Kind, domain: &str, email: &EmailEvent) {
+    let _ = tx.send(QueueEvent {
+        kind,
+        id: email.id.to_string(),
+        domain: domain.to_string(),
+        to: email.to.to_vec(),
+        error: email.error.map(str::to_string),
+        timestamp: now_millis(),
+    });
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+/// Events for `domain`, or for every domain when `None`.
+fn stream(
+    rx: broadcast::Receiver<QueueEvent>,
+    domain: Option<String>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    BroadcastStream::new(rx).filter_map(move |received| {
+        let event = received.ok()?;
+        if domain.as_ref().is_some_and(|d| *d != event.domain) {
+            return None;
+        }
+        Event::default()
+            .event(event.kind.as_str())
+            .json_data(&event)
+            .ok()
+            .map(Ok)
+    })
+}
+
+/// Activity across every domain, for the dashboard.
+pub(crate) async fn events_handler(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    Sse::new(stream(state.events.subscribe(), None)).keep_alive(KeepAlive::default())
+}
+
+/// Activity for the domain a token authorizes.
+pub(crate) async fn domain_events_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<EventsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    let token = extract_token(&headers)
+        .or(query.token)
+        .ok_or_else(|| api_error(StatusCode::UNAUTHORIZED, "missing Authorization header"))?;
+    let domain: String = {
+        let db = state.db.lock().await;
+        db.query_row("SELECT domain FROM domains WHERE token = ?1", [&token], |r| r.get(0))
+            .map_err(|_| api_error(StatusCode::UNAUTHORIZED, "invalid token"))?
+    };
+
+    // Subscribe before returning so nothing published after this is missed
+    let rx = state.events.subscribe();
+    Ok(Sse::new(stream(rx, Some(domain))).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_stream_is_scoped_to_domain() {
+        let tx = channel();
+        let scoped = stream(tx.subscribe(), Some("example.com".into()));
+        let all = stream(tx.subscribe(), None);
+
+        let to = vec!["ada@example.org".to_string()];
+        for (kind, domain, id) in [
+            (Kind::Queued, "other.com", "m1"),
+            (Kind::Sent, "example.com", "m2"),
+        ] {
+            let email = EmailEvent {
+                id,
+                from: "app@example.com",
+                to: &to,
+                error: None,
+            };
+            publish(&tx, kind, domain, &email);
+        }
+        drop(tx);
+
+        let scoped: Vec<Event> = scoped.map(|e| e.unwrap()).collect().await;
+        let all: Vec<Event> = all.map(|e| e.unwrap()).collect().await;
+        assert_eq!(scoped.len(), 1);
+        assert_eq!(all.len(), 2);
+
+        let rendered = format!("{:?}", scoped[0]);
+        assert!(rendered.contains("event: sent"), "{rendered}");
+        assert!(rendered.contains(r#"\"id\":\"m2\""#), "{rendered}");
+    }
+}
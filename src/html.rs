@@ -0,0 +1,84 @@
+/// Column width for text parts derived from HTML, the customary limit for
+/// plain-text mail.
+const TEXT_WIDTH: usize = 78;
+
+/// Derives a readable plain-text alternative from an HTML body. Links are
+/// collected as numbered footnotes; headings and lists keep their markers.
+pub(crate) fn to_text(html: &str) -> Result<String, String> {
+    html2text::from_read(html.as_bytes(), TEXT_WIDTH).map_err(|e| format!("html to text: {e}"))
+}
+
+/// Moves rules from `<style>` blocks into `style` attributes, since most
+/// webmail clients drop `<style>` entirely. Remote stylesheets are not loaded.
+pub(crate) fn inline_css(html: &str) -> Result<String, String> {
+    css_inline::CSSInliner::options()
+        .load_remote_stylesheets(false)
+        .keep_style_tags(false)
+        .build()
+        .inline(html)
+        .map_err(|e| format!("css inline: {e}"))
+}
+
+/// Strips scripts, event handlers and tags outside ammonia's allow-list while
+/// keeping the presentational attributes that email layouts rely on.
+pub(crate) fn sanitize(html: &str) -> String {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .add_tags(["font"])
+        .add_generic_attributes([
+            "style", "align", "valign", "width", "height", "bgcolor", "border", "dir",
+        ])
+        .add_tag_attributes("table", ["cellpadding", "cellspacing"])
+        .add_tag_attributes("font", ["color", "face", "size"])
+        .add_url_schemes(["cid"]);
+    builder.clean(html).to_string()
+}
+
+/// Applies the per-domain processing steps to an HTML body. CSS is inlined
+/// first so the sanitizer can drop the then-empty `<style>` blocks.
+pub(crate) fn process(html: &str, inline: bool, clean: bool) -> Result<String, String> {
+    let mut out = if inline {
+        inline_css(html)?
+    } else {
+        html.to_string()
+    };
+    if clean {
+        out = sanitize(&out);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_text_footnotes_links() {
+        let text = to_text(
+            "<h1>Hello</h1><p>Read <a href=\"https://example.com/a\">this</a>.</p>\
+             <ul><li>one</li><li>two</li></ul>",
+        )
+        .unwrap();
+        assert!(text.contains("Hello"), "{text}");
+        assert!(text.contains("[this][1]"), "{text}");
+        assert!(text.contains("[1]: https://example.com/a"), "{text}");
+        assert!(text.contains("* one"), "{text}");
+        assert!(!text.contains('<'), "{text}");
+    }
+
+    #[test]
+    fn test_process_inlines_then_sanitizes() {
+        let html = "<html><head><style>p { color: red }</style></head>\
+                    <body><p onclick=\"x()\">Hi</p><script>alert(1)</script></body></html>";
+
+        let inlined = process(html, true, false).unwrap();
+        assert!(inlined.contains("<p onclick=\"x()\" style=\"color: red;\">"), "{inlined}");
+        assert!(!inlined.contains("<style>"), "{inlined}");
+
+        let cleaned = process(html, true, true).unwrap();
+        assert_eq!(cleaned, "<p style=\"color: red;\">Hi</p>");
+
+        let untouched = process(html, false, false).unwrap();
+        assert_eq!(untouched, html);
+    }
+}
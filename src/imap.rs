@@ -0,0 +1,430 @@
+//! A minimal IMAP4rev1 client: just enough to log in, select a mailbox and
+//! fetch new messages by UID from Proton Bridge.
+
+use std::time::Duration;
+
+use rusqlite::{Connection, OptionalExtension};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::timeout,
+};
+use tokio_native_tls::{TlsConnector, native_tls};
+
+use crate::config;
+
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest literal read from the server, so a bad length cannot make us
+/// allocate without bound. No message we handle is bigger than one we send.
+const MAX_LITERAL: usize = crate::raw::MAX_SIZE;
+
+// ── Config ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Security {
+    /// Plain connection upgraded with STARTTLS, as Bridge does by default.
+    StartTls,
+    /// TLS from the first byte (IMAPS).
+    Tls,
+    /// No encryption; for local stand-ins only.
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Settings {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) security: Security,
+    pub(crate) mailbox: String,
+    pub(crate) poll_seconds: u64,
+    /// Where routed inbound mail is moved; it is only marked read otherwise.
+    pub(crate) inbound_move_to: Option<String>,
+}
+
+impl Settings {
+    /// Reads the `MAYL_IMAP_*` settings; `None` unless `MAYL_IMAP_ENABLED`
+    /// is set. The host defaults to the SMTP host, since both are the bridge.
+    pub(crate) fn load(source: &config::Source, smtp_host: &str) -> Option<Self> {
+        if !source.parse("MAYL_IMAP_ENABLED", false) {
+            return None;
+        }
+        let security = match source.or("MAYL_IMAP_SECURITY", "starttls").as_str() {
+            "starttls" => Security::StartTls,
+            "tls" => Security::Tls,
+            "none" => Security::None,
+            other => {
+                source.invalid("MAYL_IMAP_SECURITY", format!("\"{other}\" is not starttls, tls or none"));
+                Security::StartTls
+            }
+        };
+        Some(Self {
+            host: source.or("MAYL_IMAP_HOST", smtp_host),
+            port: source.parse("MAYL_IMAP_PORT", 1143),
+            security,
+            mailbox: source.or("MAYL_IMAP_MAILBOX", "INBOX"),
+            poll_seconds: source.parse("MAYL_IMAP_POLL_SECONDS", 60),
+            inbound_move_to: source.opt("MAYL_INBOUND_MOVE_TO"),
+        })
+    }
+}
+
+// ── Checkpoints ─────────────────────────────────────────────────────────────
+
+/// Where a poller stopped in a mailbox: `(uid_validity, last processed UID)`,
+/// stored in the config table under `key`.
+pub(crate) fn checkpoint(conn: &Connection, key: &str) -> Option<(u32, u32)> {
+    let value: String = conn
+        .query_row("SELECT value FROM config WHERE key = ?1", [key], |r| r.get(0))
+        .optional()
+        .ok()??;
+    let (validity, uid) = value.split_once(':')?;
+    Some((validity.parse().ok()?, uid.parse().ok()?))
+}
+
+pub(crate) fn save_checkpoint(conn: &Connection, key: &str, uid_validity: u32, uid: u32) {
+    let _ = conn.execute(
+        "INSERT INTO config (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = ?2",
+        [key, &format!("{uid_validity}:{uid}")],
+    );
+}
+
+// ── Client ──────────────────────────────────────────────────────────────────
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// One untagged response: its text with literals elided, and the literals.
+#[derive(Debug, Default)]
+struct Response {
+    text: String,
+    literals: Vec<Vec<u8>>,
+}
+
+/// What `SELECT` reports about the mailbox.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Mailbox {
+    pub(crate) uid_validity: u32,
+    pub(crate) uid_next: u32,
+}
+
+pub(crate) struct Client {
+    stream: BufReader<Box<dyn Io>>,
+    tag: u32,
+}
+
+/// Bridge serves a self-signed certificate, as it does for SMTP.
+async fn wrap_tls(host: &str, stream: Box<dyn Io>) -> Result<Box<dyn Io>, String> {
+    let connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()
+        .map_err(|e| format!("imap tls: {e}"))?;
+    let tls = TlsConnector::from(connector)
+        .connect(host, stream)
+        .await
+        .map_err(|e| format!("imap tls: {e}"))?;
+    Ok(Box::new(tls))
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The literal length announced at the end of a line (`... {123}\r\n`).
+fn literal_len(line: &str) -> Option<usize> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let open = line.rfind('{')?;
+    line.strip_suffix('}')?[open + 1..].parse().ok()
+}
+
+impl Client {
+    pub(crate) async fn connect(settings: &Settings) -> Result<Self, String> {
+        let tcp = timeout(IO_TIMEOUT, TcpStream::connect((settings.host.as_str(), settings.port)))
+            .await
+            .map_err(|_| "imap connect: timed out".to_string())?
+            .map_err(|e| format!("imap connect: {e}"))?;
+        let mut stream: Box<dyn Io> = Box::new(tcp);
+        if settings.security == Security::Tls {
+            stream = wrap_tls(&settings.host, stream).await?;
+        }
+
+        let mut client = Self {
+            stream: BufReader::new(stream),
+            tag: 0,
+        };
+        let greeting = client.read_line().await?;
+        if !greeting.starts_with("* OK") && !greeting.starts_with("* PREAUTH") {
+            return Err(format!("imap greeting: {}", greeting.trim_end()));
+        }
+
+        if settings.security == Security::StartTls {
+            client.command("STARTTLS").await?;
+            let plain = client.stream.into_inner();
+            client.stream = BufReader::new(wrap_tls(&settings.host, plain).await?);
+        }
+        Ok(client)
+    }
+
+    async fn read_line(&mut self) -> Result<String, String> {
+        let mut line = Vec::new();
+        let n = timeout(IO_TIMEOUT, self.stream.read_until(b'\n', &mut line))
+            .await
+            .map_err(|_| "imap: timed out".to_string())?
+            .map_err(|e| format!("imap read: {e}"))?;
+        if n == 0 {
+            return Err("imap: connection closed".into());
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    /// Reads one response, pulling in any literals it announces.
+    async fn read_response(&mut self) -> Result<Response, String> {
+        let mut response = Response::default();
+        loop {
+            let line = self.read_line().await?;
+            let Some(len) = literal_len(&line) else {
+                response.text.push_str(line.trim_end_matches(['\r', '\n']));
+                return Ok(response);
+            };
+            response.text.push_str(line.trim_end_matches(['\r', '\n']));
+            if len > MAX_LITERAL {
+                return Err(format!("imap: {len}-byte literal is larger than the {MAX_LITERAL}-byte limit"));
+            }
+            let mut literal = vec![0u8; len];
+            timeout(IO_TIMEOUT, self.stream.read_exact(&mut literal))
+                .await
+                .map_err(|_| "imap: timed out".to_string())?
+                .map_err(|e| format!("imap read: {e}"))?;
+            response.literals.push(literal);
+        }
+    }
+
+    /// Sends a tagged command and returns its untagged responses, failing
+    /// unless the server completes it with `OK`.
+    async fn command(&mut self, command: &str) -> Result<Vec<Response>, String> {
+        self.tag += 1;
+        let tag = format!("m{}", self.tag);
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{tag} {command}\r\n").as_bytes())
+            .await
+            .map_err(|e| format!("imap write: {e}"))?;
+        stream.flush().await.map_err(|e| format!("imap write: {e}"))?;
+
+        let verb = command.split(' ').next().unwrap_or(command);
+        let mut untagged = Vec::new();
+        loop {
+            let response = self.read_response().await?;
+            let Some(status) = response.text.strip_prefix(&format!("{tag} ")) else {
+                untagged.push(response);
+                continue;
+            };
+            if status.starts_with("OK") {
+                return Ok(untagged);
+            }
+            return Err(format!("imap {verb}: {status}"));
+        }
+    }
+
+    pub(crate) async fn login(&mut self, user: &str, pass: &str) -> Result<(), String> {
+        self.command(&format!("LOGIN {} {}", quote(user), quote(pass)))
+            .await
+            .map(|_| ())
+    }
+
+    /// Opens `mailbox` read-only.
+    pub(crate) async fn examine(&mut self, mailbox: &str) -> Result<Mailbox, String> {
+        self.open("EXAMINE", mailbox).await
+    }
+
+    /// Opens `mailbox` so flags can be changed and messages moved.
+    pub(crate) async fn select(&mut self, mailbox: &str) -> Result<Mailbox, String> {
+        self.open("SELECT", mailbox).await
+    }
+
+    async fn open(&mut self, verb: &str, mailbox: &str) -> Result<Mailbox, String> {
+        let responses = self.command(&format!("{verb} {}", quote(mailbox))).await?;
+        let code = |name: &str| {
+            responses.iter().find_map(|r| {
+                let rest = r.text.split(&format!("[{name} ")).nth(1)?;
+                rest.split(']').next()?.parse::<u32>().ok()
+            })
+        };
+        Ok(Mailbox {
+            uid_validity: code("UIDVALIDITY").ok_or_else(|| format!("imap {verb}: no UIDVALIDITY"))?,
+            uid_next: code("UIDNEXT").ok_or_else(|| format!("imap {verb}: no UIDNEXT"))?,
+        })
+    }
+
+    /// UIDs of messages at or after `from`, in ascending order.
+    pub(crate) async fn uids_from(&mut self, from: u32) -> Result<Vec<u32>, String> {
+        let responses = self.command(&format!("UID SEARCH UID {from}:*")).await?;
+        let mut uids: Vec<u32> = responses
+            .iter()
+            .filter_map(|r| r.text.strip_prefix("* SEARCH"))
+            .flat_map(|rest| rest.split_whitespace().filter_map(|u| u.parse().ok()))
+            // `n:*` matches the last message even when its UID is below n
+            .filter(|&uid| uid >= from)
+            .collect();
+        uids.sort_unstable();
+        Ok(uids)
+    }
+
+    /// The raw RFC 5322 message. `BODY.PEEK` leaves its `\Seen` flag alone,
+    /// so the mailbox owner still sees bounces as unread.
+    pub(crate) async fn fetch(&mut self, uid: u32) -> Result<Vec<u8>, String> {
+        let responses = self.command(&format!("UID FETCH {uid} (BODY.PEEK[])")).await?;
+        responses
+            .into_iter()
+            .find(|r| r.text.contains("FETCH"))
+            .and_then(|r| r.literals.into_iter().next())
+            .ok_or_else(|| format!("imap FETCH: no body for UID {uid}"))
+    }
+
+    pub(crate) async fn mark_seen(&mut self, uid: u32) -> Result<(), String> {
+        self.command(&format!("UID STORE {uid} +FLAGS.SILENT (\\Seen)"))
+            .await
+            .map(|_| ())
+    }
+
+    /// Moves a message with RFC 6851 `MOVE`, which Bridge supports.
+    pub(crate) async fn move_to(&mut self, uid: u32, mailbox: &str) -> Result<(), String> {
+        self.command(&format!("UID MOVE {uid} {}", quote(mailbox)))
+            .await
+            .map(|_| ())
+    }
+
+    pub(crate) async fn logout(mut self) {
+        let _ = self.command("LOGOUT").await;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Commands a stand-in received, without their tags.
+    pub(crate) type Log = std::sync::Arc<std::sync::Mutex<Vec<String>>>;
+
+    /// A scripted IMAP server on localhost holding `messages` as (UID, raw).
+    /// It answers LOGIN, SELECT/EXAMINE, UID SEARCH and UID FETCH, and
+    /// acknowledges everything else, for one connection at a time.
+    pub(crate) async fn stand_in(uid_validity: u32, messages: Vec<(u32, Vec<u8>)>) -> (Settings, Log) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let log = Log::default();
+        let received = log.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let (read, mut write) = socket.into_split();
+                let mut lines = BufReader::new(read).lines();
+                write.write_all(b"* OK stand-in ready\r\n").await.unwrap();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let (tag, command) = line.split_once(' ').unwrap();
+                    received.lock().unwrap().push(command.to_string());
+                    let upper = command.to_uppercase();
+                    let mut out = Vec::new();
+                    if upper.starts_with("EXAMINE") || upper.starts_with("SELECT") {
+                        let next = messages.iter().map(|(u, _)| u + 1).max().unwrap_or(1);
+                        out.extend(format!("* OK [UIDVALIDITY {uid_validity}] ok\r\n").bytes());
+                        out.extend(format!("* OK [UIDNEXT {next}] ok\r\n").bytes());
+                    } else if let Some(range) = upper.strip_prefix("UID SEARCH UID ") {
+                        let from: u32 = range.split(':').next().unwrap().parse().unwrap();
+                        let mut uids: Vec<String> = messages
+                            .iter()
+                            .filter(|(u, _)| *u >= from)
+                            .map(|(u, _)| u.to_string())
+                            .collect();
+                        if uids.is_empty() && let Some((last, _)) = messages.last() {
+                            uids.push(last.to_string());
+                        }
+                        out.extend(format!("* SEARCH {}\r\n", uids.join(" ")).bytes());
+                    } else if let Some(rest) = upper.strip_prefix("UID FETCH ") {
+                        let uid: u32 = rest.split(' ').next().unwrap().parse().unwrap();
+                        if let Some((_, raw)) = messages.iter().find(|(u, _)| *u == uid) {
+                            out.extend(format!("* 1 FETCH (UID {uid} BODY[] {{{}}}\r\n", raw.len()).bytes());
+                            out.extend(raw);
+                            out.extend(b")\r\n");
+                        }
+                    } else if upper.starts_with("LOGOUT") {
+                        out.extend(b"* BYE\r\n");
+                    }
+                    out.extend(format!("{tag} OK done\r\n").bytes());
+                    write.write_all(&out).await.unwrap();
+                }
+            }
+        });
+
+        let settings = Settings {
+            host: "127.0.0.1".into(),
+            port,
+            security: Security::None,
+            mailbox: "INBOX".into(),
+            poll_seconds: 1,
+            inbound_move_to: None,
+        };
+        (settings, log)
+    }
+
+    #[test]
+    fn test_literal_len_and_quote() {
+        assert_eq!(literal_len("* 1 FETCH (UID 5 BODY[] {120}\r\n"), Some(120));
+        assert_eq!(literal_len("* OK [UIDNEXT 4] {x}\r\n"), None);
+        assert_eq!(literal_len("m1 OK done\r\n"), None);
+        assert_eq!(quote(r#"pa"ss\word"#), r#""pa\"ss\\word""#);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_literal_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let line = format!("* OK ready\r\n* 1 FETCH (UID 4 BODY[] {{{}}}\r\n", MAX_LITERAL + 1);
+            socket.write_all(line.as_bytes()).await.unwrap();
+            let _ = socket.read(&mut [0; 64]).await;
+        });
+        let settings = Settings {
+            host: "127.0.0.1".into(),
+            port,
+            security: Security::None,
+            mailbox: "INBOX".into(),
+            poll_seconds: 1,
+            inbound_move_to: None,
+        };
+
+        let mut client = Client::connect(&settings).await.unwrap();
+        let e = client.fetch(4).await.unwrap_err();
+        assert!(e.contains("larger than"), "{e}");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_from_stand_in() {
+        let raw = b"Subject: hi\r\n\r\nline {3}\r\nbody\r\n".to_vec();
+        let (settings, log) = stand_in(7, vec![(3, b"Subject: old\r\n\r\n".to_vec()), (4, raw.clone())]).await;
+
+        let mut client = Client::connect(&settings).await.unwrap();
+        client.login("user", "pass").await.unwrap();
+        assert_eq!(
+            client.examine("INBOX").await.unwrap(),
+            Mailbox {
+                uid_validity: 7,
+                uid_next: 5
+            }
+        );
+        assert_eq!(client.uids_from(4).await.unwrap(), vec![4]);
+        assert!(client.uids_from(5).await.unwrap().is_empty());
+        assert_eq!(client.fetch(4).await.unwrap(), raw);
+        client.mark_seen(4).await.unwrap();
+        client.move_to(4, "Support").await.unwrap();
+        client.logout().await;
+
+        let log = log.lock().unwrap();
+        assert_eq!(log[0], r#"LOGIN "user" "pass""#);
+        assert!(log.contains(&r"UID STORE 4 +FLAGS.SILENT (\Seen)".to_string()), "{log:?}");
+        assert!(log.contains(&r#"UID MOVE 4 "Support""#.to_string()), "{log:?}");
+    }
+}
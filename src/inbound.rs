@@ -0,0 +1,704 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use mail_parser::{Address, HeaderValue, Message, MessageParser, MimeHeaders};
+use openssl::{base64, rand::rand_bytes};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::{ApiError, AppState, api_error, imap, now_millis, webhooks};
+
+/// Config key holding the inbound poller's [`imap::checkpoint`].
+const CHECKPOINT_KEY: &str = "inbound_checkpoint";
+/// Polls a message is retried on before it is skipped.
+const MAX_ATTEMPTS: i64 = 10;
+/// Log entries are kept this long.
+const LOG_RETENTION_MS: i64 = 30 * 24 * 3_600_000;
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RouteRequest {
+    /// Local part to match, with `*` as a wildcard: `support`, `reply+*`, `*`.
+    pattern: String,
+    url: String,
+    /// Signing secret; generated when absent.
+    secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RouteEntry {
+    id: String,
+    pattern: String,
+    url: String,
+    /// Only returned when the route is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct LogEntry {
+    message_id: Option<String>,
+    recipient: String,
+    from: Option<String>,
+    subject: Option<String>,
+    status: String,
+    attempts: i64,
+    response_status: Option<u16>,
+    last_error: Option<String>,
+    received_at: i64,
+    delivered_at: Option<i64>,
+}
+
+/// A route as the poller needs it.
+#[derive(Debug, Clone)]
+struct Route {
+    id: String,
+    domain: String,
+    pattern: String,
+    url: String,
+    secret: String,
+}
+
+// ── Database ────────────────────────────────────────────────────────────────
+
+pub(crate) fn init_db(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS inbound_routes (
+            id TEXT PRIMARY KEY,
+            domain TEXT NOT NULL,
+            pattern TEXT NOT NULL,
+            url TEXT NOT NULL,
+            secret TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            UNIQUE(domain, pattern)
+        );
+        CREATE TABLE IF NOT EXISTS inbound_log (
+            uid_validity INTEGER NOT NULL,
+            uid INTEGER NOT NULL,
+            route_id TEXT NOT NULL,
+            message_id TEXT,
+            recipient TEXT NOT NULL,
+            from_addr TEXT,
+            subject TEXT,
+            status TEXT NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            response_status INTEGER,
+            last_error TEXT,
+            received_at INTEGER NOT NULL,
+            delivered_at INTEGER,
+            PRIMARY KEY (uid_validity, uid)
+        );
+        CREATE INDEX IF NOT EXISTS idx_inbound_log_route ON inbound_log(route_id, received_at);",
+    )
+    .expect("failed to initialize inbound tables");
+}
+
+/// Removes a deleted domain's inbound routes and their log.
+pub(crate) fn delete_domain(conn: &Connection, domain: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM inbound_log WHERE route_id IN (SELECT id FROM inbound_routes WHERE domain = ?1)",
+        [domain],
+    )?;
+    conn.execute("DELETE FROM inbound_routes WHERE domain = ?1", [domain])?;
+    Ok(())
+}
+
+fn load_routes(conn: &Connection) -> rusqlite::Result<Vec<Route>> {
+    conn.prepare_cached("SELECT id, domain, pattern, url, secret FROM inbound_routes")?
+        .query_map([], |r| {
+            Ok(Route {
+                id: r.get(0)?,
+                domain: r.get(1)?,
+                pattern: r.get(2)?,
+                url: r.get(3)?,
+                secret: r.get(4)?,
+            })
+        })?
+        .collect()
+}
+
+// ── Routing ─────────────────────────────────────────────────────────────────
+
+/// Glob match where `*` stands for any run of characters.
+fn pattern_matches(pattern: &str, local: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = local.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No wildcard: the whole local part must match
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Every address the message was delivered to, most specific first:
+/// `Delivered-To`/`X-Original-To` (which Bcc recipients only appear in),
+/// then `To` and `Cc`. Lowercased, without duplicates.
+fn recipients(message: &Message) -> Vec<String> {
+    let mut found: Vec<String> = message
+        .headers_raw()
+        .filter(|(name, _)| {
+            name.eq_ignore_ascii_case("Delivered-To") || name.eq_ignore_ascii_case("X-Original-To")
+        })
+        .map(|(_, value)| value.trim().trim_matches(['<', '>']).to_string())
+        .collect();
+    for list in [message.to(), message.cc()].into_iter().flatten() {
+        found.extend(list.iter().filter_map(|a| a.address()).map(str::to_string));
+    }
+
+    let mut unique = Vec::new();
+    for addr in found {
+        let addr = addr.to_lowercase();
+        if !addr.is_empty() && !unique.contains(&addr) {
+            unique.push(addr);
+        }
+    }
+    unique
+}
+
+/// The route for the first recipient any route matches. An exact pattern
+/// beats a wildcard; among wildcards the longest pattern wins.
+fn route_for<'r>(routes: &'r [Route], recipients: &[String]) -> Option<(&'r Route, String)> {
+    recipients.iter().find_map(|addr| {
+        let (local, domain) = addr.rsplit_once('@')?;
+        routes
+            .iter()
+            .filter(|r| r.domain == domain && pattern_matches(&r.pattern, local))
+            .max_by_key(|r| (!r.pattern.contains('*'), r.pattern.len()))
+            .map(|r| (r, addr.clone()))
+    })
+}
+
+// ── Payload ─────────────────────────────────────────────────────────────────
+
+fn addresses(list: Option<&Address>) -> Vec<serde_json::Value> {
+    list.map(|l| {
+        l.iter()
+            .map(|a| serde_json::json!({ "name": a.name(), "address": a.address() }))
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Message IDs from `In-Reply-To` or `References`, without angle brackets.
+fn ids(value: &HeaderValue) -> Vec<String> {
+    match value {
+        HeaderValue::Text(id) => vec![id.to_string()],
+        HeaderValue::TextList(ids) => ids.iter().map(|id| id.to_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The JSON body POSTed to a route.
+fn payload(message: &Message, route: &Route, recipient: &str) -> serde_json::Value {
+    let headers: Vec<serde_json::Value> = message
+        .headers_raw()
+        .map(|(name, value)| serde_json::json!({ "name": name, "value": value.trim() }))
+        .collect();
+    let attachments: Vec<serde_json::Value> = message
+        .attachments()
+        .map(|part| {
+            let content_type = part.content_type().map(|ct| match ct.subtype() {
+                Some(sub) => format!("{}/{sub}", ct.ctype()),
+                None => ct.ctype().to_string(),
+            });
+            serde_json::json!({
+                "filename": part.attachment_name(),
+                "content_type": content_type,
+                "size": part.contents().len(),
+                "content": base64::encode_block(part.contents()),
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "route_id": route.id,
+        "domain": route.domain,
+        "recipient": recipient,
+        "from": addresses(message.from()).into_iter().next(),
+        "to": addresses(message.to()),
+        "cc": addresses(message.cc()),
+        "subject": message.subject(),
+        "message_id": message.message_id(),
+        "in_reply_to": ids(message.in_reply_to()),
+        "references": ids(message.references()),
+        "date": message.date().map(|d| d.to_rfc3339()),
+        "headers": headers,
+        "text": message.body_text(0),
+        "html": message.body_html(0),
+        "attachments": attachments,
+    })
+}
+
+// ── Polling ─────────────────────────────────────────────────────────────────
+
+/// Records an attempt and returns how many there have been.
+fn log_attempt(
+    conn: &Connection,
+    uid_validity: u32,
+    uid: u32,
+    route: &Route,
+    recipient: &str,
+    message: &Message,
+    result: &Result<u16, (Option<u16>, String)>,
+) -> i64 {
+    let now = now_millis();
+    let from = message.from().and_then(|f| f.first()).and_then(|a| a.address());
+    let (status, code, error) = match result {
+        Ok(code) => ("delivered", Some(*code), None),
+        Err((code, e)) => ("pending", *code, Some(e.as_str())),
+    };
+    let logged = conn.query_row(
+        "INSERT INTO inbound_log (uid_validity, uid, route_id, message_id, recipient, from_addr, subject,
+                                  status, attempts, response_status, last_error, received_at, delivered_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, 1, ?9, ?10, ?11, CASE WHEN ?8 = 'delivered' THEN ?11 END)
+         ON CONFLICT(uid_validity, uid) DO UPDATE SET
+            route_id = ?3, status = ?8, attempts = attempts + 1, response_status = ?9, last_error = ?10,
+            delivered_at = CASE WHEN ?8 = 'delivered' THEN ?11 END
+         RETURNING attempts",
+        rusqlite::params![
+            uid_validity,
+            uid,
+            &route.id,
+            message.message_id(),
+            recipient,
+            from,
+            message.subject(),
+            status,
+            code,
+            error,
+            now
+        ],
+        |r| r.get(0),
+    );
+    logged.unwrap_or_else(|e| {
+        error!(uid, "failed to log inbound delivery: {e}");
+        1
+    })
+}
+
+fn give_up(conn: &Connection, uid_validity: u32, uid: u32) {
+    let _ = conn.execute(
+        "UPDATE inbound_log SET status = 'failed' WHERE uid_validity = ?1 AND uid = ?2",
+        [uid_validity, uid],
+    );
+}
+
+/// Forwards messages that arrived since the last poll to their routes. A
+/// message whose route fails stops the poll so it is retried, in order, on
+/// the next one; after [`MAX_ATTEMPTS`] it is skipped. Returns how many
+/// messages were delivered.
+async fn poll_once(
+    db: &Mutex<Connection>,
+    client: &reqwest::Client,
+    settings: &imap::Settings,
+    user: &str,
+    pass: &str,
+) -> Result<usize, String> {
+    let mut imap_client = imap::Client::connect(settings).await?;
+    imap_client.login(user, pass).await?;
+    let mailbox = imap_client.select(&settings.mailbox).await?;
+
+    let (start, routes) = {
+        let db = db.lock().await;
+        let routes = load_routes(&db).map_err(|e| format!("inbound routes: {e}"))?;
+        match imap::checkpoint(&db, CHECKPOINT_KEY) {
+            // Without routes there is nothing to deliver; keep up with the
+            // mailbox so adding one later does not replay old mail
+            Some((validity, uid)) if validity == mailbox.uid_validity && !routes.is_empty() => {
+                (uid + 1, routes)
+            }
+            // First run, or the mailbox was rebuilt: only look at new mail
+            _ => {
+                imap::save_checkpoint(&db, CHECKPOINT_KEY, mailbox.uid_validity, mailbox.uid_next.saturating_sub(1));
+                imap_client.logout().await;
+                return Ok(0);
+            }
+        }
+    };
+
+    let mut delivered = 0;
+    for uid in imap_client.uids_from(start).await? {
+        let raw = imap_client.fetch(uid).await?;
+        let routed = MessageParser::default().parse(&raw).and_then(|message| {
+            // Delivery reports belong to bounce processing
+            let is_report = message.content_type().is_some_and(|ct| {
+                ct.ctype().eq_ignore_ascii_case("multipart")
+                    && ct.subtype().is_some_and(|s| s.eq_ignore_ascii_case("report"))
+            });
+            if is_report {
+                return None;
+            }
+            let (route, recipient) = route_for(&routes, &recipients(&message))?;
+            Some((message, route.clone(), recipient))
+        });
+
+        if let Some((message, route, recipient)) = routed {
+            let body = payload(&message, &route, &recipient).to_string();
+            let delivery_id = format!("{}:{uid}", mailbox.uid_validity);
+            let result =
+                webhooks::post_signed(client, &route.url, &route.secret, "inbound", &delivery_id, &body).await;
+
+            let attempts = {
+                let db = db.lock().await;
+                log_attempt(&db, mailbox.uid_validity, uid, &route, &recipient, &message, &result)
+            };
+            match result {
+                Ok(_) => {
+                    imap_client.mark_seen(uid).await?;
+                    if let Some(target) = &settings.inbound_move_to {
+                        imap_client.move_to(uid, target).await?;
+                    }
+                    delivered += 1;
+                    info!(uid, route = route.id, recipient, "inbound message delivered");
+                }
+                Err((_, e)) if attempts >= MAX_ATTEMPTS => {
+                    warn!(uid, route = route.id, url = route.url, "inbound delivery failed permanently: {e}");
+                    give_up(&*db.lock().await, mailbox.uid_validity, uid);
+                }
+                Err((_, e)) => {
+                    warn!(uid, route = route.id, url = route.url, attempts, "inbound delivery failed: {e}");
+                    break;
+                }
+            }
+        }
+        imap::save_checkpoint(&*db.lock().await, CHECKPOINT_KEY, mailbox.uid_validity, uid);
+    }
+    imap_client.logout().await;
+    Ok(delivered)
+}
+
+pub(crate) async fn worker(state: Arc<AppState>, settings: imap::Settings) {
+    let poll_interval = Duration::from_secs(settings.poll_seconds);
+    let client = reqwest::Client::new();
+
+    loop {
+        tokio::time::sleep(poll_interval).await;
+
+        let (user, pass) = {
+            let creds = state.smtp_creds.read().await;
+            (creds.user.clone(), creds.pass.clone())
+        };
+        if user.is_empty() {
+            continue;
+        }
+        if let Err(e) = poll_once(&state.db, &client, &settings, &user, &pass).await {
+            warn!("inbound poll failed: {e}");
+        }
+
+        let db = state.db.lock().await;
+        let _ = db.execute(
+            "DELETE FROM inbound_log WHERE status != 'pending' AND received_at < ?1",
+            [now_millis() - LOG_RETENTION_MS],
+        );
+    }
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+pub(crate) async fn create_route_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+    Json(payload): Json<RouteRequest>,
+) -> Result<(StatusCode, Json<RouteEntry>), ApiError> {
+    let domain = domain.to_lowercase();
+    let pattern = payload.pattern.trim().to_lowercase();
+    if pattern.is_empty() || pattern.contains(['@', ' ']) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "pattern must be a local part such as 'support' or 'reply+*'",
+        ));
+    }
+    let url = reqwest::Url::parse(&payload.url)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .ok_or_else(|| api_error(StatusCode::BAD_REQUEST, "url must be an http(s) URL"))?;
+
+    let secret = match payload.secret.filter(|s| !s.is_empty()) {
+        Some(s) => s,
+        None => {
+            let mut raw = [0u8; 32];
+            rand_bytes(&mut raw).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            webhooks::hex(&raw)
+        }
+    };
+
+    let db = state.db.lock().await;
+    db.query_row("SELECT 1 FROM domains WHERE domain = ?1", [&domain], |_| Ok(()))
+        .optional()
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "domain not found"))?;
+
+    let entry = RouteEntry {
+        id: uuid::Uuid::new_v4().to_string(),
+        pattern,
+        url: url.to_string(),
+        secret: Some(secret),
+        created_at: now_millis(),
+    };
+    db.execute(
+        "INSERT INTO inbound_routes (id, domain, pattern, url, secret, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![&entry.id, &domain, &entry.pattern, &entry.url, &entry.secret, entry.created_at],
+    )
+    .map_err(|e| match e {
+        rusqlite::Error::SqliteFailure(f, _) if f.code == rusqlite::ErrorCode::ConstraintViolation => {
+            api_error(StatusCode::CONFLICT, "a route with this pattern already exists")
+        }
+        e => api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")),
+    })?;
+
+    info!(domain, id = entry.id, pattern = entry.pattern, url = entry.url, "inbound route created");
+    Ok((StatusCode::CREATED, Json(entry)))
+}
+
+pub(crate) async fn list_routes_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Json<Vec<RouteEntry>> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let mut stmt = db
+        .prepare("SELECT id, pattern, url, created_at FROM inbound_routes WHERE domain = ?1 ORDER BY created_at")
+        .unwrap();
+    let entries: Vec<RouteEntry> = stmt
+        .query_map([&domain], |row| {
+            Ok(RouteEntry {
+                id: row.get(0)?,
+                pattern: row.get(1)?,
+                url: row.get(2)?,
+                secret: None,
+                created_at: row.get(3)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Json(entries)
+}
+
+pub(crate) async fn delete_route_handler(
+    State(state): State<Arc<AppState>>,
+    Path((domain, id)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let deleted = db
+        .execute("DELETE FROM inbound_routes WHERE domain = ?1 AND id = ?2", [&domain, &id])
+        .unwrap_or(0);
+
+    if deleted == 0 {
+        Err(api_error(StatusCode::NOT_FOUND, "inbound route not found"))
+    } else {
+        let _ = db.execute("DELETE FROM inbound_log WHERE route_id = ?1", [&id]);
+        info!(domain, id, "inbound route deleted");
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+/// Messages forwarded to one route, newest first.
+pub(crate) async fn list_log_handler(
+    State(state): State<Arc<AppState>>,
+    Path((domain, id)): Path<(String, String)>,
+) -> Result<Json<Vec<LogEntry>>, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    db.query_row(
+        "SELECT 1 FROM inbound_routes WHERE domain = ?1 AND id = ?2",
+        [&domain, &id],
+        |_| Ok(()),
+    )
+    .optional()
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?
+    .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "inbound route not found"))?;
+
+    let mut stmt = db
+        .prepare(
+            "SELECT message_id, recipient, from_addr, subject, status, attempts, response_status,
+                    last_error, received_at, delivered_at
+             FROM inbound_log WHERE route_id = ?1
+             ORDER BY received_at DESC LIMIT 100",
+        )
+        .unwrap();
+    let entries: Vec<LogEntry> = stmt
+        .query_map([&id], |row| {
+            Ok(LogEntry {
+                message_id: row.get(0)?,
+                recipient: row.get(1)?,
+                from: row.get(2)?,
+                subject: row.get(3)?,
+                status: row.get(4)?,
+                attempts: row.get(5)?,
+                response_status: row.get(6)?,
+                last_error: row.get(7)?,
+                received_at: row.get(8)?,
+                delivered_at: row.get(9)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Ok(Json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, http::HeaderMap, routing::post};
+
+    fn route(id: &str, pattern: &str) -> Route {
+        Route {
+            id: id.into(),
+            domain: "example.com".into(),
+            pattern: pattern.into(),
+            url: String::new(),
+            secret: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_routing() {
+        assert!(pattern_matches("support", "support"));
+        assert!(!pattern_matches("support", "support2"));
+        assert!(pattern_matches("reply+*", "reply+t42"));
+        assert!(pattern_matches("*-bot", "ci-bot"));
+        assert!(pattern_matches("a*b*c", "axxbyyc"));
+        assert!(!pattern_matches("a*b*c", "axxcyyb"));
+        assert!(pattern_matches("*", "anyone"));
+
+        let routes = [route("any", "*"), route("reply", "reply+*"), route("support", "support")];
+        let pick = |to: &[&str]| {
+            let to: Vec<String> = to.iter().map(|s| s.to_string()).collect();
+            route_for(&routes, &to).map(|(r, addr)| (r.id.clone(), addr))
+        };
+        assert_eq!(pick(&["support@example.com"]), Some(("support".into(), "support@example.com".into())));
+        assert_eq!(pick(&["reply+7@example.com"]), Some(("reply".into(), "reply+7@example.com".into())));
+        assert_eq!(pick(&["ada@other.org", "sales@example.com"]), Some(("any".into(), "sales@example.com".into())));
+        assert_eq!(pick(&["ada@other.org"]), None);
+
+        let raw = b"Delivered-To: Support@example.com\r\n\
+            From: Ada <ada@example.org>\r\n\
+            To: team@example.com, Support <support@example.com>\r\n\
+            Cc: bob@example.org\r\n\
+            \r\n\
+            hi\r\n";
+        let message = MessageParser::default().parse(raw.as_slice()).unwrap();
+        assert_eq!(
+            recipients(&message),
+            vec!["support@example.com", "team@example.com", "bob@example.org"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poll_delivers_and_retries() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<(HeaderMap, String)>();
+        let up = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let app = Router::new().route(
+            "/tickets",
+            post({
+                let up = up.clone();
+                move |headers: HeaderMap, body: String| {
+                    let tx = tx.clone();
+                    let up = up.load(std::sync::atomic::Ordering::SeqCst);
+                    async move {
+                        if !up {
+                            return StatusCode::SERVICE_UNAVAILABLE;
+                        }
+                        tx.send((headers, body)).unwrap();
+                        StatusCode::OK
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let reply = b"From: Ada <ada@example.org>\r\n\
+            To: support@example.com\r\n\
+            Subject: Re: Ticket 42\r\n\
+            Message-ID: <r1@example.org>\r\n\
+            In-Reply-To: <t42@example.com>\r\n\
+            MIME-Version: 1.0\r\n\
+            Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            Still broken.\r\n\
+            --b\r\n\
+            Content-Type: text/csv\r\n\
+            Content-Disposition: attachment; filename=\"log.csv\"\r\n\
+            \r\n\
+            a,b\r\n\
+            --b--\r\n"
+            .to_vec();
+        let unrouted = b"From: x@example.org\r\nTo: nobody@other.org\r\n\r\nhi\r\n".to_vec();
+        let (mut settings, log) = imap::tests::stand_in(5, vec![(1, unrouted), (2, reply)]).await;
+        settings.inbound_move_to = Some("Tickets".into());
+
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+        conn.execute(
+            "INSERT INTO inbound_routes VALUES ('r1', 'example.com', 'support', ?1, 'secret', 0)",
+            [format!("http://{addr}/tickets")],
+        )
+        .unwrap();
+        imap::save_checkpoint(&conn, CHECKPOINT_KEY, 5, 0);
+        let db = Mutex::new(conn);
+        let client = reqwest::Client::new();
+
+        // The endpoint is down: the unrouted message is passed, the reply waits
+        assert_eq!(poll_once(&db, &client, &settings, "u", "p").await.unwrap(), 0);
+        assert_eq!(imap::checkpoint(&*db.lock().await, CHECKPOINT_KEY), Some((5, 1)));
+
+        up.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(poll_once(&db, &client, &settings, "u", "p").await.unwrap(), 1);
+        assert_eq!(imap::checkpoint(&*db.lock().await, CHECKPOINT_KEY), Some((5, 2)));
+
+        let (headers, body) = rx.recv().await.unwrap();
+        assert_eq!(headers["x-mayl-event"], "inbound");
+        assert_eq!(headers["x-mayl-delivery"], "5:2");
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["route_id"], "r1");
+        assert_eq!(json["recipient"], "support@example.com");
+        assert_eq!(json["from"]["address"], "ada@example.org");
+        assert_eq!(json["subject"], "Re: Ticket 42");
+        assert_eq!(json["in_reply_to"][0], "t42@example.com");
+        assert_eq!(json["text"].as_str().unwrap().trim(), "Still broken.");
+        assert_eq!(json["attachments"][0]["filename"], "log.csv");
+        assert_eq!(json["attachments"][0]["content_type"], "text/csv");
+
+        let commands = log.lock().unwrap().clone();
+        assert!(commands.contains(&r"UID STORE 2 +FLAGS.SILENT (\Seen)".to_string()), "{commands:?}");
+        assert!(commands.contains(&r#"UID MOVE 2 "Tickets""#.to_string()), "{commands:?}");
+        assert!(!commands.iter().any(|c| c.starts_with("UID STORE 1")), "{commands:?}");
+
+        let (status, attempts): (String, i64) = db
+            .lock()
+            .await
+            .query_row("SELECT status, attempts FROM inbound_log WHERE uid = 2", [], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((status.as_str(), attempts), ("delivered", 2));
+    }
+}
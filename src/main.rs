@@ -1,17 +1,20 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use axum::{
     Json, Router,
-    extract::{Path, Query, State},
+    extract::{DefaultBodyLimit, Path, Query, State},
     http::{HeaderMap, StatusCode},
-    routing::{delete, get, post},
+    routing::{delete, get, patch, post, put},
 };
 use lettre::{
-    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
-    message::{MultiPart, SinglePart, header::ContentType},
-    transport::smtp::{
-        authentication::Credentials,
-        client::{Tls, TlsParameters},
+    AsyncTransport,
+    message::{
+        MultiPart, SinglePart,
+        header::{ContentType, HeaderName, HeaderValue},
     },
 };
 use rusqlite::Connection;
@@ -20,54 +23,120 @@ use tokio::sync::{Mutex, RwLock};
 use maud::{DOCTYPE, html};
 use tracing::{error, info, warn};
 
-type QueueRow = (String, String, String, String, String, Option<String>, bool);
-
-// ── Config ──────────────────────────────────────────────────────────────────
-
-fn env_or(key: &str, default: &str) -> String {
-    std::env::var(key).unwrap_or_else(|_| default.to_string())
+mod bounces;
+mod calendar;
+mod cli;
+mod config;
+mod dkim;
+mod dns;
+mod dns_report;
+mod events;
+mod html;
+mod imap;
+mod inbound;
+mod markdown;
+mod mime;
+mod pgp;
+mod queue;
+mod raw;
+mod recipients;
+mod smime;
+mod submission;
+mod suppressions;
+mod templates;
+mod unsubscribe;
+mod upstreams;
+mod verification;
+mod webhooks;
+
+/// A row claimed from `email_queue` by the queue worker.
+struct QueuedEmail {
+    id: String,
+    from: String,
+    to_json: String,
+    content: EmailContent,
+    save: bool,
 }
 
-fn env_parse<T: std::str::FromStr>(key: &str, default: T) -> T {
-    std::env::var(key)
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(default)
-}
+// ── Config ──────────────────────────────────────────────────────────────────
 
 #[derive(Debug, Clone)]
 struct Config {
     smtp_host: String,
     smtp_port: u16,
+    smtp_user: String,
+    smtp_pass: String,
     server_host: String,
     server_port: u16,
     queue_poll_seconds: u64,
     archive_max_rows: u64,
     archive_cull_interval_seconds: u64,
     db_path: String,
-    seed_domains: Vec<String>,
+    seed_domains: Vec<config::DomainSeed>,
+    markdown_layout: String,
+    public_url: Option<String>,
+    recipient_policy: recipients::Policy,
+    dns_resolver: std::net::SocketAddr,
+    require_domain_verification: bool,
+    relay: dns_report::Relay,
+    imap: Option<imap::Settings>,
+    submission: Option<submission::Settings>,
+    failover: upstreams::Failover,
 }
 
 impl Config {
-    fn load() -> Self {
-        let domains_str = env_or("MAYL_DOMAINS", "");
-        let seed_domains = if domains_str.is_empty() {
-            vec![]
-        } else {
-            domains_str.split(',').map(|s| s.trim().to_string()).collect()
+    /// Loads settings from the environment and the `MAYL_CONFIG` file, or
+    /// returns every problem found.
+    fn load() -> Result<Self, Vec<String>> {
+        Self::from_source(&config::Source::load(None))
+    }
+
+    /// Every setting at its default, whatever the environment holds.
+    #[cfg(test)]
+    fn defaults() -> Self {
+        Self::from_source(&config::Source::empty()).unwrap()
+    }
+
+    fn from_source(source: &config::Source) -> Result<Self, Vec<String>> {
+        let markdown_layout = match source.opt("MAYL_MARKDOWN_LAYOUT") {
+            Some(path) => std::fs::read_to_string(&path).unwrap_or_else(|e| {
+                source.invalid("MAYL_MARKDOWN_LAYOUT", format!("failed to read '{path}': {e}"));
+                String::new()
+            }),
+            None => markdown::DEFAULT_LAYOUT.to_string(),
         };
 
-        Self {
-            smtp_host: env_or("MAYL_SMTP_HOST", "localhost"),
-            smtp_port: env_parse("MAYL_SMTP_PORT", 1025),
-            server_host: env_or("MAYL_SERVER_HOST", "0.0.0.0"),
-            server_port: env_parse("MAYL_SERVER_PORT", 8080),
-            queue_poll_seconds: env_parse("MAYL_QUEUE_POLL_SECONDS", 5),
-            archive_max_rows: env_parse("MAYL_ARCHIVE_MAX_ROWS", 100_000),
-            archive_cull_interval_seconds: env_parse("MAYL_ARCHIVE_CULL_INTERVAL_SECONDS", 600),
-            db_path: env_or("MAYL_DB_PATH", "mayl.db"),
-            seed_domains,
-        }
+        let smtp_host = source.or("MAYL_SMTP_HOST", "localhost");
+        let server_host = source.or("MAYL_SERVER_HOST", "0.0.0.0");
+
+        let config = Self {
+            imap: imap::Settings::load(source, &smtp_host),
+            smtp_host,
+            smtp_port: source.parse("MAYL_SMTP_PORT", 1025),
+            smtp_user: source.or("MAYL_SMTP_USER", ""),
+            smtp_pass: source.or("MAYL_SMTP_PASS", ""),
+            submission: submission::Settings::load(source, &server_host),
+            server_host,
+            server_port: source.parse("MAYL_SERVER_PORT", 8080),
+            queue_poll_seconds: source.parse("MAYL_QUEUE_POLL_SECONDS", 5),
+            archive_max_rows: source.parse("MAYL_ARCHIVE_MAX_ROWS", 100_000),
+            archive_cull_interval_seconds: source.parse("MAYL_ARCHIVE_CULL_INTERVAL_SECONDS", 600),
+            db_path: source.or("MAYL_DB_PATH", "mayl.db"),
+            seed_domains: source.domains(),
+            markdown_layout,
+            public_url: source
+                .opt("MAYL_PUBLIC_URL")
+                .map(|u| u.trim_end_matches('/').to_string())
+                .filter(|u| !u.is_empty()),
+            recipient_policy: recipients::Policy::load(source),
+            dns_resolver: dns::configured_resolver(source),
+            require_domain_verification: source.parse("MAYL_REQUIRE_DOMAIN_VERIFICATION", false),
+            relay: dns_report::Relay::load(source),
+            failover: upstreams::Failover::load(source),
+        };
+
+        let errors = source.errors();
+        if errors.is_empty() { Ok(config) } else { Err(errors) }
     }
 }
 
@@ -77,26 +146,124 @@ impl Config {
 struct EmailRequest {
     from: String,
     to: Vec<String>,
+    subject: Option<String>,
+    body: Option<String>,
+    html: Option<String>,
+    markdown: Option<String>,
+    calendar: Option<calendar::CalendarInput>,
+    template: Option<String>,
+    template_version: Option<i64>,
+    #[serde(default)]
+    data: serde_json::Value,
+    #[serde(default)]
+    pgp: pgp::PgpOptions,
+    smime: Option<bool>,
+    list: Option<String>,
+}
+
+/// The rendered subject and parts of a message, as stored in the queue.
+#[derive(Debug, Default, Clone)]
+struct EmailContent {
     subject: String,
     body: String,
     html: Option<String>,
+    calendar: Option<String>,
+    pgp: pgp::PgpOptions,
+    smime: bool,
+    unsubscribe_url: Option<String>,
+    /// A complete message submitted over SMTP, relayed as-is. The fields
+    /// above then only describe it for the archive.
+    raw: Option<Vec<u8>>,
 }
 
 #[derive(Debug, Deserialize)]
 struct SendQuery {
     sync: Option<bool>,
     save: Option<bool>,
+    /// Validate and render the message, then return it instead of sending.
+    dry_run: Option<bool>,
 }
 
 #[derive(Debug, Serialize)]
 struct QueueResponse {
     id: String,
+    /// Every message id when a list send was split per recipient.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ids: Vec<String>,
     status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    calendar_uid: Option<String>,
+    /// Recipients dropped because they are on the domain's suppression list.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    suppressed: Vec<String>,
+    /// Recipients dropped because they unsubscribed from the list.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unsubscribed: Vec<String>,
+    /// The rendered messages of a dry run, one per id.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    messages: Vec<RenderedMessage>,
+}
+
+/// A message as it would have gone to the relay.
+#[derive(Debug, Serialize)]
+struct RenderedMessage {
+    id: String,
+    to: Vec<String>,
+    /// Top-level headers in order, values as encoded on the wire.
+    headers: Vec<RenderedHeader>,
+    raw: String,
 }
 
 #[derive(Debug, Serialize)]
+struct RenderedHeader {
+    name: String,
+    value: String,
+}
+
+impl RenderedMessage {
+    fn new(id: String, to: &[String], raw: &[u8]) -> Self {
+        let headers = mail_parser::MessageParser::default()
+            .parse_headers(raw)
+            .map(|message| {
+                message
+                    .headers()
+                    .iter()
+                    .map(|h| RenderedHeader {
+                        name: h.name.as_str().to_string(),
+                        value: String::from_utf8_lossy(&raw[h.offset_start as usize..h.offset_end as usize])
+                            .trim()
+                            .to_string(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            id,
+            to: to.to_vec(),
+            headers,
+            raw: String::from_utf8_lossy(raw).into_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
 struct ErrorResponse {
     error: String,
+    /// Recipients refused by validation, with the reason for each.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    rejected: Vec<recipients::Rejection>,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn api_error(status: StatusCode, error: impl Into<String>) -> ApiError {
+    (
+        status,
+        Json(ErrorResponse {
+            error: error.into(),
+            ..Default::default()
+        }),
+    )
 }
 
 #[derive(Debug, Serialize)]
@@ -104,23 +271,68 @@ struct HealthResponse {
     status: String,
     queue_size: i64,
     archive_size: i64,
+    retrying: i64,
+    /// Permanently failed messages kept in the queue for inspection.
+    failed: i64,
+    /// Circuit breaker of every SMTP relay, the configured one first.
+    upstreams: Vec<upstreams::BreakerStatus>,
 }
 
 #[derive(Debug, Deserialize)]
 struct DomainRequest {
     domain: String,
+    /// Hold the domain in `pending_verification` until a DNS challenge is
+    /// published; defaults to `MAYL_REQUIRE_DOMAIN_VERIFICATION`.
+    verify: Option<bool>,
+    #[serde(flatten)]
+    options: DomainOptionsRequest,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+struct DomainOptionsRequest {
+    inline_css: Option<bool>,
+    sanitize_html: Option<bool>,
+    smime_sign: Option<bool>,
+}
+
+/// Per-domain settings that shape outgoing messages.
+#[derive(Debug, Default, Clone, Serialize)]
+struct DomainOptions {
+    inline_css: bool,
+    sanitize_html: bool,
+    smime_sign: bool,
+}
+
+impl DomainOptions {
+    fn apply(&mut self, req: &DomainOptionsRequest) {
+        if let Some(v) = req.inline_css {
+            self.inline_css = v;
+        }
+        if let Some(v) = req.sanitize_html {
+            self.sanitize_html = v;
+        }
+        if let Some(v) = req.smime_sign {
+            self.smime_sign = v;
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 struct DomainResponse {
     domain: String,
     token: String,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verification: Option<verification::Challenge>,
 }
 
 #[derive(Debug, Serialize)]
 struct DomainListEntry {
     domain: String,
     created_at: i64,
+    status: String,
+    #[serde(flatten)]
+    options: DomainOptions,
 }
 
 #[derive(Debug, Deserialize)]
@@ -129,6 +341,23 @@ struct SmtpRequest {
     pass: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct SmtpSetQuery {
+    /// Log in to the relay with the new credentials before saving them.
+    verify: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ReadyResponse {
+    status: String,
+    /// The relays checked: every one some domain sends through.
+    upstreams: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// When the relay was last checked; results are reused for `READY_CACHE`.
+    checked_at: i64,
+}
+
 #[derive(Debug, Serialize)]
 struct SmtpStatusResponse {
     configured: bool,
@@ -146,6 +375,10 @@ struct AppState {
     db: Mutex<Connection>,
     config: Config,
     smtp_creds: RwLock<SmtpCredentials>,
+    events: events::Sender,
+    breakers: upstreams::Breakers,
+    /// The last `GET /ready` check, cleared when the credentials change.
+    readiness: Mutex<Option<(Instant, ReadyResponse)>>,
 }
 
 // ── Database ────────────────────────────────────────────────────────────────
@@ -163,7 +396,12 @@ fn init_db(conn: &Connection) {
             created_at INTEGER NOT NULL,
             attempts INTEGER NOT NULL DEFAULT 0,
             last_error TEXT,
-            save INTEGER NOT NULL DEFAULT 1
+            save INTEGER NOT NULL DEFAULT 1,
+            calendar TEXT,
+            pgp TEXT,
+            smime INTEGER NOT NULL DEFAULT 0,
+            unsubscribe_url TEXT,
+            raw BLOB
         );
         CREATE TABLE IF NOT EXISTS email_archive (
             id INTEGER PRIMARY KEY,
@@ -173,12 +411,18 @@ fn init_db(conn: &Connection) {
             subject TEXT NOT NULL,
             body TEXT NOT NULL,
             html TEXT,
-            sent_at INTEGER NOT NULL
+            sent_at INTEGER NOT NULL,
+            calendar TEXT
         );
         CREATE TABLE IF NOT EXISTS domains (
             domain TEXT PRIMARY KEY,
             token TEXT NOT NULL UNIQUE,
-            created_at INTEGER NOT NULL
+            created_at INTEGER NOT NULL,
+            inline_css INTEGER NOT NULL DEFAULT 0,
+            sanitize_html INTEGER NOT NULL DEFAULT 0,
+            smime_sign INTEGER NOT NULL DEFAULT 0,
+            status TEXT NOT NULL DEFAULT 'active',
+            challenge TEXT
         );
         CREATE TABLE IF NOT EXISTS config (
             key TEXT PRIMARY KEY,
@@ -189,10 +433,92 @@ fn init_db(conn: &Connection) {
         CREATE INDEX IF NOT EXISTS idx_domains_token ON domains(token);",
     )
     .expect("failed to initialize database");
+
+    add_column_if_missing(conn, "domains", "inline_css", "INTEGER NOT NULL DEFAULT 0");
+    add_column_if_missing(conn, "domains", "sanitize_html", "INTEGER NOT NULL DEFAULT 0");
+    add_column_if_missing(conn, "email_queue", "calendar", "TEXT");
+    add_column_if_missing(conn, "email_archive", "calendar", "TEXT");
+    add_column_if_missing(conn, "email_queue", "pgp", "TEXT");
+    add_column_if_missing(conn, "domains", "smime_sign", "INTEGER NOT NULL DEFAULT 0");
+    add_column_if_missing(conn, "email_queue", "smime", "INTEGER NOT NULL DEFAULT 0");
+    add_column_if_missing(conn, "email_queue", "unsubscribe_url", "TEXT");
+    add_column_if_missing(conn, "email_queue", "raw", "BLOB");
+    add_column_if_missing(conn, "domains", "status", "TEXT NOT NULL DEFAULT 'active'");
+    add_column_if_missing(conn, "domains", "challenge", "TEXT");
+
+    templates::init_db(conn);
+    pgp::init_db(conn);
+    dkim::init_db(conn);
+    smime::init_db(conn);
+    suppressions::init_db(conn);
+    unsubscribe::init_db(conn);
+    dns_report::init_db(conn);
+    webhooks::init_db(conn);
+    bounces::init_db(conn);
+    inbound::init_db(conn);
+    upstreams::init_db(conn);
+}
+
+/// Brings a database created by an older version up to the current schema.
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) {
+    let exists: bool = conn
+        .query_row(
+            &format!("SELECT COUNT(*) > 0 FROM pragma_table_info('{table}') WHERE name = ?1"),
+            [column],
+            |r| r.get(0),
+        )
+        .unwrap_or(false);
+
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))
+            .expect("failed to migrate database");
+        info!(table, column, "added column");
+    }
+}
+
+fn archive_email(
+    conn: &Connection,
+    queue_id: &str,
+    from: &str,
+    to_json: &str,
+    content: &EmailContent,
+) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO email_archive (queue_id, from_addr, to_addrs, subject, body, html, calendar, sent_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![
+            queue_id,
+            from,
+            to_json,
+            &content.subject,
+            &content.body,
+            &content.html,
+            &content.calendar,
+            now_millis()
+        ],
+    )
+}
+
+fn domain_options_from_row(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<DomainOptions> {
+    Ok(DomainOptions {
+        inline_css: row.get(offset)?,
+        sanitize_html: row.get(offset + 1)?,
+        smime_sign: row.get(offset + 2)?,
+    })
+}
+
+fn save_domain_options(conn: &Connection, domain: &str, opts: &DomainOptions) -> rusqlite::Result<usize> {
+    conn.execute(
+        "UPDATE domains SET inline_css = ?2, sanitize_html = ?3, smime_sign = ?4 WHERE domain = ?1",
+        rusqlite::params![domain, opts.inline_css, opts.sanitize_html, opts.smime_sign],
+    )
 }
 
-fn seed_domains(conn: &Connection, domains: &[String]) {
-    for domain in domains {
+/// Registers the configured domains that are missing. A pinned token or an
+/// option set in the config file is applied to existing domains too.
+fn seed_domains(conn: &Connection, domains: &[config::DomainSeed]) {
+    for seed in domains {
+        let domain = &seed.domain;
         let exists: bool = conn
             .query_row(
                 "SELECT COUNT(*) > 0 FROM domains WHERE domain = ?1",
@@ -202,14 +528,41 @@ fn seed_domains(conn: &Connection, domains: &[String]) {
             .unwrap_or(false);
 
         if !exists {
-            let token = uuid::Uuid::new_v4().to_string();
+            let token = seed.token.clone().unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
             let now = now_millis();
             match conn.execute(
                 "INSERT INTO domains (domain, token, created_at) VALUES (?1, ?2, ?3)",
                 rusqlite::params![domain, token, now],
             ) {
                 Ok(_) => info!(domain, token, "seeded domain"),
-                Err(e) => warn!(domain, "failed to seed domain: {e}"),
+                Err(e) => {
+                    warn!(domain, "failed to seed domain: {e}");
+                    continue;
+                }
+            }
+        } else if let Some(token) = &seed.token {
+            match conn.execute(
+                "UPDATE domains SET token = ?2 WHERE domain = ?1 AND token != ?2",
+                [domain, token],
+            ) {
+                Ok(0) => {}
+                Ok(_) => info!(domain, "restored configured token"),
+                Err(e) => warn!(domain, "failed to restore configured token: {e}"),
+            }
+        }
+
+        let o = &seed.options;
+        if o.inline_css.is_some() || o.sanitize_html.is_some() || o.smime_sign.is_some() {
+            let opts = conn.query_row(
+                "SELECT inline_css, sanitize_html, smime_sign FROM domains WHERE domain = ?1",
+                [domain],
+                |row| domain_options_from_row(row, 0),
+            );
+            if let Ok(mut opts) = opts {
+                opts.apply(o);
+                if let Err(e) = save_domain_options(conn, domain, &opts) {
+                    warn!(domain, "failed to apply configured options: {e}");
+                }
             }
         }
     }
@@ -224,6 +577,36 @@ fn extract_token(headers: &HeaderMap) -> Option<String> {
         .map(|v| v.strip_prefix("Bearer ").unwrap_or(v).to_string())
 }
 
+/// The domain `token` authorizes and its options, once it is checked that
+/// the domain is active and that `from` belongs to it.
+fn authorize_sender(conn: &Connection, token: &str, from: &str) -> Result<(String, DomainOptions), ApiError> {
+    let (domain, status, opts): (String, String, DomainOptions) = conn
+        .query_row(
+            "SELECT domain, status, inline_css, sanitize_html, smime_sign FROM domains WHERE token = ?1",
+            [token],
+            |r| Ok((r.get(0)?, r.get(1)?, domain_options_from_row(r, 2)?)),
+        )
+        .map_err(|_| api_error(StatusCode::UNAUTHORIZED, "invalid token"))?;
+
+    if status != verification::ACTIVE {
+        return Err(api_error(
+            StatusCode::FORBIDDEN,
+            format!("domain '{domain}' is {status}; publish its challenge and POST /domains/{domain}/verify"),
+        ));
+    }
+
+    let from_domain =
+        extract_domain_from_addr(from).ok_or_else(|| api_error(StatusCode::BAD_REQUEST, "invalid from address"))?;
+    if from_domain != domain {
+        return Err(api_error(
+            StatusCode::FORBIDDEN,
+            format!("token authorizes domain '{domain}', but from address uses '{from_domain}'"),
+        ));
+    }
+
+    Ok((domain, opts))
+}
+
 fn extract_domain_from_addr(from: &str) -> Option<String> {
     // Handle "Name <user@domain>" or plain "user@domain"
     let addr = if let Some(start) = from.find('<') {
@@ -235,56 +618,122 @@ fn extract_domain_from_addr(from: &str) -> Option<String> {
     addr.split('@').nth(1).map(|d| d.to_lowercase())
 }
 
+/// `<{id}@{from domain}>`, so bounces can be traced back to the queue id.
+fn message_id(id: &str, from: &str) -> String {
+    let domain = extract_domain_from_addr(from).unwrap_or_else(|| "localhost".into());
+    format!("<{id}@{domain}>")
+}
+
+/// The Message-ID a sent message carried: the submitter's own for raw
+/// messages, otherwise the one mayl generated.
+fn sent_message_id(id: &str, from: &str, content: &EmailContent) -> String {
+    content
+        .raw
+        .as_deref()
+        .and_then(|raw| mail_parser::MessageParser::default().parse_headers(raw))
+        .and_then(|headers| headers.message_id().map(|m| format!("<{m}>")))
+        .unwrap_or_else(|| message_id(id, from))
+}
+
+/// The bare lowercase address of `addr`, used as a lookup key.
+fn normalize_address(addr: &str) -> Result<String, String> {
+    let mbox: lettre::message::Mailbox = addr
+        .parse()
+        .map_err(|e| format!("bad address '{addr}': {e}"))?;
+    Ok(mbox.email.to_string().to_lowercase())
+}
+
 // ── SMTP ────────────────────────────────────────────────────────────────────
 
-fn build_mailer(
-    host: &str,
-    port: u16,
-    user: &str,
-    pass: &str,
-) -> AsyncSmtpTransport<Tokio1Executor> {
-    let tls_params = TlsParameters::builder(host.to_string())
-        .dangerous_accept_invalid_certs(true)
-        .build()
-        .expect("failed to build TLS parameters");
-
-    let mut builder =
-        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
-            .port(port)
-            .tls(Tls::Required(tls_params));
-
-    if !user.is_empty() {
-        builder = builder.credentials(Credentials::new(
-            user.to_string(),
-            pass.to_string(),
-        ));
+/// The configured relay, logging in as `user`.
+fn default_upstream(config: &Config, user: &str, pass: &str) -> upstreams::Upstream {
+    upstreams::Upstream {
+        name: upstreams::DEFAULT.into(),
+        host: config.smtp_host.clone(),
+        port: config.smtp_port,
+        tls: upstreams::TlsMode::StartTls,
+        user: user.to_string(),
+        pass: pass.to_string(),
+        verify_tls: false,
+    }
+}
+
+/// The relays for mail from `from` in failover order: the domain's
+/// upstreams, or the configured host with the `POST /smtp` credentials.
+async fn upstreams_for(state: &AppState, from: &str) -> Result<Vec<upstreams::Upstream>, String> {
+    let default = {
+        let creds = state.smtp_creds.read().await;
+        default_upstream(&state.config, &creds.user, &creds.pass)
+    };
+    let domain = extract_domain_from_addr(from).unwrap_or_default();
+    upstreams::for_domain(&*state.db.lock().await, &domain, &default)
+}
+
+/// Signing and encryption keys resolved for one message.
+#[derive(Default)]
+struct MessageKeys {
+    pgp: pgp::Keys,
+    smime: Option<smime::Signer>,
+    dkim: Option<lettre::message::dkim::DkimConfig>,
+}
+
+impl MessageKeys {
+    /// Loads the keys `content` asks for, failing if any are not configured,
+    /// plus the sending domain's DKIM key when it has one.
+    fn load(conn: &Connection, from: &str, to: &[String], content: &EmailContent) -> Result<Self, String> {
+        let domain = extract_domain_from_addr(from).ok_or("invalid from address")?;
+
+        let pgp = if content.pgp.is_empty() {
+            pgp::Keys::default()
+        } else {
+            pgp::load_keys(conn, &domain, to, &content.pgp)?
+        };
+        let smime = if content.smime {
+            Some(smime::load_signer(conn, &domain)?)
+        } else {
+            None
+        };
+        let dkim = dkim::load_config(conn, &domain)?;
+        Ok(Self { pgp, smime, dkim })
     }
 
-    builder.build()
+    fn protect(&self, part: mime::Part) -> Result<mime::Part, String> {
+        let part = match &self.smime {
+            Some(signer) => smime::sign(part, signer)?,
+            None => part,
+        };
+        pgp::protect(part, &self.pgp)
+    }
 }
 
-async fn send_email(
-    state: &AppState,
+fn build_message(
+    id: &str,
     from: &str,
     to: &[String],
-    subject: &str,
-    body: &str,
-    html: Option<&str>,
-) -> Result<(), String> {
-    let creds = state.smtp_creds.read().await;
-    let mailer = build_mailer(
-        &state.config.smtp_host,
-        state.config.smtp_port,
-        &creds.user,
-        &creds.pass,
-    );
-    drop(creds);
+    content: &EmailContent,
+    keys: &MessageKeys,
+) -> Result<lettre::Message, String> {
+    let mut message = assemble_message(id, from, to, content, keys)?;
+    // DKIM goes last: it covers the final body, including any PGP/S/MIME wrapping.
+    if let Some(config) = &keys.dkim {
+        message.sign(config);
+    }
+    Ok(message)
+}
 
+fn assemble_message(
+    id: &str,
+    from: &str,
+    to: &[String],
+    content: &EmailContent,
+    keys: &MessageKeys,
+) -> Result<lettre::Message, String> {
     let from_mbox: lettre::message::Mailbox = from.parse().map_err(|e| format!("bad from: {e}"))?;
 
     let mut email_builder = lettre::Message::builder()
         .from(from_mbox)
-        .subject(subject);
+        .message_id(Some(message_id(id, from)))
+        .subject(&content.subject);
 
     for addr in to {
         let mbox: lettre::message::Mailbox =
@@ -292,40 +741,199 @@ async fn send_email(
         email_builder = email_builder.to(mbox);
     }
 
-    let message = if let Some(html_body) = html {
-        email_builder
-            .multipart(
-                MultiPart::alternative()
-                    .singlepart(
-                        SinglePart::builder()
-                            .header(ContentType::TEXT_PLAIN)
-                            .body(body.to_string()),
-                    )
-                    .singlepart(
-                        SinglePart::builder()
-                            .header(ContentType::TEXT_HTML)
-                            .body(html_body.to_string()),
-                    ),
-            )
-            .map_err(|e| format!("build email: {e}"))?
-    } else {
-        email_builder
-            .body(body.to_string())
-            .map_err(|e| format!("build email: {e}"))?
+    if let Some(url) = &content.unsubscribe_url {
+        // RFC 8058 one-click unsubscribe
+        email_builder = email_builder
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe"),
+                format!("<{url}>"),
+            ))
+            .raw_header(HeaderValue::new(
+                HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+                "List-Unsubscribe=One-Click".into(),
+            ));
+    }
+
+    let plain_only = content.html.is_none() && content.calendar.is_none();
+    if plain_only && content.pgp.is_empty() && !content.smime {
+        return email_builder
+            .body(content.body.clone())
+            .map_err(|e| format!("build email: {e}"));
+    }
+
+    if plain_only {
+        let text = SinglePart::builder()
+            .header(ContentType::TEXT_PLAIN)
+            .body(content.body.clone());
+        return finish_message(email_builder, keys.protect(mime::Part::Single(text))?);
+    }
+
+    let mut alternative = MultiPart::alternative().singlepart(
+        SinglePart::builder()
+            .header(ContentType::TEXT_PLAIN)
+            .body(content.body.clone()),
+    );
+    if let Some(html_body) = &content.html {
+        alternative = alternative.singlepart(
+            SinglePart::builder()
+                .header(ContentType::TEXT_HTML)
+                .body(html_body.clone()),
+        );
+    }
+    if let Some(ics) = &content.calendar {
+        // The method parameter is what makes clients offer accept/decline.
+        let method = calendar::method(ics).unwrap_or_else(|| "REQUEST".into());
+        let content_type = ContentType::parse(&format!("text/calendar; method={method}; charset=utf-8"))
+            .map_err(|e| format!("calendar content type: {e}"))?;
+        alternative = alternative.singlepart(
+            SinglePart::builder()
+                .header(content_type)
+                .body(ics.clone()),
+        );
+    }
+
+    finish_message(email_builder, keys.protect(mime::Part::Multi(alternative))?)
+}
+
+fn finish_message(builder: lettre::message::MessageBuilder, body: mime::Part) -> Result<lettre::Message, String> {
+    match body {
+        mime::Part::Single(part) => builder.singlepart(part),
+        mime::Part::Multi(part) => builder.multipart(part),
+    }
+    .map_err(|e| format!("build email: {e}"))
+}
+
+/// A failed send. Permanent failures (SMTP 5xx) will not succeed on retry.
+#[derive(Debug)]
+struct SendError {
+    message: String,
+    permanent: bool,
+}
+
+impl From<String> for SendError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            permanent: false,
+        }
+    }
+}
+
+impl std::fmt::Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+async fn send_email(
+    state: &AppState,
+    id: &str,
+    from: &str,
+    to: &[String],
+    content: &EmailContent,
+) -> Result<(), SendError> {
+    let (envelope, message) = match &content.raw {
+        Some(raw) => {
+            let domain = extract_domain_from_addr(from).ok_or_else(|| "invalid from address".to_string())?;
+            let signed = {
+                let db = state.db.lock().await;
+                dkim::sign_raw(&db, &domain, raw)?
+            };
+            (raw_envelope(from, to)?, signed)
+        }
+        None => {
+            let keys = {
+                let db = state.db.lock().await;
+                MessageKeys::load(&db, from, to, content)?
+            };
+            let message = build_message(id, from, to, content, &keys)?;
+            (message.envelope().clone(), message.formatted())
+        }
     };
 
-    mailer
-        .send(message)
-        .await
-        .map_err(|e| format!("smtp send: {e}"))?;
+    // Go down the list past relays whose breaker is open. A failure that
+    // opens a breaker moves this message on to the next relay too.
+    let mut last_error = None;
+    for upstream in upstreams_for(state, from).await? {
+        if !state.breakers.available(&upstream.name) {
+            continue;
+        }
+        match upstream.transport()?.send_raw(&envelope, &message).await {
+            Err(e) if upstreams::is_relay_failure(&e) => {
+                let error = format!("smtp send via '{}': {e}", upstream.name);
+                if !state.breakers.failure(&upstream.name, &error, &state.config.failover) {
+                    return Err(error.into());
+                }
+                last_error = Some(error);
+            }
+            result => {
+                state.breakers.success(&upstream.name);
+                result.map_err(|e| SendError {
+                    permanent: e.is_permanent(),
+                    message: format!("smtp send: {e}"),
+                })?;
+                return Ok(());
+            }
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| "every SMTP upstream for this domain is failing, waiting for one to recover".into())
+        .into())
+}
+
+fn raw_envelope(from: &str, to: &[String]) -> Result<lettre::address::Envelope, String> {
+    let address = |addr: &str| -> Result<lettre::Address, String> {
+        let mbox: lettre::message::Mailbox = addr.parse().map_err(|e| format!("bad address '{addr}': {e}"))?;
+        Ok(mbox.email)
+    };
+    let recipients = to.iter().map(|a| address(a)).collect::<Result<Vec<_>, _>>()?;
+    lettre::address::Envelope::new(Some(address(from)?), recipients).map_err(|e| format!("envelope: {e}"))
+}
+
+// ── Content ─────────────────────────────────────────────────────────────────
+
+/// Turns the content fields of a request into the final subject and parts,
+/// rendering a stored template when one is named.
+async fn resolve_content(state: &AppState, req: &EmailRequest) -> Result<EmailContent, String> {
+    if let Some(template) = &req.template {
+        if req.subject.is_some() || req.body.is_some() || req.html.is_some() || req.markdown.is_some() {
+            return Err("template cannot be combined with subject, body, html or markdown".into());
+        }
+        let db = state.db.lock().await;
+        return templates::render_stored(&db, template, req.template_version, &req.data);
+    }
+
+    if req.template_version.is_some() {
+        return Err("template_version requires template".into());
+    }
+
+    if let Some(md) = &req.markdown {
+        if req.body.is_some() || req.html.is_some() {
+            return Err("markdown cannot be combined with body or html".into());
+        }
+        let subject = req.subject.as_deref().ok_or("subject is required")?;
+        return markdown::render(md, subject, &state.config.markdown_layout);
+    }
+
+    let body = match (&req.body, &req.html) {
+        (Some(body), _) => body.clone(),
+        (None, Some(html_body)) => html::to_text(html_body)?,
+        (None, None) => return Err("body or html is required".into()),
+    };
 
-    Ok(())
+    Ok(EmailContent {
+        subject: req.subject.clone().ok_or("subject is required")?,
+        body,
+        html: req.html.clone(),
+        ..Default::default()
+    })
 }
 
 // ── Handlers ────────────────────────────────────────────────────────────────
 
 async fn index_handler(State(state): State<Arc<AppState>>) -> maud::Markup {
-    let (queue_size, archive_size, failed_count, domains) = {
+    let (queue_size, archive_size, retrying, failed, domains, template_ids, dns_reports) = {
         let db = state.db.lock().await;
         let qs: i64 = db
             .query_row(
@@ -337,20 +945,29 @@ async fn index_handler(State(state): State<Arc<AppState>>) -> maud::Markup {
         let ar: i64 = db
             .query_row("SELECT COUNT(*) FROM email_archive", [], |r| r.get(0))
             .unwrap_or(0);
-        let fc: i64 = db
+        let rc: i64 = db
             .query_row(
-                "SELECT COUNT(*) FROM email_queue WHERE attempts > 0",
+                "SELECT COUNT(*) FROM email_queue WHERE status = 'pending' AND attempts > 0",
                 [],
                 |r| r.get(0),
             )
             .unwrap_or(0);
-        let mut stmt = db.prepare("SELECT domain FROM domains ORDER BY domain").unwrap();
-        let ds: Vec<String> = stmt
+        let fc: i64 = db
+            .query_row("SELECT COUNT(*) FROM email_queue WHERE status = 'failed'", [], |r| r.get(0))
+            .unwrap_or(0);
+        let mut stmt = db.prepare("SELECT domain, status FROM domains ORDER BY domain").unwrap();
+        let ds: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect();
+        let mut stmt = db.prepare("SELECT id FROM templates ORDER BY id").unwrap();
+        let ts: Vec<String> = stmt
             .query_map([], |row| row.get(0))
             .unwrap()
             .filter_map(|r| r.ok())
             .collect();
-        (qs, ar, fc, ds)
+        (qs, ar, rc, fc, ds, ts, dns_report::cached(&db))
     };
 
     let smtp_host = &state.config.smtp_host;
@@ -376,12 +993,20 @@ async fn index_handler(State(state): State<Arc<AppState>>) -> maud::Markup {
                         .subtitle { color: #888; margin-bottom: 2rem; }
                         .card { background: #161616; border: 1px solid #2a2a2a; border-radius: 8px; padding: 1.25rem; margin-bottom: 1rem; }
                         .card h2 { font-size: 0.875rem; text-transform: uppercase; letter-spacing: 0.05em; color: #888; margin-bottom: 0.75rem; }
-                        .stat-grid { display: grid; grid-template-columns: repeat(3, 1fr); gap: 1rem; }
+                        .stat-grid { display: grid; grid-template-columns: repeat(4, 1fr); gap: 1rem; }
                         .stat .value { font-size: 1.5rem; font-weight: 600; color: #fff; }
                         .stat .label { font-size: 0.75rem; color: #888; }
                         .domain-list { list-style: none; }
                         .domain-list li { padding: 0.375rem 0; border-bottom: 1px solid #2a2a2a; font-family: monospace; font-size: 0.875rem; }
                         .domain-list li:last-child { border-bottom: none; }
+                        .domain-list a { color: #6cb6ff; text-decoration: none; }
+                        .tag { margin-left: 0.5rem; padding: 0 0.375rem; border: 1px solid #6e5b1e; border-radius: 4px; color: #d29922; font-size: 0.75rem; }
+                        .domain-list a.tag { color: #888; border-color: #333; }
+                        .domain-list a.tag.pass { color: #4ec970; border-color: #1f4d2c; }
+                        .domain-list a.tag.warn { color: #d29922; border-color: #6e5b1e; }
+                        .domain-list a.tag.fail { color: #f85149; border-color: #6e2320; }
+                        .fix { color: #888; font-size: 0.75rem; margin-top: 0.25rem; font-family: system-ui, sans-serif; }
+                        .activity { margin-top: 0.75rem; min-height: 1em; font-family: monospace; font-size: 0.75rem; color: #888; overflow: hidden; text-overflow: ellipsis; white-space: nowrap; }
                         .empty { color: #555; font-style: italic; font-size: 0.875rem; }
                         .smtp-info { font-family: monospace; font-size: 0.875rem; color: #aaa; }
                         .routes { font-family: monospace; font-size: 0.875rem; }
@@ -406,17 +1031,27 @@ async fn index_handler(State(state): State<Arc<AppState>>) -> maud::Markup {
                         h2 { "Status" }
                         .stat-grid {
                             .stat {
-                                .value { (queue_size) }
+                                .value id="stat-queued" { (queue_size) }
                                 .label { "queued" }
                             }
                             .stat {
-                                .value { (archive_size) }
+                                .value id="stat-sent" { (archive_size) }
                                 .label { "sent" }
                             }
                             .stat {
-                                .value { (failed_count) }
+                                .value id="stat-retrying" { (retrying) }
                                 .label { "retrying" }
                             }
+                            .stat {
+                                .value id="stat-failed" { (failed) }
+                                .label { "failed" }
+                            }
+                        }
+                        p.activity id="activity" {}
+                        script {
+                            (maud::PreEscaped("
+                                (function(){let t;const set=(id,v)=>document.getElementById(id).textContent=v;const refresh=()=>{clearTimeout(t);t=setTimeout(async()=>{try{const h=await(await fetch('/health')).json();set('stat-queued',h.queue_size);set('stat-sent',h.archive_size);set('stat-retrying',h.retrying);set('stat-failed',h.failed)}catch(_){}},250)};const es=new EventSource('/events');for(const k of ['queued','sent','retrying','failed','bounced']){es.addEventListener(k,e=>{const d=JSON.parse(e.data);set('activity',k+' '+d.id.slice(0,8)+' → '+d.to.join(', ')+(d.error?' ('+d.error+')':''));refresh()})}})();
+                            "))
                         }
                     }
 
@@ -426,8 +1061,29 @@ async fn index_handler(State(state): State<Arc<AppState>>) -> maud::Markup {
                             p.empty { "No domains configured" }
                         } @else {
                             ul.domain-list {
-                                @for domain in &domains {
-                                    li { (domain) }
+                                @for (domain, status) in &domains {
+                                    li {
+                                        (domain)
+                                        @if status != verification::ACTIVE {
+                                            span.tag { (status) }
+                                        }
+                                        @match dns_reports.get(domain) {
+                                            Some(report) => {
+                                                a.tag.(report.status.as_str()) href={ "/domains/" (domain) "/dns-report" } {
+                                                    "dns " (report.status.as_str())
+                                                }
+                                                @for check in report.checks.iter().filter(|c| c.fix.is_some()) {
+                                                    .fix {
+                                                        (check.name) ": " (check.detail)
+                                                        @if let Some(fix) = &check.fix { " — " (fix) }
+                                                    }
+                                                }
+                                            }
+                                            None => {
+                                                a.tag href={ "/domains/" (domain) "/dns-report" } { "check dns" }
+                                            }
+                                        }
+                                    }
                                 }
                             }
                         }
@@ -443,6 +1099,19 @@ async fn index_handler(State(state): State<Arc<AppState>>) -> maud::Markup {
                         }
                     }
 
+                    .card {
+                        h2 { "Templates" }
+                        @if template_ids.is_empty() {
+                            p.empty { "No templates stored" }
+                        } @else {
+                            ul.domain-list {
+                                @for id in &template_ids {
+                                    li { a href={ "/templates/" (id) "/preview" } { (id) } }
+                                }
+                            }
+                        }
+                    }
+
                     .card {
                         h2 { "SMTP" }
                         p.smtp-info { (smtp_host) ":" (smtp_port) }
@@ -460,18 +1129,76 @@ async fn index_handler(State(state): State<Arc<AppState>>) -> maud::Markup {
                             dd { "Register a domain, get a token" }
                             dt { "GET /domains" }
                             dd { "List registered domains" }
+                            dt { "PATCH /domains/:domain" }
+                            dd { "Set CSS inlining, HTML sanitization, S/MIME signing" }
+                            dt { "POST /domains/:domain/verify" }
+                            dd { "Check the _mayl-challenge TXT record" }
+                            dt { "GET /domains/:domain/dns-report" }
+                            dd { "Check SPF, DKIM, DMARC and MX records" }
                             dt { "DELETE /domains/:domain" }
                             dd { "Remove a domain" }
+                            dt { "POST /domains/:domain/token" }
+                            dd { "Rotate a domain's API token" }
+                            dt { "PUT /domains/:domain/pgp-key" }
+                            dd { "Set the OpenPGP signing key" }
+                            dt { "GET /domains/:domain/suppressions" }
+                            dd { "Suppressed recipients (POST to add)" }
+                            dt { "POST /domains/:domain/webhooks" }
+                            dd { "Subscribe a URL to sent/retrying/failed events" }
+                            dt { "GET /domains/:domain/webhooks/:id/deliveries" }
+                            dd { "Webhook delivery log" }
+                            dt { "GET /domains/:domain/bounces" }
+                            dd { "Messages reported undeliverable by DSN" }
+                            dt { "POST /domains/:domain/inbound-routes" }
+                            dd { "Forward received mail for an address to a URL" }
+                            dt { "GET /domains/:domain/unsubscribes" }
+                            dd { "List unsubscribes (?list=)" }
+                            dt { "PUT /domains/:domain/dkim" }
+                            dd { "Generate or upload a DKIM key" }
+                            dt { "GET /domains/:domain/dkim" }
+                            dd { "DKIM TXT record to publish" }
+                            dt { "PUT /domains/:domain/smime" }
+                            dd { "Set the S/MIME signing certificate" }
+                            dt { "POST /keys" }
+                            dd { "Store a recipient's OpenPGP public key" }
+                            dt { "GET /queue" }
+                            dd { "List queued messages" }
+                            dt { "POST /queue/:id/retry" }
+                            dd { "Retry a failed message" }
+                            dt { "DELETE /queue" }
+                            dd { "Purge failed messages" }
+                            dt { "POST /upstreams" }
+                            dd { "Add a named SMTP relay" }
+                            dt { "PUT /domains/:domain/upstream" }
+                            dd { "Set a domain's relays, in failover order" }
                             dt { "GET /smtp" }
                             dd { "SMTP credential status" }
                             dt { "POST /smtp" }
-                            dd { "Set SMTP credentials" }
+                            dd { "Set SMTP credentials, after a test login" }
+                            dt { "POST /smtp/test" }
+                            dd { "Test SMTP credentials without saving" }
                             dt { "POST /email" }
                             dd { "Queue an email (Authorization: Bearer <token>)" }
                             dt { "POST /email?sync=true" }
                             dd { "Send immediately" }
+                            dt { "POST /email?dry_run=true" }
+                            dd { "Validate and render without sending" }
+                            dt { "POST /email/raw" }
+                            dd { "Queue a complete RFC 5322 message" }
+                            dt { "POST /templates" }
+                            dd { "Store a template (version 1)" }
+                            dt { "POST /templates/:id/versions" }
+                            dd { "Add a new template version" }
+                            dt { "POST /templates/:id/preview" }
+                            dd { "Render a template with sample data" }
+                            dt { "GET /events" }
+                            dd { "Live queue activity (SSE)" }
+                            dt { "GET /email/events" }
+                            dd { "Live activity for a token's domain (SSE)" }
                             dt { "GET /health" }
                             dd { "Queue and archive stats (JSON)" }
+                            dt { "GET /ready" }
+                            dd { "SMTP relay login check (503 when down)" }
                         }
                     }
                 }
@@ -495,13 +1222,83 @@ async fn health_handler(State(state): State<Arc<AppState>>) -> Json<HealthRespon
         .query_row("SELECT COUNT(*) FROM email_archive", [], |r| r.get(0))
         .unwrap_or(0);
 
+    let retrying: i64 = db
+        .query_row(
+            "SELECT COUNT(*) FROM email_queue WHERE status = 'pending' AND attempts > 0",
+            [],
+            |r| r.get(0),
+        )
+        .unwrap_or(0);
+
+    let failed: i64 = db
+        .query_row("SELECT COUNT(*) FROM email_queue WHERE status = 'failed'", [], |r| r.get(0))
+        .unwrap_or(0);
+
+    let upstreams = state
+        .breakers
+        .status(&upstreams::names(&db).unwrap_or_else(|_| vec![upstreams::DEFAULT.into()]));
+    let degraded = upstreams.iter().any(|u| u.state == "open");
+
     Json(HealthResponse {
-        status: "ok".into(),
+        status: if degraded { "degraded" } else { "ok" }.into(),
         queue_size,
         archive_size,
+        retrying,
+        failed,
+        upstreams,
     })
 }
 
+/// How long a `GET /ready` result is reused before the relay is checked again.
+const READY_CACHE: Duration = Duration::from_secs(30);
+
+/// Whether mail can go out: logs in to every relay a domain sends through
+/// and sends NOOP. Answers `503` when any of them fails.
+async fn ready_handler(State(state): State<Arc<AppState>>) -> (StatusCode, Json<ReadyResponse>) {
+    let mut cached = state.readiness.lock().await;
+    if cached.as_ref().is_none_or(|(at, _)| at.elapsed() >= READY_CACHE) {
+        let default = {
+            let creds = state.smtp_creds.read().await;
+            default_upstream(&state.config, &creds.user, &creds.pass)
+        };
+        let in_use = upstreams::in_use(&*state.db.lock().await, &default);
+        let mut names = Vec::new();
+        let mut errors = Vec::new();
+        match in_use {
+            Ok(in_use) => {
+                let checks: Vec<_> = in_use
+                    .into_iter()
+                    .map(|upstream| tokio::spawn(async move { (upstream.check().await, upstream.name) }))
+                    .collect();
+                for check in checks {
+                    let (result, name) = check.await.expect("readiness check panicked");
+                    if let Err(e) = result {
+                        warn!("readiness check of '{name}' failed: {e}");
+                        errors.push(format!("{name}: {e}"));
+                    }
+                    names.push(name);
+                }
+            }
+            Err(e) => errors.push(format!("db error: {e}")),
+        }
+        let response = ReadyResponse {
+            status: if errors.is_empty() { "ready" } else { "unavailable" }.into(),
+            upstreams: names,
+            error: (!errors.is_empty()).then(|| errors.join("; ")),
+            checked_at: now_millis(),
+        };
+        *cached = Some((Instant::now(), response));
+    }
+
+    let response = cached.as_ref().map(|(_, r)| r.clone()).expect("just checked");
+    let status = if response.error.is_none() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(response))
+}
+
 // ── Domain Handlers ─────────────────────────────────────────────────────────
 
 async fn create_domain_handler(
@@ -515,31 +1312,52 @@ async fn create_domain_handler(
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
                 error: "invalid domain".into(),
+                ..Default::default()
             }),
         ));
     }
 
     let token = uuid::Uuid::new_v4().to_string();
     let now = now_millis();
+    let challenge = payload
+        .verify
+        .unwrap_or(state.config.require_domain_verification)
+        .then(verification::new_challenge);
+    let status = if challenge.is_some() {
+        verification::PENDING
+    } else {
+        verification::ACTIVE
+    };
 
     let db = state.db.lock().await;
     db.execute(
-        "INSERT INTO domains (domain, token, created_at) VALUES (?1, ?2, ?3)",
-        rusqlite::params![domain, token, now],
+        "INSERT INTO domains (domain, token, created_at, status, challenge) VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![domain, token, now, status, challenge],
     )
     .map_err(|_| {
         (
             StatusCode::CONFLICT,
             Json(ErrorResponse {
                 error: "domain already exists".into(),
+                ..Default::default()
             }),
         )
     })?;
 
-    info!(domain, "domain registered");
+    let mut opts = DomainOptions::default();
+    opts.apply(&payload.options);
+    save_domain_options(&db, &domain, &opts)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+
+    info!(domain, status, "domain registered");
     Ok((
         StatusCode::CREATED,
-        Json(DomainResponse { domain, token }),
+        Json(DomainResponse {
+            verification: challenge.map(|c| verification::Challenge::for_domain(&domain, &c)),
+            domain,
+            token,
+            status: status.into(),
+        }),
     ))
 }
 
@@ -548,13 +1366,18 @@ async fn list_domains_handler(
 ) -> Json<Vec<DomainListEntry>> {
     let db = state.db.lock().await;
     let mut stmt = db
-        .prepare("SELECT domain, created_at FROM domains ORDER BY domain")
+        .prepare(
+            "SELECT domain, created_at, status, inline_css, sanitize_html, smime_sign
+             FROM domains ORDER BY domain",
+        )
         .unwrap();
     let domains: Vec<DomainListEntry> = stmt
         .query_map([], |row| {
             Ok(DomainListEntry {
                 domain: row.get(0)?,
                 created_at: row.get(1)?,
+                status: row.get(2)?,
+                options: domain_options_from_row(row, 3)?,
             })
         })
         .unwrap()
@@ -564,21 +1387,70 @@ async fn list_domains_handler(
     Json(domains)
 }
 
+async fn update_domain_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+    Json(payload): Json<DomainOptionsRequest>,
+) -> Result<Json<DomainListEntry>, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let (created_at, status, mut opts) = db
+        .query_row(
+            "SELECT created_at, status, inline_css, sanitize_html, smime_sign FROM domains WHERE domain = ?1",
+            [&domain],
+            |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, domain_options_from_row(row, 2)?)),
+        )
+        .map_err(|_| api_error(StatusCode::NOT_FOUND, "domain not found"))?;
+
+    opts.apply(&payload);
+    save_domain_options(&db, &domain, &opts)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+
+    info!(domain, ?opts, "domain options updated");
+    Ok(Json(DomainListEntry {
+        domain,
+        created_at,
+        status,
+        options: opts,
+    }))
+}
+
+/// Deletes a domain and everything kept for it, so one re-created under the
+/// same name starts clean. Returns whether the domain existed.
+fn delete_domain(conn: &mut Connection, domain: &str) -> rusqlite::Result<bool> {
+    let tx = conn.transaction()?;
+    if tx.execute("DELETE FROM domains WHERE domain = ?1", [domain])? == 0 {
+        return Ok(false);
+    }
+    pgp::delete_domain(&tx, domain)?;
+    smime::delete_domain(&tx, domain)?;
+    dkim::delete_domain(&tx, domain)?;
+    suppressions::delete_domain(&tx, domain)?;
+    unsubscribe::delete_domain(&tx, domain)?;
+    dns_report::delete_domain(&tx, domain)?;
+    webhooks::delete_domain(&tx, domain)?;
+    bounces::delete_domain(&tx, domain)?;
+    inbound::delete_domain(&tx, domain)?;
+    upstreams::delete_domain(&tx, domain)?;
+    tx.commit()?;
+    Ok(true)
+}
+
 async fn delete_domain_handler(
     State(state): State<Arc<AppState>>,
     Path(domain): Path<String>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
     let domain = domain.to_lowercase();
-    let db = state.db.lock().await;
-    let deleted = db
-        .execute("DELETE FROM domains WHERE domain = ?1", [&domain])
-        .unwrap_or(0);
+    let mut db = state.db.lock().await;
+    let deleted = delete_domain(&mut db, &domain)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
 
-    if deleted == 0 {
+    if !deleted {
         Err((
             StatusCode::NOT_FOUND,
             Json(ErrorResponse {
                 error: "domain not found".into(),
+                ..Default::default()
             }),
         ))
     } else {
@@ -587,7 +1459,32 @@ async fn delete_domain_handler(
     }
 }
 
-// ── SMTP Config Handlers ────────────────────────────────────────────────────
+/// Issues a new API token for a domain; the old one stops working at once.
+async fn rotate_token_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Result<Json<DomainResponse>, ApiError> {
+    let domain = domain.to_lowercase();
+    let token = uuid::Uuid::new_v4().to_string();
+    let db = state.db.lock().await;
+    let status: String = db
+        .query_row(
+            "UPDATE domains SET token = ?2 WHERE domain = ?1 RETURNING status",
+            rusqlite::params![domain, token],
+            |r| r.get(0),
+        )
+        .map_err(|_| api_error(StatusCode::NOT_FOUND, "domain not found"))?;
+
+    info!(domain, "domain token rotated");
+    Ok(Json(DomainResponse {
+        domain,
+        token,
+        status,
+        verification: None,
+    }))
+}
+
+// ── SMTP Config Handlers ────────────────────────────────────────────────────
 
 async fn get_smtp_handler(
     State(state): State<Arc<AppState>>,
@@ -599,17 +1496,36 @@ async fn get_smtp_handler(
     })
 }
 
+/// Logs in to the configured relay with the given credentials. A refused
+/// login is the caller's mistake (`400`); an unreachable relay is `502`.
+async fn check_smtp_credentials(state: &AppState, req: &SmtpRequest) -> Result<(), ApiError> {
+    if req.user.is_empty() || req.pass.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "user and pass are required"));
+    }
+    default_upstream(&state.config, &req.user, &req.pass)
+        .check()
+        .await
+        .map_err(upstreams::check_error)
+}
+
+/// Checks credentials against the configured relay without saving them.
+async fn smtp_test_handler(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<SmtpRequest>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    check_smtp_credentials(&state, &payload).await?;
+    Ok(Json(serde_json::json!({"status": "ok"})))
+}
+
 async fn set_smtp_handler(
     State(state): State<Arc<AppState>>,
+    Query(query): Query<SmtpSetQuery>,
     Json(payload): Json<SmtpRequest>,
 ) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, Json<ErrorResponse>)> {
-    if payload.user.is_empty() || payload.pass.is_empty() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "user and pass are required".into(),
-            }),
-        ));
+    if query.verify.unwrap_or(true) {
+        check_smtp_credentials(&state, &payload).await?;
+    } else if payload.user.is_empty() || payload.pass.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "user and pass are required"));
     }
 
     // Persist to DB
@@ -625,6 +1541,7 @@ async fn set_smtp_handler(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorResponse {
                     error: format!("db error: {e}"),
+                    ..Default::default()
                 }),
             )
         })?;
@@ -638,6 +1555,7 @@ async fn set_smtp_handler(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorResponse {
                     error: format!("db error: {e}"),
+                    ..Default::default()
                 }),
             )
         })?;
@@ -649,6 +1567,7 @@ async fn set_smtp_handler(
         creds.user = payload.user;
         creds.pass = payload.pass;
     }
+    *state.readiness.lock().await = None;
 
     info!("SMTP credentials updated");
     Ok((
@@ -663,7 +1582,7 @@ async fn email_handler(
     State(state): State<Arc<AppState>>,
     headers: HeaderMap,
     Query(query): Query<SendQuery>,
-    Json(payload): Json<EmailRequest>,
+    Json(mut payload): Json<EmailRequest>,
 ) -> Result<(StatusCode, Json<QueueResponse>), (StatusCode, Json<ErrorResponse>)> {
     let is_sync = query.sync.unwrap_or(false);
     let save = query.save.unwrap_or(true);
@@ -674,127 +1593,255 @@ async fn email_handler(
             StatusCode::UNAUTHORIZED,
             Json(ErrorResponse {
                 error: "missing Authorization header".into(),
+                ..Default::default()
             }),
         )
     })?;
 
-    // Look up the domain this token authorizes
-    let authorized_domain: String = {
+    let (authorized_domain, domain_opts) = {
         let db = state.db.lock().await;
-        db.query_row(
-            "SELECT domain FROM domains WHERE token = ?1",
-            [&token],
-            |r| r.get(0),
-        )
-        .map_err(|_| {
-            (
-                StatusCode::UNAUTHORIZED,
-                Json(ErrorResponse {
-                    error: "invalid token".into(),
-                }),
-            )
-        })?
+        authorize_sender(&db, &token, &payload.from)?
     };
 
-    // Validate from address domain matches token
-    let from_domain = extract_domain_from_addr(&payload.from).ok_or_else(|| {
-        (
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "invalid from address".into(),
-            }),
-        )
-    })?;
-
-    if from_domain != authorized_domain {
-        return Err((
-            StatusCode::FORBIDDEN,
-            Json(ErrorResponse {
-                error: format!(
-                    "token authorizes domain '{}', but from address uses '{}'",
-                    authorized_domain, from_domain
-                ),
-            }),
-        ));
-    }
-
     if payload.to.is_empty() {
         return Err((
             StatusCode::BAD_REQUEST,
             Json(ErrorResponse {
                 error: "to list is empty".into(),
+                ..Default::default()
             }),
         ));
     }
 
-    if is_sync {
-        if let Err(e) = send_email(
-            &state,
-            &payload.from,
-            &payload.to,
-            &payload.subject,
-            &payload.body,
-            payload.html.as_deref(),
+    payload.to = recipients::validate(&payload.to, &state.config.recipient_policy).map_err(|rejected| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("{} recipient(s) rejected", rejected.len()),
+                rejected,
+            }),
         )
-        .await
-        {
-            return Err((
-                StatusCode::BAD_GATEWAY,
-                Json(ErrorResponse {
-                    error: format!("smtp error: {e}"),
-                }),
+    })?;
+
+    let suppressed = {
+        let db = state.db.lock().await;
+        let (allowed, suppressed) = suppressions::partition(&db, &authorized_domain, &payload.to)
+            .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+        if allowed.is_empty() {
+            return Err(api_error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "all recipients are suppressed",
             ));
         }
+        payload.to = allowed;
+        suppressed
+    };
 
-        let id = uuid::Uuid::new_v4().to_string();
+    let unsubscribed = match &payload.list {
+        Some(list) => {
+            if state.config.public_url.is_none() {
+                return Err(api_error(StatusCode::BAD_REQUEST, "list requires MAYL_PUBLIC_URL to be set"));
+            }
+            // A list send is one message per recipient; a sync request could
+            // fail part way with some of them already sent.
+            if is_sync && !query.dry_run.unwrap_or(false) {
+                return Err(api_error(StatusCode::BAD_REQUEST, "list cannot be combined with sync=true"));
+            }
+            let db = state.db.lock().await;
+            let (allowed, unsubscribed) = unsubscribe::partition(&db, &authorized_domain, list, &payload.to)
+                .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+            if allowed.is_empty() {
+                return Err(api_error(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "all recipients have unsubscribed from this list",
+                ));
+            }
+            payload.to = allowed;
+            unsubscribed
+        }
+        None => Vec::new(),
+    };
 
-        if save {
-            let now = now_millis();
-            let to_json = serde_json::to_string(&payload.to).unwrap();
+    let mut content = resolve_content(&state, &payload)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+
+    if let Some(h) = &content.html {
+        content.html = Some(
+            html::process(h, domain_opts.inline_css, domain_opts.sanitize_html)
+                .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?,
+        );
+    }
+
+    let calendar_uid = match &payload.calendar {
+        Some(input) => {
+            let invitation = calendar::build(input, &payload.from, &payload.to)
+                .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+            content.calendar = Some(invitation.ics);
+            Some(invitation.uid)
+        }
+        None => None,
+    };
+
+    content.pgp = payload.pgp;
+    content.smime = payload.smime.unwrap_or(domain_opts.smime_sign);
+    if content.smime && !content.pgp.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "smime cannot be combined with pgp"));
+    }
+
+    // Fail at submission rather than in the queue when keys are missing
+    {
+        let db = state.db.lock().await;
+        MessageKeys::load(&db, &payload.from, &payload.to, &content)
+            .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    }
+
+    // List mail goes to each recipient separately so that every copy carries
+    // its own unsubscribe link.
+    let deliveries: Vec<(Vec<String>, EmailContent)> = match (&payload.list, &state.config.public_url) {
+        (Some(list), Some(public_url)) => {
             let db = state.db.lock().await;
-            let _ = db.execute(
-                "INSERT INTO email_archive (queue_id, from_addr, to_addrs, subject, body, html, sent_at)
-                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-                rusqlite::params![&id, &payload.from, &to_json, &payload.subject, &payload.body, &payload.html, now],
-            );
+            payload
+                .to
+                .iter()
+                .map(|addr| {
+                    let mut content = content.clone();
+                    content.unsubscribe_url =
+                        Some(unsubscribe::link(&db, public_url, &authorized_domain, list, addr)?);
+                    Ok((vec![addr.clone()], content))
+                })
+                .collect::<Result<_, String>>()
+                .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?
         }
+        _ => vec![(payload.to.clone(), content)],
+    };
 
-        Ok((
+    if query.dry_run.unwrap_or(false) {
+        let db = state.db.lock().await;
+        let messages = deliveries
+            .iter()
+            .map(|(to, content)| {
+                let id = uuid::Uuid::new_v4().to_string();
+                let keys = MessageKeys::load(&db, &payload.from, to, content)?;
+                let message = build_message(&id, &payload.from, to, content, &keys)?;
+                Ok(RenderedMessage::new(id, to, &message.formatted()))
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+        let ids: Vec<String> = messages.iter().map(|m| m.id.clone()).collect();
+        return Ok((
             StatusCode::OK,
             Json(QueueResponse {
-                id,
-                status: "sent".into(),
+                id: ids[0].clone(),
+                ids: if ids.len() > 1 { ids } else { Vec::new() },
+                status: "dry_run".into(),
+                calendar_uid,
+                suppressed,
+                unsubscribed,
+                messages,
             }),
-        ))
+        ));
+    }
+
+    let mut ids = Vec::with_capacity(deliveries.len());
+    let (status_code, status) = if is_sync {
+        for (to, content) in &deliveries {
+            let id = uuid::Uuid::new_v4().to_string();
+            let event = webhooks::EmailEvent {
+                id: &id,
+                from: &payload.from,
+                to,
+                error: None,
+            };
+
+            if let Err(e) = send_email(&state, &id, &payload.from, to, content).await {
+                let db = state.db.lock().await;
+                if e.permanent {
+                    suppressions::record_bounce(&db, &authorized_domain, to, &e.message);
+                }
+                let event = webhooks::EmailEvent {
+                    error: Some(&e.message),
+                    ..event
+                };
+                webhooks::emit(&db, &authorized_domain, webhooks::Event::Failed, &event);
+                events::publish(&state.events, events::Kind::Failed, &authorized_domain, &event);
+                return Err((
+                    StatusCode::BAD_GATEWAY,
+                    Json(ErrorResponse {
+                        error: format!("smtp error: {e}"),
+                        ..Default::default()
+                    }),
+                ));
+            }
+
+            let db = state.db.lock().await;
+            webhooks::emit(&db, &authorized_domain, webhooks::Event::Sent, &event);
+            events::publish(&state.events, events::Kind::Sent, &authorized_domain, &event);
+            if state.config.imap.is_some() {
+                bounces::record_sent(&db, &message_id(&id, &payload.from), &id, &payload.from, to);
+            }
+            if save {
+                let to_json = serde_json::to_string(to).unwrap();
+                let _ = archive_email(&db, &id, &payload.from, &to_json, content);
+            }
+            drop(db);
+            ids.push(id);
+        }
+        (StatusCode::OK, "sent")
     } else {
-        let id = uuid::Uuid::new_v4().to_string();
         let now = now_millis();
-        let to_json = serde_json::to_string(&payload.to).unwrap();
         let save_flag: i64 = if save { 1 } else { 0 };
 
         let db = state.db.lock().await;
-        db.execute(
-            "INSERT INTO email_queue (id, status, from_addr, to_addrs, subject, body, html, created_at, save)
-             VALUES (?1, 'pending', ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-            rusqlite::params![&id, &payload.from, &to_json, &payload.subject, &payload.body, &payload.html, now, save_flag],
-        )
-        .map_err(|e| {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("db error: {e}"),
-                }),
+        let tx = db
+            .unchecked_transaction()
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+        for (to, content) in &deliveries {
+            let id = uuid::Uuid::new_v4().to_string();
+            let to_json = serde_json::to_string(to).unwrap();
+            let pgp_json = (!content.pgp.is_empty()).then(|| serde_json::to_string(&content.pgp).unwrap());
+            tx.execute(
+                "INSERT INTO email_queue (id, status, from_addr, to_addrs, subject, body, html, calendar, pgp, smime, unsubscribe_url, created_at, save)
+                 VALUES (?1, 'pending', ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                rusqlite::params![&id, &payload.from, &to_json, &content.subject, &content.body, &content.html, &content.calendar, pgp_json, content.smime, &content.unsubscribe_url, now, save_flag],
             )
-        })?;
-
-        Ok((
-            StatusCode::ACCEPTED,
-            Json(QueueResponse {
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: format!("db error: {e}"),
+                        ..Default::default()
+                    }),
+                )
+            })?;
+            ids.push(id);
+        }
+        tx.commit()
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+        for (id, (to, _)) in ids.iter().zip(&deliveries) {
+            let event = webhooks::EmailEvent {
                 id,
-                status: "queued".into(),
-            }),
-        ))
-    }
+                from: &payload.from,
+                to,
+                error: None,
+            };
+            events::publish(&state.events, events::Kind::Queued, &authorized_domain, &event);
+        }
+        (StatusCode::ACCEPTED, "queued")
+    };
+
+    Ok((
+        status_code,
+        Json(QueueResponse {
+            id: ids[0].clone(),
+            ids: if ids.len() > 1 { ids } else { Vec::new() },
+            status: status.into(),
+            calendar_uid,
+            suppressed,
+            unsubscribed,
+            messages: Vec::new(),
+        }),
+    ))
 }
 
 // ── Background Workers ──────────────────────────────────────────────────────
@@ -805,10 +1852,10 @@ async fn queue_worker(state: Arc<AppState>) {
     loop {
         tokio::time::sleep(poll_interval).await;
 
-        let emails: Vec<QueueRow> = {
+        let emails: Vec<QueuedEmail> = {
             let db = state.db.lock().await;
             let mut stmt = match db.prepare(
-                "SELECT id, from_addr, to_addrs, subject, body, html, save
+                "SELECT id, from_addr, to_addrs, subject, body, html, save, calendar, pgp, smime, unsubscribe_url, raw
                  FROM email_queue WHERE status = 'pending' ORDER BY created_at LIMIT 10",
             ) {
                 Ok(s) => s,
@@ -818,17 +1865,27 @@ async fn queue_worker(state: Arc<AppState>) {
                 }
             };
 
-            let rows: Vec<QueueRow> = stmt
+            let rows: Vec<QueuedEmail> = stmt
                 .query_map([], |row| {
-                    Ok((
-                        row.get(0)?,
-                        row.get(1)?,
-                        row.get(2)?,
-                        row.get(3)?,
-                        row.get(4)?,
-                        row.get(5)?,
-                        row.get::<_, i64>(6).map(|v| v != 0).unwrap_or(true),
-                    ))
+                    Ok(QueuedEmail {
+                        id: row.get(0)?,
+                        from: row.get(1)?,
+                        to_json: row.get(2)?,
+                        content: EmailContent {
+                            subject: row.get(3)?,
+                            body: row.get(4)?,
+                            html: row.get(5)?,
+                            calendar: row.get(7)?,
+                            pgp: row
+                                .get::<_, Option<String>>(8)?
+                                .and_then(|s| serde_json::from_str(&s).ok())
+                                .unwrap_or_default(),
+                            smime: row.get(9)?,
+                            unsubscribe_url: row.get(10)?,
+                            raw: row.get(11)?,
+                        },
+                        save: row.get::<_, i64>(6).map(|v| v != 0).unwrap_or(true),
+                    })
                 })
                 .ok()
                 .map(|r| r.filter_map(|x| x.ok()).collect())
@@ -837,53 +1894,92 @@ async fn queue_worker(state: Arc<AppState>) {
             for row in &rows {
                 let _ = db.execute(
                     "UPDATE email_queue SET status = 'sending' WHERE id = ?1",
-                    [&row.0],
+                    [&row.id],
                 );
             }
 
             rows
         };
 
-        for (id, from, to_json, subject, body, html, save) in &emails {
+        for QueuedEmail { id, from, to_json, content, save } in &emails {
             let to_addrs: Vec<String> = serde_json::from_str(to_json).unwrap_or_default();
-
-            match send_email(
-                &state,
+            let domain = extract_domain_from_addr(from).unwrap_or_default();
+            let event = webhooks::EmailEvent {
+                id,
                 from,
-                &to_addrs,
-                subject,
-                body,
-                html.as_deref(),
-            )
-            .await
-            {
+                to: &to_addrs,
+                error: None,
+            };
+
+            match send_email(&state, id, from, &to_addrs, content).await {
                 Ok(()) => {
                     info!("sent queued email {id}");
                     let db = state.db.lock().await;
-                    if *save {
-                        let now = now_millis();
-                        if let Err(e) = db.execute(
-                            "INSERT INTO email_archive (queue_id, from_addr, to_addrs, subject, body, html, sent_at)
-                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
-                            rusqlite::params![id, from, to_json, subject, body, html, now],
-                        ) {
-                            error!("archive insert failed for {id}: {e}, returning to pending");
-                            let _ = db.execute(
-                                "UPDATE email_queue SET status = 'pending' WHERE id = ?1",
-                                [id],
-                            );
-                            continue;
-                        }
+                    if *save && let Err(e) = archive_email(&db, id, from, to_json, content) {
+                        error!("archive insert failed for {id}: {e}, returning to pending");
+                        let _ = db.execute(
+                            "UPDATE email_queue SET status = 'pending' WHERE id = ?1",
+                            [id],
+                        );
+                        continue;
                     }
                     let _ = db.execute("DELETE FROM email_queue WHERE id = ?1", [id]);
+                    webhooks::emit(&db, &domain, webhooks::Event::Sent, &event);
+                    events::publish(&state.events, events::Kind::Sent, &domain, &event);
+                    if state.config.imap.is_some() {
+                        bounces::record_sent(&db, &sent_message_id(id, from, content), id, from, &to_addrs);
+                    }
+                }
+                Err(e) if e.permanent => {
+                    warn!("permanent failure for {id}: {e}");
+                    let db = state.db.lock().await;
+                    // A refused RCPT aborts the whole transaction. Send again
+                    // to the other recipients, and report only the refused
+                    // ones as failed.
+                    let bounced = suppressions::record_bounce(&db, &domain, &to_addrs, &e.message);
+                    let rest: Vec<&String> = to_addrs.iter().filter(|a| !bounced.contains(a)).collect();
+                    if !bounced.is_empty() && !rest.is_empty() {
+                        info!("re-queueing {id} without {} refused recipient(s)", bounced.len());
+                        let _ = db.execute(
+                            "UPDATE email_queue SET status = 'pending', to_addrs = ?2, last_error = ?3 WHERE id = ?1",
+                            rusqlite::params![id, serde_json::to_string(&rest).unwrap(), e.message],
+                        );
+                    } else {
+                        let _ = db.execute(
+                            "UPDATE email_queue SET status = 'failed', attempts = attempts + 1, last_error = ?2 WHERE id = ?1",
+                            rusqlite::params![id, e.message],
+                        );
+                    }
+                    let to = if bounced.is_empty() { &to_addrs } else { &bounced };
+                    let event = webhooks::EmailEvent {
+                        to,
+                        error: Some(&e.message),
+                        ..event
+                    };
+                    webhooks::emit(&db, &domain, webhooks::Event::Failed, &event);
+                    events::publish(&state.events, events::Kind::Failed, &domain, &event);
                 }
                 Err(e) => {
                     warn!("failed to send {id}: {e}");
                     let db = state.db.lock().await;
+                    // Announce the first failure and any change of error, not
+                    // every poll while a relay stays down.
+                    let previous: Option<String> = db
+                        .query_row("SELECT last_error FROM email_queue WHERE id = ?1", [id], |r| r.get(0))
+                        .unwrap_or(None);
                     let _ = db.execute(
                         "UPDATE email_queue SET status = 'pending', attempts = attempts + 1, last_error = ?2 WHERE id = ?1",
-                        rusqlite::params![id, e],
+                        rusqlite::params![id, e.message],
                     );
+                    if previous.as_deref() == Some(e.message.as_str()) {
+                        continue;
+                    }
+                    let event = webhooks::EmailEvent {
+                        error: Some(&e.message),
+                        ..event
+                    };
+                    webhooks::emit(&db, &domain, webhooks::Event::Retrying, &event);
+                    events::publish(&state.events, events::Kind::Retrying, &domain, &event);
                 }
             }
         }
@@ -926,42 +2022,12 @@ fn now_millis() -> i64 {
 
 // ── Main ────────────────────────────────────────────────────────────────────
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "mayl=info".parse().unwrap()),
-        )
-        .init();
-
-    let config = Config::load();
-    info!(
-        smtp = %format!("{}:{}", config.smtp_host, config.smtp_port),
-        server = %format!("{}:{}", config.server_host, config.server_port),
-        "starting mayl"
-    );
-
-    let conn = Connection::open(&config.db_path).expect("failed to open database");
-    conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA busy_timeout=5000;")
-        .expect("failed to set pragmas");
-    init_db(&conn);
-    seed_domains(&conn, &config.seed_domains);
-
-    // Recover any emails stuck in 'sending' from a previous crash
-    let reset_count = conn
-        .execute(
-            "UPDATE email_queue SET status = 'pending' WHERE status = 'sending'",
-            [],
-        )
-        .unwrap_or(0);
-    if reset_count > 0 {
-        warn!(count = reset_count, "reset stale 'sending' rows to 'pending'");
-    }
-
-    // Load SMTP credentials: env vars first, then DB overrides
-    let mut smtp_user = env_or("MAYL_SMTP_USER", "");
-    let mut smtp_pass = env_or("MAYL_SMTP_PASS", "");
+/// Builds the shared state around an opened, migrated database. SMTP
+/// credentials come from the environment, overridden by those saved with
+/// `POST /smtp`.
+fn app_state(config: Config, conn: Connection) -> Arc<AppState> {
+    let mut smtp_user = config.smtp_user.clone();
+    let mut smtp_pass = config.smtp_pass.clone();
 
     if let Ok(db_user) = conn.query_row(
         "SELECT value FROM config WHERE key = 'smtp_user'",
@@ -984,49 +2050,176 @@ async fn main() {
         info!("no SMTP credentials configured (use POST /smtp to set)");
     }
 
-    let bind_addr = format!("{}:{}", config.server_host, config.server_port);
-
-    let state = Arc::new(AppState {
+    Arc::new(AppState {
         db: Mutex::new(conn),
         config,
         smtp_creds: RwLock::new(SmtpCredentials {
             user: smtp_user,
             pass: smtp_pass,
         }),
-    });
+        events: events::channel(),
+        breakers: upstreams::Breakers::default(),
+        readiness: Mutex::new(None),
+    })
+}
 
-    tokio::spawn(queue_worker(Arc::clone(&state)));
-    tokio::spawn(archive_culler(Arc::clone(&state)));
+/// Opens the database at `MAYL_DB_PATH`, creating and migrating it as needed.
+fn open_db(config: &Config) -> Connection {
+    let conn = Connection::open(&config.db_path).expect("failed to open database");
+    conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA busy_timeout=5000;")
+        .expect("failed to set pragmas");
+    init_db(&conn);
+    seed_domains(&conn, &config.seed_domains);
+    conn
+}
 
-    let app = Router::new()
+fn router(state: Arc<AppState>) -> Router {
+    Router::new()
         .route("/", get(index_handler))
         .route("/health", get(health_handler))
+        .route("/ready", get(ready_handler))
+        .route("/events", get(events::events_handler))
         .route("/domains", post(create_domain_handler))
         .route("/domains", get(list_domains_handler))
+        .route("/domains/{domain}", patch(update_domain_handler))
         .route("/domains/{domain}", delete(delete_domain_handler))
+        .route("/domains/{domain}/token", post(rotate_token_handler))
+        .route("/domains/{domain}/verify", get(verification::get_verification_handler))
+        .route("/domains/{domain}/verify", post(verification::verify_domain_handler))
+        .route("/domains/{domain}/dns-report", get(dns_report::dns_report_handler))
+        .route("/domains/{domain}/pgp-key", put(pgp::put_signing_key_handler))
+        .route("/domains/{domain}/pgp-key", get(pgp::get_signing_key_handler))
+        .route("/domains/{domain}/pgp-key", delete(pgp::delete_signing_key_handler))
+        .route("/domains/{domain}/dkim", put(dkim::put_dkim_handler))
+        .route("/domains/{domain}/dkim", get(dkim::get_dkim_handler))
+        .route("/domains/{domain}/dkim", delete(dkim::delete_dkim_handler))
+        .route("/domains/{domain}/upstream", put(upstreams::put_domain_upstream_handler))
+        .route("/domains/{domain}/upstream", get(upstreams::get_domain_upstream_handler))
+        .route("/domains/{domain}/upstream", delete(upstreams::delete_domain_upstream_handler))
+        .route("/domains/{domain}/smime", put(smime::put_certificate_handler))
+        .route("/domains/{domain}/smime", get(smime::get_certificate_handler))
+        .route("/domains/{domain}/smime", delete(smime::delete_certificate_handler))
+        .route("/domains/{domain}/suppressions", get(suppressions::list_suppressions_handler))
+        .route("/domains/{domain}/suppressions", post(suppressions::add_suppression_handler))
+        .route(
+            "/domains/{domain}/suppressions/{address}",
+            delete(suppressions::delete_suppression_handler),
+        )
+        .route("/domains/{domain}/webhooks", post(webhooks::create_webhook_handler))
+        .route("/domains/{domain}/webhooks", get(webhooks::list_webhooks_handler))
+        .route("/domains/{domain}/webhooks/{id}", delete(webhooks::delete_webhook_handler))
+        .route(
+            "/domains/{domain}/webhooks/{id}/deliveries",
+            get(webhooks::list_deliveries_handler),
+        )
+        .route("/domains/{domain}/bounces", get(bounces::list_bounces_handler))
+        .route("/domains/{domain}/inbound-routes", post(inbound::create_route_handler))
+        .route("/domains/{domain}/inbound-routes", get(inbound::list_routes_handler))
+        .route("/domains/{domain}/inbound-routes/{id}", delete(inbound::delete_route_handler))
+        .route("/domains/{domain}/inbound-routes/{id}/log", get(inbound::list_log_handler))
+        .route("/domains/{domain}/unsubscribes", get(unsubscribe::list_unsubscribes_handler))
+        .route(
+            "/domains/{domain}/unsubscribes/{list}/{address}",
+            delete(unsubscribe::delete_unsubscribe_handler),
+        )
+        .route("/unsubscribe/{token}", get(unsubscribe::unsubscribe_page_handler))
+        .route("/unsubscribe/{token}", post(unsubscribe::unsubscribe_handler))
+        .route("/keys", post(pgp::add_public_key_handler))
+        .route("/keys", get(pgp::list_public_keys_handler))
+        .route("/keys/{address}", delete(pgp::delete_public_key_handler))
+        .route("/queue", get(queue::list_queue_handler))
+        .route("/queue", delete(queue::purge_queue_handler))
+        .route("/queue/{id}/retry", post(queue::retry_handler))
+        .route("/upstreams", post(upstreams::create_upstream_handler))
+        .route("/upstreams", get(upstreams::list_upstreams_handler))
+        .route("/upstreams/{name}", put(upstreams::update_upstream_handler))
+        .route("/upstreams/{name}", delete(upstreams::delete_upstream_handler))
         .route("/smtp", get(get_smtp_handler))
         .route("/smtp", post(set_smtp_handler))
+        .route("/smtp/test", post(smtp_test_handler))
         .route("/email", post(email_handler))
-        .with_state(state);
+        .route(
+            "/email/raw",
+            post(raw::raw_email_handler).layer(DefaultBodyLimit::max(raw::MAX_SIZE)),
+        )
+        .route("/email/events", get(events::domain_events_handler))
+        .route("/templates", post(templates::create_template_handler))
+        .route("/templates", get(templates::list_templates_handler))
+        .route("/templates/{id}", get(templates::get_template_handler))
+        .route("/templates/{id}", delete(templates::delete_template_handler))
+        .route("/templates/{id}/versions", post(templates::create_version_handler))
+        .route("/templates/{id}/preview", post(templates::preview_handler))
+        .route("/templates/{id}/preview", get(templates::preview_page_handler))
+        .with_state(state)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let serve = args.first().is_none_or(|a| a == "serve");
+
+    // Subcommands print their own results, so keep routine logging quiet.
+    let default_filter = if serve { "mayl=info" } else { "mayl=warn" };
+    let subscriber = tracing_subscriber::fmt().with_env_filter(
+        tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| default_filter.parse().unwrap()),
+    );
+    if !serve {
+        subscriber.with_writer(std::io::stderr).init();
+        return cli::run(&args).await;
+    }
+    subscriber.init();
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(errors) => return config::report_errors(&errors),
+    };
+    info!(
+        smtp = %format!("{}:{}", config.smtp_host, config.smtp_port),
+        server = %format!("{}:{}", config.server_host, config.server_port),
+        "starting mayl"
+    );
+
+    let conn = open_db(&config);
+
+    // Recover any emails stuck in 'sending' from a previous crash
+    let reset_count = conn
+        .execute(
+            "UPDATE email_queue SET status = 'pending' WHERE status = 'sending'",
+            [],
+        )
+        .unwrap_or(0);
+    if reset_count > 0 {
+        warn!(count = reset_count, "reset stale 'sending' rows to 'pending'");
+    }
+
+    let bind_addr = format!("{}:{}", config.server_host, config.server_port);
+    let state = app_state(config, conn);
+
+    tokio::spawn(queue_worker(Arc::clone(&state)));
+    tokio::spawn(archive_culler(Arc::clone(&state)));
+    tokio::spawn(webhooks::worker(Arc::clone(&state)));
+    if let Some(settings) = state.config.imap.clone() {
+        tokio::spawn(bounces::worker(Arc::clone(&state), settings.clone()));
+        tokio::spawn(inbound::worker(Arc::clone(&state), settings));
+    }
+    if let Some(settings) = state.config.submission.clone() {
+        tokio::spawn(submission::serve(Arc::clone(&state), settings));
+    }
 
     let listener = tokio::net::TcpListener::bind(&bind_addr)
         .await
         .expect("failed to bind");
 
     info!("listening on {bind_addr}");
-    axum::serve(listener, app).await.expect("server error");
+    axum::serve(listener, router(state)).await.expect("server error");
+    ExitCode::SUCCESS
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_env_helpers() {
-        assert_eq!(env_or("MAYL_TEST_NONEXISTENT_KEY", "fallback"), "fallback");
-        assert_eq!(env_parse::<u16>("MAYL_TEST_NONEXISTENT_KEY", 42), 42);
-    }
-
     #[test]
     fn test_init_db() {
         let conn = Connection::open_in_memory().unwrap();
@@ -1042,6 +2235,29 @@ mod tests {
         assert_eq!(count, 4);
     }
 
+    #[test]
+    fn test_init_db_migrates_old_domains_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE domains (domain TEXT PRIMARY KEY, token TEXT NOT NULL UNIQUE, created_at INTEGER NOT NULL);
+             INSERT INTO domains VALUES ('example.com', 'tok', 0);",
+        )
+        .unwrap();
+        init_db(&conn);
+
+        let opts = conn
+            .query_row(
+                "SELECT inline_css, sanitize_html, smime_sign FROM domains WHERE domain = 'example.com'",
+                [],
+                |r| domain_options_from_row(r, 0),
+            )
+            .unwrap();
+        assert!(!opts.inline_css && !opts.sanitize_html);
+
+        // Running again is a no-op
+        init_db(&conn);
+    }
+
     #[test]
     fn test_seed_domains() {
         let conn = Connection::open_in_memory().unwrap();
@@ -1060,6 +2276,26 @@ mod tests {
             .query_row("SELECT COUNT(*) FROM domains", [], |r| r.get(0))
             .unwrap();
         assert_eq!(count, 2);
+
+        // A pinned token and configured options win over the stored ones
+        let seed = config::DomainSeed {
+            token: Some("pinned-token-0123".into()),
+            options: DomainOptionsRequest {
+                inline_css: Some(true),
+                ..Default::default()
+            },
+            ..config::DomainSeed::from("example.com")
+        };
+        seed_domains(&conn, &[seed]);
+        let (token, opts) = conn
+            .query_row(
+                "SELECT token, inline_css, sanitize_html, smime_sign FROM domains WHERE domain = 'example.com'",
+                [],
+                |r| Ok((r.get::<_, String>(0)?, domain_options_from_row(r, 1)?)),
+            )
+            .unwrap();
+        assert_eq!(token, "pinned-token-0123");
+        assert!(opts.inline_css && !opts.sanitize_html);
     }
 
     #[test]
@@ -1137,6 +2373,42 @@ mod tests {
         assert_eq!(status, "pending");
     }
 
+    #[test]
+    fn test_build_message_with_calendar() {
+        let content = EmailContent {
+            subject: "Invite".into(),
+            body: "See invite".into(),
+            html: None,
+            calendar: Some("BEGIN:VCALENDAR\r\nMETHOD:CANCEL\r\nUID:x\r\nEND:VCALENDAR\r\n".into()),
+            ..Default::default()
+        };
+        let message =
+            build_message("q1", "a@example.com", &["b@example.org".into()], &content, &MessageKeys::default())
+                .unwrap();
+        let raw = String::from_utf8(message.formatted()).unwrap();
+        assert!(raw.contains("Content-Type: multipart/alternative"), "{raw}");
+        assert!(raw.contains("Content-Type: text/calendar; method=CANCEL; charset=utf-8"), "{raw}");
+        assert!(raw.contains("METHOD:CANCEL"), "{raw}");
+    }
+
+    #[test]
+    fn test_build_message_list_unsubscribe() {
+        let content = EmailContent {
+            subject: "News".into(),
+            body: "Hello".into(),
+            unsubscribe_url: Some("https://mail.example.com/unsubscribe/abc.def".into()),
+            ..Default::default()
+        };
+        let message =
+            build_message("q1", "a@example.com", &["b@example.org".into()], &content, &MessageKeys::default())
+                .unwrap();
+        let raw = String::from_utf8(message.formatted()).unwrap();
+        assert!(raw.contains("List-Unsubscribe: <https://mail.example.com/unsubscribe/abc.def>\r\n"), "{raw}");
+        assert!(raw.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n"), "{raw}");
+        // The queue id is recoverable from bounces
+        assert!(raw.contains("Message-ID: <q1@example.com>\r\n"), "{raw}");
+    }
+
     #[test]
     fn test_now_millis() {
         let ms = now_millis();
@@ -1223,4 +2495,176 @@ mod tests {
         assert_eq!(save2, 1);
         assert_eq!(save3, 1);
     }
+
+    #[tokio::test]
+    async fn test_smtp_credentials_checked_before_saving() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let mut config = Config::defaults();
+        config.smtp_host = "127.0.0.1".into();
+        config.smtp_port = 1;
+        let state = app_state(config, conn);
+        let creds = || SmtpRequest {
+            user: "user@proton.me".into(),
+            pass: "secret".into(),
+        };
+
+        let (status, _) = set_smtp_handler(State(Arc::clone(&state)), Query(SmtpSetQuery { verify: None }), Json(creds()))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(state.smtp_creds.read().await.user.is_empty());
+
+        let (status, Json(ready)) = ready_handler(State(Arc::clone(&state))).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(ready.error.is_some());
+
+        let (status, _) =
+            set_smtp_handler(State(Arc::clone(&state)), Query(SmtpSetQuery { verify: Some(false) }), Json(creds()))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.smtp_creds.read().await.user, "user@proton.me");
+        assert!(state.readiness.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_email_dry_run() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        seed_domains(&conn, &["example.com".into()]);
+        let token: String = conn
+            .query_row("SELECT token FROM domains WHERE domain = 'example.com'", [], |r| r.get(0))
+            .unwrap();
+        let state = app_state(Config::defaults(), conn);
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", format!("Bearer {token}").parse().unwrap());
+        let request = || {
+            serde_json::from_value::<EmailRequest>(serde_json::json!({
+                "from": "noreply@example.com",
+                "to": ["ada@example.org"],
+                "subject": "Dry run",
+                "html": "<p>Hello <b>Ada</b></p>",
+            }))
+            .unwrap()
+        };
+        // Even with sync, nothing goes to the (absent) relay.
+        let query = || SendQuery {
+            sync: Some(true),
+            save: None,
+            dry_run: Some(true),
+        };
+
+        let (status, Json(response)) =
+            email_handler(State(Arc::clone(&state)), headers.clone(), Query(query()), Json(request()))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.status, "dry_run");
+        let message = &response.messages[0];
+        assert_eq!(message.id, response.id);
+        let subject = message.headers.iter().find(|h| h.name == "Subject").unwrap();
+        assert_eq!(subject.value, "Dry run");
+        assert!(message.raw.contains(&format!("Message-ID: <{}@example.com>", response.id)));
+        assert!(message.raw.contains("multipart/alternative"));
+
+        let queued: i64 = state
+            .db
+            .lock()
+            .await
+            .query_row("SELECT COUNT(*) FROM email_queue", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(queued, 0);
+
+        // Validation and authorization still apply.
+        let (status, _) =
+            email_handler(State(Arc::clone(&state)), HeaderMap::new(), Query(query()), Json(request()))
+                .await
+                .unwrap_err();
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_list_send_refuses_sync() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        seed_domains(&conn, &["example.com".into()]);
+        let token: String = conn
+            .query_row("SELECT token FROM domains WHERE domain = 'example.com'", [], |r| r.get(0))
+            .unwrap();
+        let mut config = Config::defaults();
+        config.public_url = Some("https://mayl.example.com".into());
+        let state = app_state(config, conn);
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", format!("Bearer {token}").parse().unwrap());
+        let request = serde_json::from_value::<EmailRequest>(serde_json::json!({
+            "from": "news@example.com",
+            "to": ["ada@example.org", "bob@example.org"],
+            "subject": "Issue 1",
+            "body": "Hello",
+            "list": "news",
+        }))
+        .unwrap();
+        let query = SendQuery {
+            sync: Some(true),
+            save: None,
+            dry_run: None,
+        };
+
+        let (status, Json(err)) = email_handler(State(Arc::clone(&state)), headers, Query(query), Json(request))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(err.error, "list cannot be combined with sync=true");
+    }
+
+    #[test]
+    fn test_deleted_domain_takes_its_rows() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        seed_domains(&conn, &["example.com".into(), "other.com".into()]);
+        for domain in ["example.com", "other.com"] {
+            conn.execute_batch(&format!(
+                "INSERT INTO keys VALUES ('pgp_secret', '{domain}', 'f', 'a', NULL, 0);
+                 INSERT INTO smime_certs (domain, certificate, private_key, created_at) VALUES ('{domain}', 'c', 'k', 0);
+                 INSERT INTO dkim_keys VALUES ('{domain}', 'mayl', 'rsa', 'k', 0);
+                 INSERT INTO suppressions VALUES ('{domain}', 'gone@example.org', 'manual', 0);
+                 INSERT INTO unsubscribes VALUES ('{domain}', 'news', 'ada@example.org', 0);
+                 INSERT INTO dns_reports VALUES ('{domain}', 'pass', '{{}}', 0);
+                 INSERT INTO webhooks VALUES ('wh-{domain}', '{domain}', 'https://hooks.test', 's', '[]', 0);
+                 INSERT INTO webhook_deliveries (id, webhook_id, event, payload, created_at, next_attempt_at)
+                      VALUES ('d-{domain}', 'wh-{domain}', 'sent', '{{}}', 0, 0);
+                 INSERT INTO sent_messages (message_id, queue_id, domain, from_addr, to_addrs, sent_at)
+                      VALUES ('m-{domain}', 'q', '{domain}', 'a@{domain}', '[]', 0);
+                 INSERT INTO inbound_routes VALUES ('r-{domain}', '{domain}', '*', 'https://hooks.test', 's', 0);
+                 INSERT INTO inbound_log (uid_validity, uid, route_id, recipient, received_at)
+                      VALUES (1, length('{domain}'), 'r-{domain}', 'in@{domain}', 0);
+                 INSERT INTO domain_upstreams VALUES ('{domain}', 0, 'default');"
+            ))
+            .unwrap();
+        }
+
+        assert!(delete_domain(&mut conn, "example.com").unwrap());
+        assert!(!delete_domain(&mut conn, "example.com").unwrap());
+        seed_domains(&conn, &["example.com".into()]);
+
+        let count = |sql: &str, domain: &str| -> i64 { conn.query_row(sql, [domain], |r| r.get(0)).unwrap() };
+        for sql in [
+            "SELECT COUNT(*) FROM keys WHERE owner = ?1",
+            "SELECT COUNT(*) FROM smime_certs WHERE domain = ?1",
+            "SELECT COUNT(*) FROM dkim_keys WHERE domain = ?1",
+            "SELECT COUNT(*) FROM suppressions WHERE domain = ?1",
+            "SELECT COUNT(*) FROM unsubscribes WHERE domain = ?1",
+            "SELECT COUNT(*) FROM dns_reports WHERE domain = ?1",
+            "SELECT COUNT(*) FROM webhooks WHERE domain = ?1",
+            "SELECT COUNT(*) FROM webhook_deliveries WHERE webhook_id = 'wh-' || ?1",
+            "SELECT COUNT(*) FROM sent_messages WHERE domain = ?1",
+            "SELECT COUNT(*) FROM inbound_routes WHERE domain = ?1",
+            "SELECT COUNT(*) FROM inbound_log WHERE route_id = 'r-' || ?1",
+            "SELECT COUNT(*) FROM domain_upstreams WHERE domain = ?1",
+        ] {
+            assert_eq!(count(sql, "example.com"), 0, "{sql}");
+            assert_eq!(count(sql, "other.com"), 1, "{sql}");
+        }
+    }
 }
@@ -0,0 +1,241 @@
+use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd, html};
+use serde_json::json;
+
+use crate::{EmailContent, templates};
+
+/// Layout used when `MAYL_MARKDOWN_LAYOUT` is not set. `{{ content }}` is
+/// replaced with the rendered Markdown and `{{ subject }}` with the escaped
+/// subject line.
+pub(crate) const DEFAULT_LAYOUT: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{ subject }}</title>
+</head>
+<body style="margin:0;padding:0;background:#f6f6f6;">
+<div style="max-width:600px;margin:0 auto;padding:24px;background:#ffffff;font-family:-apple-system,Segoe UI,Helvetica,Arial,sans-serif;font-size:16px;line-height:1.5;color:#222222;">
+{{ content }}
+</div>
+</body>
+</html>
+"#;
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS
+}
+
+/// Renders a Markdown body into a plain-text part and an HTML part wrapped in
+/// `layout`.
+pub(crate) fn render(
+    markdown: &str,
+    subject: &str,
+    layout: &str,
+) -> Result<EmailContent, String> {
+    let mut content = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut content, Parser::new_ext(markdown, options()));
+
+    let data = json!({
+        "content": content,
+        "subject": templates::escape_html(subject),
+    });
+    let html = templates::render(layout, &data, false)
+        .map_err(|e| format!("markdown layout: {e}"))?;
+
+    Ok(EmailContent {
+        subject: subject.to_string(),
+        body: to_text(markdown),
+        html: Some(html),
+        ..Default::default()
+    })
+}
+
+/// Accumulates plain text, prefixing each new line with the current
+/// blockquote/list indentation.
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    prefix: String,
+    at_line_start: bool,
+}
+
+impl TextWriter {
+    fn write(&mut self, s: &str) {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            if !line.is_empty() {
+                if self.at_line_start || self.out.is_empty() {
+                    self.out.push_str(&self.prefix);
+                    self.at_line_start = false;
+                }
+                self.out.push_str(line);
+            }
+        }
+    }
+
+    fn newline(&mut self) {
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    fn ensure_newline(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.newline();
+        }
+    }
+
+    fn end_block(&mut self) {
+        self.ensure_newline();
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.newline();
+        }
+    }
+}
+
+/// Renders Markdown to readable plain text: emphasis markers are dropped,
+/// links become `text (url)`, list markers and block quotes are kept.
+pub(crate) fn to_text(markdown: &str) -> String {
+    let mut w = TextWriter::default();
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    let mut links: Vec<(String, usize)> = Vec::new();
+    let mut heading_start = 0;
+    let mut in_code_block = false;
+
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => {
+                w.ensure_newline();
+                heading_start = w.out.len();
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                let width = w.out[heading_start..].chars().count();
+                let underline = match level {
+                    HeadingLevel::H1 => Some('='),
+                    HeadingLevel::H2 => Some('-'),
+                    _ => None,
+                };
+                if let Some(c) = underline {
+                    w.newline();
+                    w.write(&c.to_string().repeat(width));
+                }
+                w.end_block();
+            }
+            Event::End(TagEnd::Paragraph) => {
+                if lists.is_empty() {
+                    w.end_block();
+                } else {
+                    w.ensure_newline();
+                }
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                w.ensure_newline();
+                w.prefix.push_str("> ");
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                let len = w.prefix.len().saturating_sub(2);
+                w.prefix.truncate(len);
+                w.end_block();
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                w.ensure_newline();
+                w.prefix.push_str("    ");
+                in_code_block = true;
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                let len = w.prefix.len().saturating_sub(4);
+                w.prefix.truncate(len);
+                in_code_block = false;
+                w.end_block();
+            }
+            Event::Start(Tag::List(start)) => {
+                w.ensure_newline();
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    w.end_block();
+                }
+            }
+            Event::Start(Tag::Item) => {
+                w.ensure_newline();
+                let depth = lists.len().saturating_sub(1);
+                let marker = match lists.last_mut() {
+                    Some(Some(n)) => {
+                        let m = format!("{n}. ");
+                        *n += 1;
+                        m
+                    }
+                    _ => "- ".to_string(),
+                };
+                w.write(&format!("{}{marker}", "  ".repeat(depth)));
+            }
+            Event::End(TagEnd::Item) => w.ensure_newline(),
+            Event::Start(Tag::Link { dest_url, .. }) | Event::Start(Tag::Image { dest_url, .. }) => {
+                links.push((dest_url.to_string(), w.out.len()));
+            }
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => {
+                if let Some((url, start)) = links.pop() {
+                    let text = &w.out[start..];
+                    if !url.is_empty() && text != url && !url.starts_with('#') {
+                        w.write(&format!(" ({url})"));
+                    }
+                }
+            }
+            Event::Text(t) => {
+                let t = if in_code_block {
+                    t.trim_end_matches('\n')
+                } else {
+                    &t
+                };
+                w.write(t);
+            }
+            Event::Code(t) => w.write(&t),
+            Event::SoftBreak | Event::HardBreak => w.newline(),
+            Event::Rule => {
+                w.ensure_newline();
+                w.write("----");
+                w.end_block();
+            }
+            Event::TaskListMarker(done) => w.write(if done { "[x] " } else { "[ ] " }),
+            Event::End(TagEnd::TableCell) => w.write("  "),
+            Event::End(TagEnd::TableHead) | Event::End(TagEnd::TableRow) => w.ensure_newline(),
+            Event::End(TagEnd::Table) => w.end_block(),
+            _ => {}
+        }
+    }
+
+    let mut text = w.out.trim_end().to_string();
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_text() {
+        let md = "# Welcome\n\nHello **Ada**, see [the docs](https://example.com/docs).\n\n\
+                  - one\n- two\n\n1. first\n2. second\n\n> quoted\n\n```\ncode\n```\n";
+        assert_eq!(
+            to_text(md),
+            "Welcome\n=======\n\nHello Ada, see the docs (https://example.com/docs).\n\n\
+             - one\n- two\n\n1. first\n2. second\n\n> quoted\n\n    code\n"
+        );
+    }
+
+    #[test]
+    fn test_render_wraps_layout() {
+        let content = render("Hi *there*", "A & B", "<title>{{ subject }}</title>{{ content }}")
+            .unwrap();
+        assert_eq!(
+            content.html.unwrap(),
+            "<title>A &amp; B</title><p>Hi <em>there</em></p>\n"
+        );
+        assert_eq!(content.body, "Hi there\n");
+        assert_eq!(content.subject, "A & B");
+    }
+}
@@ -0,0 +1,32 @@
+use lettre::message::{MultiPart, MultiPartBuilder, SinglePart, header::ContentType};
+
+/// A MIME entity that may be wrapped for signing or encryption; lettre keeps
+/// its own equivalent private.
+pub(crate) enum Part {
+    Single(SinglePart),
+    Multi(MultiPart),
+}
+
+/// The exact bytes of a MIME entity as it appears inside a multipart body.
+/// The CRLF lettre writes after it belongs to the next boundary delimiter.
+pub(crate) fn entity_bytes(part: &Part) -> Vec<u8> {
+    let mut out = match part {
+        Part::Single(p) => p.formatted(),
+        Part::Multi(m) => m.formatted(),
+    };
+    if out.ends_with(b"\r\n") {
+        out.truncate(out.len() - 2);
+    }
+    out
+}
+
+pub(crate) fn with_part(builder: MultiPartBuilder, part: Part) -> MultiPart {
+    match part {
+        Part::Single(p) => builder.singlepart(p),
+        Part::Multi(m) => builder.multipart(m),
+    }
+}
+
+pub(crate) fn content_type(s: &str) -> ContentType {
+    ContentType::parse(s).expect("static content type")
+}
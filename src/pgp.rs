@@ -0,0 +1,673 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use lettre::message::{MultiPart, SinglePart, header::ContentDisposition};
+use pgp::{
+    composed::{
+        ArmorOptions, Deserializable, DetachedSignature, MessageBuilder, SignedPublicKey,
+        SignedSecretKey,
+    },
+    crypto::{hash::HashAlgorithm, sym::SymmetricKeyAlgorithm},
+    types::{KeyDetails, Password, SigningKey},
+};
+use rand::thread_rng;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    ApiError, AppState, api_error,
+    mime::{Part, content_type, entity_bytes, with_part},
+    normalize_address, now_millis,
+};
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+/// What to do with PGP for a single message. Stored with the queued message
+/// so the worker applies the same protection later.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct PgpOptions {
+    /// Sign with the sending domain's key.
+    pub(crate) sign: bool,
+    /// Encrypt when every recipient has a known public key.
+    pub(crate) encrypt: bool,
+    /// Encrypt, and fail instead of sending in the clear if any key is missing.
+    pub(crate) require_encryption: bool,
+}
+
+impl PgpOptions {
+    pub(crate) fn is_empty(&self) -> bool {
+        !self.sign && !self.encrypt && !self.require_encryption
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SigningKeyRequest {
+    private_key: String,
+    #[serde(default)]
+    passphrase: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct SigningKeyResponse {
+    domain: String,
+    fingerprint: String,
+    public_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PublicKeyRequest {
+    address: String,
+    public_key: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PublicKeyEntry {
+    address: String,
+    fingerprint: String,
+    created_at: i64,
+}
+
+/// Keys resolved for one message. Empty when no PGP protection applies.
+#[derive(Default)]
+pub(crate) struct Keys {
+    signing: Option<(SignedSecretKey, Password)>,
+    recipients: Vec<SignedPublicKey>,
+}
+
+// ── Database ────────────────────────────────────────────────────────────────
+
+const KIND_SECRET: &str = "pgp_secret";
+const KIND_PUBLIC: &str = "pgp_public";
+
+pub(crate) fn init_db(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS keys (
+            kind TEXT NOT NULL,
+            owner TEXT NOT NULL,
+            fingerprint TEXT NOT NULL,
+            armored TEXT NOT NULL,
+            passphrase TEXT,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (kind, owner)
+        );",
+    )
+    .expect("failed to initialize keys table");
+}
+
+/// Removes a deleted domain's signing key. Recipients' public keys are not
+/// tied to a sending domain and stay.
+pub(crate) fn delete_domain(conn: &Connection, domain: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM keys WHERE kind = ?1 AND owner = ?2", [KIND_SECRET, domain])
+        .map(|_| ())
+}
+
+fn store_key(
+    conn: &Connection,
+    kind: &str,
+    owner: &str,
+    fingerprint: &str,
+    armored: &str,
+    passphrase: Option<&str>,
+) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO keys (kind, owner, fingerprint, armored, passphrase, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(kind, owner) DO UPDATE SET
+            fingerprint = excluded.fingerprint,
+            armored = excluded.armored,
+            passphrase = excluded.passphrase,
+            created_at = excluded.created_at",
+        rusqlite::params![kind, owner, fingerprint, armored, passphrase, now_millis()],
+    )
+}
+
+fn load_key(conn: &Connection, kind: &str, owner: &str) -> Option<(String, Option<String>)> {
+    conn.query_row(
+        "SELECT armored, passphrase FROM keys WHERE kind = ?1 AND owner = ?2",
+        [kind, owner],
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )
+    .optional()
+    .ok()
+    .flatten()
+}
+
+/// Looks up the keys needed to apply `opts` to a message from `domain`.
+/// Opportunistic encryption silently falls back to cleartext when any
+/// recipient has no key; required encryption turns that into an error.
+pub(crate) fn load_keys(
+    conn: &Connection,
+    domain: &str,
+    to: &[String],
+    opts: &PgpOptions,
+) -> Result<Keys, String> {
+    let mut keys = Keys::default();
+
+    if opts.sign {
+        let (armored, passphrase) = load_key(conn, KIND_SECRET, domain)
+            .ok_or_else(|| format!("no PGP signing key configured for '{domain}'"))?;
+        let passphrase = Password::from(passphrase.unwrap_or_default());
+        let key = parse_secret(&armored, &passphrase)?;
+        keys.signing = Some((key, passphrase));
+    }
+
+    if opts.encrypt || opts.require_encryption {
+        let mut recipients = Vec::with_capacity(to.len());
+        let mut missing = Vec::new();
+        for addr in to {
+            let address = normalize_address(addr)?;
+            match load_key(conn, KIND_PUBLIC, &address) {
+                Some((armored, _)) => recipients.push(parse_public(&armored)?),
+                None => missing.push(address),
+            }
+        }
+
+        if missing.is_empty() {
+            keys.recipients = recipients;
+        } else if opts.require_encryption {
+            return Err(format!(
+                "encryption required but no PGP key is known for: {}",
+                missing.join(", ")
+            ));
+        }
+    }
+
+    Ok(keys)
+}
+
+// ── Keys ────────────────────────────────────────────────────────────────────
+
+fn parse_secret(armored: &str, passphrase: &Password) -> Result<SignedSecretKey, String> {
+    let (key, _) =
+        SignedSecretKey::from_string(armored).map_err(|e| format!("invalid private key: {e}"))?;
+    key.verify_bindings()
+        .map_err(|e| format!("invalid private key: {e}"))?;
+    // Unlocking is the only way to find out whether the passphrase is right.
+    DetachedSignature::sign_binary_data(
+        thread_rng(),
+        &Box::new(signer(&key)),
+        passphrase,
+        HashAlgorithm::Sha256,
+        &b""[..],
+    )
+    .map_err(|e| format!("cannot sign with private key: {e}"))?;
+    Ok(key)
+}
+
+fn parse_public(armored: &str) -> Result<SignedPublicKey, String> {
+    let (key, _) =
+        SignedPublicKey::from_string(armored).map_err(|e| format!("invalid public key: {e}"))?;
+    key.verify_bindings()
+        .map_err(|e| format!("invalid public key: {e}"))?;
+    Ok(key)
+}
+
+fn fingerprint(key: &impl KeyDetails) -> String {
+    key.fingerprint()
+        .as_bytes()
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect()
+}
+
+/// Prefers a subkey flagged for signing, as GnuPG does, over the primary key.
+/// pgp only implements `SigningKey` for the boxed form of the trait object.
+fn signer(key: &SignedSecretKey) -> &dyn SigningKey {
+    key.secret_subkeys
+        .iter()
+        .find(|sk| sk.signatures.iter().any(|s| s.key_flags().sign()))
+        .map(|sk| &sk.key as &dyn SigningKey)
+        .unwrap_or(&key.primary_key)
+}
+
+// ── PGP/MIME (RFC 3156) ─────────────────────────────────────────────────────
+
+/// Signs and/or encrypts a message body according to `keys`. Signing happens
+/// first so that the signature is protected by the encryption.
+pub(crate) fn protect(part: Part, keys: &Keys) -> Result<Part, String> {
+    let mut part = part;
+    if let Some((key, passphrase)) = &keys.signing {
+        part = sign(part, key, passphrase)?;
+    }
+    if !keys.recipients.is_empty() {
+        part = encrypt(part, &keys.recipients)?;
+    }
+    Ok(part)
+}
+
+fn sign(part: Part, key: &SignedSecretKey, passphrase: &Password) -> Result<Part, String> {
+    let data = entity_bytes(&part);
+    let signature = DetachedSignature::sign_binary_data(
+        thread_rng(),
+        &Box::new(signer(key)),
+        passphrase,
+        HashAlgorithm::Sha256,
+        &data[..],
+    )
+    .map_err(|e| format!("pgp sign: {e}"))?
+    .to_armored_string(ArmorOptions::default())
+    .map_err(|e| format!("pgp sign: {e}"))?;
+
+    let signed = with_part(
+        MultiPart::signed("application/pgp-signature".into(), "pgp-sha256".into()),
+        part,
+    )
+    .singlepart(
+        SinglePart::builder()
+            .header(content_type(
+                "application/pgp-signature; name=\"signature.asc\"",
+            ))
+            .header(ContentDisposition::attachment("signature.asc"))
+            .body(signature),
+    );
+    Ok(Part::Multi(signed))
+}
+
+fn encrypt(part: Part, recipients: &[SignedPublicKey]) -> Result<Part, String> {
+    let data = entity_bytes(&part);
+    let mut builder =
+        MessageBuilder::from_bytes("", data).seipd_v1(thread_rng(), SymmetricKeyAlgorithm::AES256);
+
+    for key in recipients {
+        let subkey = key.public_subkeys.iter().find(|sk| {
+            sk.signatures.iter().any(|s| {
+                let flags = s.key_flags();
+                flags.encrypt_comms() || flags.encrypt_storage()
+            })
+        });
+        match subkey {
+            Some(sk) => builder.encrypt_to_key(thread_rng(), sk),
+            None => builder.encrypt_to_key(thread_rng(), key),
+        }
+        .map_err(|e| format!("pgp encrypt to {}: {e}", fingerprint(key)))?;
+    }
+
+    let armored = builder
+        .to_armored_string(thread_rng(), ArmorOptions::default())
+        .map_err(|e| format!("pgp encrypt: {e}"))?;
+
+    let encrypted = MultiPart::encrypted("application/pgp-encrypted".into())
+        .singlepart(
+            SinglePart::builder()
+                .header(content_type("application/pgp-encrypted"))
+                .body(String::from("Version: 1\r\n")),
+        )
+        .singlepart(
+            SinglePart::builder()
+                .header(content_type(
+                    "application/octet-stream; name=\"encrypted.asc\"",
+                ))
+                .header(ContentDisposition::inline_with_name("encrypted.asc"))
+                .body(armored),
+        );
+    Ok(Part::Multi(encrypted))
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+fn db_error(e: rusqlite::Error) -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}"))
+}
+
+fn signing_key_response(
+    domain: String,
+    key: &SignedSecretKey,
+) -> Result<SigningKeyResponse, ApiError> {
+    let public_key = SignedPublicKey::from(key.clone())
+        .to_armored_string(ArmorOptions::default())
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("pgp: {e}")))?;
+    Ok(SigningKeyResponse {
+        domain,
+        fingerprint: fingerprint(&key.primary_key),
+        public_key,
+    })
+}
+
+pub(crate) async fn put_signing_key_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+    Json(payload): Json<SigningKeyRequest>,
+) -> Result<Json<SigningKeyResponse>, ApiError> {
+    let domain = domain.to_lowercase();
+    let key = parse_secret(
+        &payload.private_key,
+        &Password::from(payload.passphrase.as_str()),
+    )
+    .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+
+    let db = state.db.lock().await;
+    let exists: bool = db
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM domains WHERE domain = ?1",
+            [&domain],
+            |r| r.get(0),
+        )
+        .map_err(db_error)?;
+    if !exists {
+        return Err(api_error(StatusCode::NOT_FOUND, "domain not found"));
+    }
+
+    let response = signing_key_response(domain, &key)?;
+    store_key(
+        &db,
+        KIND_SECRET,
+        &response.domain,
+        &response.fingerprint,
+        &payload.private_key,
+        Some(&payload.passphrase),
+    )
+    .map_err(db_error)?;
+
+    info!(
+        domain = response.domain,
+        fingerprint = response.fingerprint,
+        "PGP signing key set"
+    );
+    Ok(Json(response))
+}
+
+pub(crate) async fn get_signing_key_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Result<Json<SigningKeyResponse>, ApiError> {
+    let domain = domain.to_lowercase();
+    let (armored, _) = {
+        let db = state.db.lock().await;
+        load_key(&db, KIND_SECRET, &domain)
+            .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "no signing key for domain"))?
+    };
+    let (key, _) = SignedSecretKey::from_string(&armored).map_err(|e| {
+        api_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("stored key: {e}"),
+        )
+    })?;
+    signing_key_response(domain, &key).map(Json)
+}
+
+pub(crate) async fn delete_signing_key_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let deleted = db
+        .execute(
+            "DELETE FROM keys WHERE kind = ?1 AND owner = ?2",
+            [KIND_SECRET, &domain],
+        )
+        .unwrap_or(0);
+
+    if deleted == 0 {
+        Err(api_error(
+            StatusCode::NOT_FOUND,
+            "no signing key for domain",
+        ))
+    } else {
+        info!(domain, "PGP signing key removed");
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+pub(crate) async fn add_public_key_handler(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<PublicKeyRequest>,
+) -> Result<(StatusCode, Json<PublicKeyEntry>), ApiError> {
+    let address =
+        normalize_address(&payload.address).map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    let key =
+        parse_public(&payload.public_key).map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    let fingerprint = fingerprint(&key);
+
+    let db = state.db.lock().await;
+    store_key(
+        &db,
+        KIND_PUBLIC,
+        &address,
+        &fingerprint,
+        &payload.public_key,
+        None,
+    )
+    .map_err(db_error)?;
+
+    info!(address, fingerprint, "PGP public key stored");
+    Ok((
+        StatusCode::CREATED,
+        Json(PublicKeyEntry {
+            address,
+            fingerprint,
+            created_at: now_millis(),
+        }),
+    ))
+}
+
+pub(crate) async fn list_public_keys_handler(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<PublicKeyEntry>> {
+    let db = state.db.lock().await;
+    let mut stmt = db
+        .prepare("SELECT owner, fingerprint, created_at FROM keys WHERE kind = ?1 ORDER BY owner")
+        .unwrap();
+    let keys: Vec<PublicKeyEntry> = stmt
+        .query_map([KIND_PUBLIC], |row| {
+            Ok(PublicKeyEntry {
+                address: row.get(0)?,
+                fingerprint: row.get(1)?,
+                created_at: row.get(2)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Json(keys)
+}
+
+pub(crate) async fn delete_public_key_handler(
+    State(state): State<Arc<AppState>>,
+    Path(address): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let address = address.to_lowercase();
+    let db = state.db.lock().await;
+    let deleted = db
+        .execute(
+            "DELETE FROM keys WHERE kind = ?1 AND owner = ?2",
+            [KIND_PUBLIC, &address],
+        )
+        .unwrap_or(0);
+
+    if deleted == 0 {
+        Err(api_error(StatusCode::NOT_FOUND, "no key for address"))
+    } else {
+        info!(address, "PGP public key removed");
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use pgp::{
+        composed::{EncryptionCaps, KeyType, Message, SecretKeyParamsBuilder, SubkeyParamsBuilder},
+        crypto::ecc_curve::ECCCurve,
+    };
+
+    /// A small Curve25519 key with signing and encryption subkeys.
+    pub(crate) fn test_key(uid: &str) -> SignedSecretKey {
+        let mut sign = SubkeyParamsBuilder::default();
+        sign.key_type(KeyType::Ed25519Legacy)
+            .can_sign(true)
+            .can_encrypt(EncryptionCaps::None);
+        let mut enc = SubkeyParamsBuilder::default();
+        enc.key_type(KeyType::ECDH(ECCCurve::Curve25519Legacy))
+            .can_sign(false)
+            .can_encrypt(EncryptionCaps::All);
+
+        let mut params = SecretKeyParamsBuilder::default();
+        params
+            .key_type(KeyType::Ed25519Legacy)
+            .can_certify(true)
+            .can_sign(false)
+            .can_encrypt(EncryptionCaps::None)
+            .primary_user_id(uid.into())
+            .subkeys(vec![sign.build().unwrap(), enc.build().unwrap()]);
+        params.build().unwrap().generate(thread_rng()).unwrap()
+    }
+
+    fn armored_secret(key: &SignedSecretKey) -> String {
+        key.to_armored_string(ArmorOptions::default()).unwrap()
+    }
+
+    fn armored_public(key: &SignedSecretKey) -> String {
+        SignedPublicKey::from(key.clone())
+            .to_armored_string(ArmorOptions::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_load_keys_requirements() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let to = vec![
+            "Bob <bob@example.org>".to_string(),
+            "carol@example.org".to_string(),
+        ];
+
+        let sign = PgpOptions {
+            sign: true,
+            ..Default::default()
+        };
+        assert!(load_keys(&conn, "example.com", &to, &sign).is_err());
+
+        let bob = test_key("Bob <bob@example.org>");
+        store_key(
+            &conn,
+            KIND_PUBLIC,
+            "bob@example.org",
+            &fingerprint(&bob.primary_key),
+            &armored_public(&bob),
+            None,
+        )
+        .unwrap();
+
+        // Carol has no key: opportunistic encryption falls back to cleartext
+        let opportunistic = PgpOptions {
+            encrypt: true,
+            ..Default::default()
+        };
+        assert!(
+            load_keys(&conn, "example.com", &to, &opportunistic)
+                .unwrap()
+                .recipients
+                .is_empty()
+        );
+
+        let required = PgpOptions {
+            require_encryption: true,
+            ..Default::default()
+        };
+        let err = load_keys(&conn, "example.com", &to, &required)
+            .err()
+            .unwrap();
+        assert!(err.contains("carol@example.org"), "{err}");
+        assert_eq!(
+            load_keys(&conn, "example.com", &to[..1], &required)
+                .unwrap()
+                .recipients
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_sign_and_encrypt_roundtrip() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+
+        let sender = test_key("Ada <ada@example.com>");
+        let bob = test_key("Bob <bob@example.org>");
+        store_key(
+            &conn,
+            KIND_SECRET,
+            "example.com",
+            &fingerprint(&sender.primary_key),
+            &armored_secret(&sender),
+            None,
+        )
+        .unwrap();
+        store_key(
+            &conn,
+            KIND_PUBLIC,
+            "bob@example.org",
+            &fingerprint(&bob.primary_key),
+            &armored_public(&bob),
+            None,
+        )
+        .unwrap();
+
+        let opts = PgpOptions {
+            sign: true,
+            require_encryption: true,
+            ..Default::default()
+        };
+        let keys = load_keys(&conn, "example.com", &["bob@example.org".into()], &opts).unwrap();
+        let inner = Part::Single(SinglePart::plain(String::from("hello")));
+        let Part::Multi(protected) = protect(inner, &keys).unwrap() else {
+            panic!("expected multipart");
+        };
+
+        let raw = String::from_utf8(protected.formatted()).unwrap();
+        assert!(raw.contains("multipart/encrypted"), "{raw}");
+        assert!(
+            raw.contains("protocol=\"application/pgp-encrypted\""),
+            "{raw}"
+        );
+
+        // Bob can decrypt to a multipart/signed entity carrying the text
+        let start = raw.find("-----BEGIN PGP MESSAGE-----").unwrap();
+        let end =
+            raw.find("-----END PGP MESSAGE-----").unwrap() + "-----END PGP MESSAGE-----".len();
+        let (message, _) = Message::from_string(&raw[start..end]).unwrap();
+        let mut decrypted = message.decrypt(&Password::empty(), &bob).unwrap();
+        if decrypted.is_compressed() {
+            decrypted = decrypted.decompress().unwrap();
+        }
+        let plain = decrypted.as_data_string().unwrap();
+        assert!(plain.contains("multipart/signed"), "{plain}");
+        assert!(
+            plain.contains("micalg=\"pgp-sha256\"") || plain.contains("micalg=pgp-sha256"),
+            "{plain}"
+        );
+        assert!(plain.contains("hello"), "{plain}");
+    }
+
+    #[test]
+    fn test_signature_covers_entity_bytes() {
+        let sender = test_key("Ada <ada@example.com>");
+        let inner = Part::Single(SinglePart::plain(String::from("signed body")));
+        let expected = entity_bytes(&inner);
+
+        let Part::Multi(signed) = sign(inner, &sender, &Password::empty()).unwrap() else {
+            panic!("expected multipart");
+        };
+        let raw = String::from_utf8(signed.formatted()).unwrap();
+        let start = raw.find("-----BEGIN PGP SIGNATURE-----").unwrap();
+        let end =
+            raw.find("-----END PGP SIGNATURE-----").unwrap() + "-----END PGP SIGNATURE-----".len();
+        let (sig, _) = DetachedSignature::from_string(&raw[start..end]).unwrap();
+
+        let public = SignedPublicKey::from(sender.clone());
+        let verified = public
+            .public_subkeys
+            .iter()
+            .any(|sk| sig.verify(&sk.key, &expected).is_ok());
+        assert!(verified);
+        assert!(raw.contains(std::str::from_utf8(&expected).unwrap()));
+    }
+}
@@ -0,0 +1,192 @@
+//! Operator access to `email_queue`: inspect what is waiting, put failed
+//! messages back in line and clear out what is no longer wanted.
+
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{ApiError, AppState, api_error, extract_domain_from_addr};
+
+const STATUSES: [&str; 3] = ["pending", "sending", "failed"];
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ListQuery {
+    status: Option<String>,
+    /// Only messages sent from this domain.
+    domain: Option<String>,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PurgeQuery {
+    status: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct QueueEntry {
+    id: String,
+    status: String,
+    from: String,
+    to: Vec<String>,
+    subject: String,
+    attempts: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+    created_at: i64,
+}
+
+fn check_status(status: &str) -> Result<(), ApiError> {
+    if STATUSES.contains(&status) {
+        Ok(())
+    } else {
+        Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("status must be one of: {}", STATUSES.join(", ")),
+        ))
+    }
+}
+
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<QueueEntry> {
+    Ok(QueueEntry {
+        id: row.get(0)?,
+        status: row.get(1)?,
+        from: row.get(2)?,
+        to: serde_json::from_str(&row.get::<_, String>(3)?).unwrap_or_default(),
+        subject: row.get(4)?,
+        attempts: row.get(5)?,
+        last_error: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+const COLUMNS: &str = "id, status, from_addr, to_addrs, subject, attempts, last_error, created_at";
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+/// Lists queued messages, oldest first.
+pub(crate) async fn list_queue_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<QueueEntry>>, ApiError> {
+    if let Some(status) = &query.status {
+        check_status(status)?;
+    }
+    let domain = query.domain.map(|d| d.to_lowercase());
+    let limit = query.limit.unwrap_or(100) as usize;
+
+    // The sender's domain is matched in Rust, so display names ("Name
+    // <user@domain>") work; the SQL limit only applies without a filter.
+    let db = state.db.lock().await;
+    let mut stmt = db
+        .prepare(&format!(
+            "SELECT {COLUMNS} FROM email_queue
+             WHERE (?1 IS NULL OR status = ?1)
+             ORDER BY created_at LIMIT ?2"
+        ))
+        .unwrap();
+    let sql_limit = if domain.is_some() { -1 } else { limit as i64 };
+    let entries: Vec<QueueEntry> = stmt
+        .query_map(rusqlite::params![query.status, sql_limit], entry_from_row)
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .filter(|e| domain.is_none() || extract_domain_from_addr(&e.from) == domain)
+        .take(limit)
+        .collect();
+
+    Ok(Json(entries))
+}
+
+/// Returns a failed or retrying message to `pending` with its attempt count
+/// reset, so the queue worker picks it up on its next pass.
+pub(crate) async fn retry_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<QueueEntry>, ApiError> {
+    let db = state.db.lock().await;
+    let status: String = db
+        .query_row("SELECT status FROM email_queue WHERE id = ?1", [&id], |r| r.get(0))
+        .map_err(|_| api_error(StatusCode::NOT_FOUND, "message not found"))?;
+    if status == "sending" {
+        return Err(api_error(StatusCode::CONFLICT, "message is being sent"));
+    }
+
+    let entry = db
+        .query_row(
+            &format!(
+                "UPDATE email_queue SET status = 'pending', attempts = 0, last_error = NULL
+                 WHERE id = ?1 RETURNING {COLUMNS}"
+            ),
+            [&id],
+            entry_from_row,
+        )
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+
+    info!(id, "queued message retried");
+    Ok(Json(entry))
+}
+
+/// Deletes every message with the given status, `failed` by default.
+/// Messages being sent cannot be purged.
+pub(crate) async fn purge_queue_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PurgeQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let status = query.status.as_deref().unwrap_or("failed");
+    check_status(status)?;
+    if status == "sending" {
+        return Err(api_error(StatusCode::BAD_REQUEST, "messages being sent cannot be purged"));
+    }
+
+    let db = state.db.lock().await;
+    let purged = db
+        .execute("DELETE FROM email_queue WHERE status = ?1", [status])
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+
+    info!(status, purged, "queue purged");
+    Ok(Json(serde_json::json!({ "purged": purged })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_list_by_domain() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+        for (id, from) in [
+            ("q1", "Alerts <alerts@example.com>"),
+            ("q2", "noreply@EXAMPLE.com"),
+            ("q4", "noreply@example_com"),
+        ] {
+            conn.execute(
+                "INSERT INTO email_queue (id, status, from_addr, to_addrs, subject, body, created_at)
+                 VALUES (?1, 'pending', ?2, '[]', 's', 'b', ?3)",
+                rusqlite::params![id, from, id[1..].parse::<i64>().unwrap()],
+            )
+            .unwrap();
+        }
+        let state = crate::app_state(crate::Config::defaults(), conn);
+        let list = async |domain: &str, limit| {
+            let query = ListQuery {
+                status: None,
+                domain: Some(domain.into()),
+                limit,
+            };
+            let Json(entries) = list_queue_handler(State(Arc::clone(&state)), Query(query)).await.unwrap();
+            entries.into_iter().map(|e| e.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(list("example.com", None).await, ["q1", "q2"]);
+        assert_eq!(list("example.com", Some(1)).await, ["q1"]);
+        assert_eq!(list("example_com", None).await, ["q4"]);
+    }
+}
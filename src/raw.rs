@@ -0,0 +1,290 @@
+//! Complete messages written by the client rather than built by mayl, from
+//! SMTP submission or `POST /email/raw`. They are queued and relayed as-is,
+//! apart from a DKIM signature when the sending domain has a key.
+
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+};
+use mail_parser::{Message, MessageParser, PartType};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::{
+    ApiError, AppState, EmailContent, ErrorResponse, QueueResponse, RenderedMessage, api_error, authorize_sender,
+    dkim, events, extract_domain_from_addr, extract_token, now_millis, recipients, suppressions, webhooks,
+};
+
+/// Largest raw message accepted, in bytes.
+pub(crate) const MAX_SIZE: usize = 25 * 1024 * 1024;
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RawQuery {
+    /// Envelope sender; defaults to the `From` header.
+    from: Option<String>,
+    /// Comma-separated envelope recipients; default to `To`, `Cc` and `Bcc`.
+    to: Option<String>,
+    save: Option<bool>,
+    /// Validate and prepare the message, then return it instead of queuing.
+    dry_run: Option<bool>,
+}
+
+// ── Preparation ─────────────────────────────────────────────────────────────
+
+fn parse(raw: &[u8]) -> Result<Message<'_>, ApiError> {
+    MessageParser::default()
+        .parse(raw)
+        .ok_or_else(|| api_error(StatusCode::BAD_REQUEST, "message could not be parsed"))
+}
+
+fn header_from<'a>(message: &'a Message) -> Result<&'a str, ApiError> {
+    message
+        .from()
+        .and_then(|f| f.first())
+        .and_then(|a| a.address())
+        .ok_or_else(|| api_error(StatusCode::BAD_REQUEST, "message has no From header"))
+}
+
+/// Everyone in `To`, `Cc` and `Bcc`, as `sendmail -t` would send to.
+fn header_recipients(message: &Message) -> Vec<String> {
+    [message.to(), message.cc(), message.bcc()]
+        .into_iter()
+        .flatten()
+        .flat_map(|list| list.iter().filter_map(|a| a.address()).map(str::to_string))
+        .collect()
+}
+
+/// Removes every `name` header, with its continuation lines, from the
+/// header section of `raw`.
+fn strip_header(raw: &[u8], name: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut rest = raw;
+    let mut skipping = false;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|&b| b == b'\n').map_or(rest.len(), |i| i + 1);
+        let (line, tail) = rest.split_at(end);
+        if line == b"\r\n" || line == b"\n" {
+            out.extend_from_slice(rest);
+            break;
+        }
+        if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+            skipping = line.len() > name.len()
+                && line[name.len()] == b':'
+                && line[..name.len()].eq_ignore_ascii_case(name.as_bytes());
+        }
+        if !skipping {
+            out.extend_from_slice(line);
+        }
+        rest = tail;
+    }
+    out
+}
+
+/// Turns bare `\n` line endings into the `\r\n` SMTP and DKIM expect, as
+/// messages written by scripts often use.
+pub(crate) fn to_crlf(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    for (i, &b) in raw.iter().enumerate() {
+        if b == b'\n' && (i == 0 || raw[i - 1] != b'\r') {
+            out.push(b'\r');
+        }
+        out.push(b);
+    }
+    out
+}
+
+/// Checks that a raw message is sent as `domain`, drops its `Bcc` header and
+/// adds a `Message-ID` and `Date` when missing, as a submission server does
+/// (RFC 6409 §8). Returns the content to queue under `id`.
+pub(crate) fn prepare(id: &str, domain: &str, from: &str, raw: &[u8]) -> Result<EmailContent, ApiError> {
+    let raw = &to_crlf(raw);
+    let message = parse(raw)?;
+    let header_domain = extract_domain_from_addr(header_from(&message)?);
+    if header_domain.as_deref() != Some(domain) {
+        return Err(api_error(
+            StatusCode::FORBIDDEN,
+            format!("From header must use the domain '{domain}'"),
+        ));
+    }
+
+    let mut missing = String::new();
+    if message.message_id().is_none() {
+        missing.push_str(&format!("Message-ID: {}\r\n", crate::message_id(id, from)));
+    }
+    if message.date().is_none() {
+        let date = mail_parser::DateTime::from_timestamp(now_millis() / 1000);
+        missing.push_str(&format!("Date: {}\r\n", date.to_rfc822()));
+    }
+    let mut prepared = missing.into_bytes();
+    prepared.extend(strip_header(raw, "Bcc"));
+
+    let has_html = message.parts.iter().any(|p| matches!(p.body, PartType::Html(_)));
+    Ok(EmailContent {
+        subject: message.subject().unwrap_or_default().to_string(),
+        body: message.body_text(0).unwrap_or_default().into_owned(),
+        html: has_html.then(|| message.body_html(0)).flatten().map(|h| h.into_owned()),
+        raw: Some(prepared),
+        ..Default::default()
+    })
+}
+
+/// Adds a prepared message to `email_queue` and announces it.
+pub(crate) async fn enqueue(
+    state: &AppState,
+    domain: &str,
+    id: &str,
+    from: &str,
+    to: &[String],
+    content: &EmailContent,
+    save: bool,
+) -> rusqlite::Result<()> {
+    let to_json = serde_json::to_string(to).unwrap();
+    state.db.lock().await.execute(
+        "INSERT INTO email_queue (id, status, from_addr, to_addrs, subject, body, html, raw, created_at, save)
+         VALUES (?1, 'pending', ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        rusqlite::params![id, from, &to_json, &content.subject, &content.body, &content.html, &content.raw, now_millis(), save],
+    )?;
+
+    let event = webhooks::EmailEvent {
+        id,
+        from,
+        to,
+        error: None,
+    };
+    events::publish(&state.events, events::Kind::Queued, domain, &event);
+    Ok(())
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+/// Queues a complete RFC 5322 message sent as the request body.
+pub(crate) async fn raw_email_handler(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<RawQuery>,
+    body: Bytes,
+) -> Result<(StatusCode, Json<QueueResponse>), ApiError> {
+    let token = extract_token(&headers)
+        .ok_or_else(|| api_error(StatusCode::UNAUTHORIZED, "missing Authorization header"))?;
+
+    let (from, to) = {
+        let message = parse(&body)?;
+        let from = match query.from.filter(|f| !f.is_empty()) {
+            Some(from) => from,
+            None => header_from(&message)?.to_string(),
+        };
+        let to: Vec<String> = match &query.to {
+            Some(to) => to.split(',').map(|a| a.trim().to_string()).filter(|a| !a.is_empty()).collect(),
+            None => header_recipients(&message),
+        };
+        (from, to)
+    };
+
+    let (domain, _) = {
+        let db = state.db.lock().await;
+        authorize_sender(&db, &token, &from)?
+    };
+    if to.is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "message has no recipients"));
+    }
+    let to = recipients::validate(&to, &state.config.recipient_policy).map_err(|rejected| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("{} recipient(s) rejected", rejected.len()),
+                rejected,
+            }),
+        )
+    })?;
+    let (to, suppressed) = {
+        let db = state.db.lock().await;
+        suppressions::partition(&db, &domain, &to).map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?
+    };
+    if to.is_empty() {
+        return Err(api_error(StatusCode::UNPROCESSABLE_ENTITY, "all recipients are suppressed"));
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let content = prepare(&id, &domain, &from, &body)?;
+    if query.dry_run.unwrap_or(false) {
+        let signed = {
+            let db = state.db.lock().await;
+            dkim::sign_raw(&db, &domain, content.raw.as_deref().unwrap_or_default())
+                .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("dkim: {e}")))?
+        };
+        let message = RenderedMessage::new(id.clone(), &to, &signed);
+        return Ok((
+            StatusCode::OK,
+            Json(QueueResponse {
+                id,
+                ids: Vec::new(),
+                status: "dry_run".into(),
+                calendar_uid: None,
+                suppressed,
+                unsubscribed: Vec::new(),
+                messages: vec![message],
+            }),
+        ));
+    }
+    enqueue(&state, &domain, &id, &from, &to, &content, query.save.unwrap_or(true))
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+    info!(domain, id, "queued raw message");
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(QueueResponse {
+            id,
+            ids: Vec::new(),
+            status: "queued".into(),
+            calendar_uid: None,
+            suppressed,
+            unsubscribed: Vec::new(),
+            messages: Vec::new(),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare() {
+        let raw = b"From: Cron <cron@example.com>\r\n\
+            To: ada@example.org\r\n\
+            Bcc: audit@example.com,\r\n \tlog@example.com\r\n\
+            Subject: Backup\r\n\
+            \r\n\
+            Bcc: this line is body text\r\n";
+
+        let message = parse(raw).unwrap();
+        assert_eq!(
+            header_recipients(&message),
+            vec!["ada@example.org", "audit@example.com", "log@example.com"]
+        );
+
+        let content = prepare("q1", "example.com", "cron@example.com", raw).unwrap();
+        let prepared = String::from_utf8(content.raw.unwrap()).unwrap();
+        assert!(prepared.starts_with("Message-ID: <q1@example.com>\r\nDate: "), "{prepared}");
+        assert!(prepared.contains("To: ada@example.org\r\nSubject: Backup\r\n\r\nBcc: this line"), "{prepared}");
+        assert!(!prepared.contains("audit@"), "{prepared}");
+        assert_eq!(content.subject, "Backup");
+
+        let (status, _) = prepare("q2", "other.com", "cron@other.com", raw).unwrap_err();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let lf = String::from_utf8_lossy(raw).replace("\r\n", "\n");
+        let content = prepare("q3", "example.com", "cron@example.com", lf.as_bytes()).unwrap();
+        let prepared = String::from_utf8(content.raw.unwrap()).unwrap();
+        assert!(prepared.contains("To: ada@example.org\r\nSubject: Backup\r\n\r\nBcc: this line"), "{prepared}");
+        assert!(!prepared.contains("audit@"), "{prepared}");
+        assert_eq!(to_crlf(b"a\r\nb\nc\n"), b"a\r\nb\r\nc\r\n");
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::HashSet;
+
+use lettre::{Address, message::Mailbox};
+use serde::Serialize;
+
+use crate::config;
+
+/// Where recipients are refused regardless of syntax.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Policy {
+    /// Lowercase TLDs without the leading dot, e.g. `zip`.
+    pub(crate) blocked_tlds: HashSet<String>,
+    /// Lowercase ASCII domains; subdomains are blocked too.
+    pub(crate) disposable_domains: HashSet<String>,
+}
+
+impl Policy {
+    /// Reads `MAYL_BLOCKED_TLDS` (comma separated) and the blocklist file
+    /// named by `MAYL_DISPOSABLE_DOMAINS_FILE` (one domain per line, `#`
+    /// starts a comment).
+    pub(crate) fn load(source: &config::Source) -> Self {
+        let blocked_tlds = source
+            .opt("MAYL_BLOCKED_TLDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|t| t.trim().trim_start_matches('.').to_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+
+        let disposable_domains = match source.opt("MAYL_DISPOSABLE_DOMAINS_FILE") {
+            Some(path) => match std::fs::read_to_string(&path) {
+                Ok(list) => parse_domain_list(&list),
+                Err(e) => {
+                    source.invalid("MAYL_DISPOSABLE_DOMAINS_FILE", format!("failed to read '{path}': {e}"));
+                    HashSet::new()
+                }
+            },
+            None => HashSet::new(),
+        };
+
+        Self {
+            blocked_tlds,
+            disposable_domains,
+        }
+    }
+}
+
+fn parse_domain_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(|l| l.split('#').next().unwrap_or_default().trim())
+        .filter(|l| !l.is_empty())
+        .filter_map(|l| idna::domain_to_ascii(l).ok())
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Rejection {
+    pub(crate) address: String,
+    pub(crate) reason: String,
+}
+
+/// Checks one recipient and returns it with its domain in ASCII (punycode)
+/// form, keeping any display name.
+fn check(addr: &str, policy: &Policy) -> Result<String, String> {
+    let mbox: Mailbox = addr
+        .trim()
+        .parse()
+        .map_err(|e| format!("not a valid address: {e}"))?;
+
+    let domain = idna::domain_to_ascii(mbox.email.domain())
+        .map_err(|_| "domain is not a valid internationalized name".to_string())?;
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 || labels.iter().any(|l| l.is_empty()) {
+        return Err("domain must be fully qualified".into());
+    }
+    let tld = labels[labels.len() - 1];
+    if tld.chars().all(|c| c.is_ascii_digit()) {
+        return Err("address literals are not accepted".into());
+    }
+    if policy.blocked_tlds.contains(tld) {
+        return Err(format!("top-level domain .{tld} is blocked"));
+    }
+    if let Some(blocked) = (0..labels.len() - 1)
+        .map(|i| labels[i..].join("."))
+        .find(|d| policy.disposable_domains.contains(d))
+    {
+        return Err(format!("{blocked} is a disposable email domain"));
+    }
+
+    let email = Address::new(mbox.email.user(), &domain)
+        .map_err(|e| format!("not a valid address: {e}"))?;
+    Ok(Mailbox::new(mbox.name, email).to_string())
+}
+
+/// Validates every recipient, returning them normalized, or every rejection
+/// with its reason.
+pub(crate) fn validate(to: &[String], policy: &Policy) -> Result<Vec<String>, Vec<Rejection>> {
+    let mut accepted = Vec::with_capacity(to.len());
+    let mut rejected = Vec::new();
+    for addr in to {
+        match check(addr, policy) {
+            Ok(normalized) => accepted.push(normalized),
+            Err(reason) => rejected.push(Rejection {
+                address: addr.clone(),
+                reason,
+            }),
+        }
+    }
+
+    if rejected.is_empty() {
+        Ok(accepted)
+    } else {
+        Err(rejected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_normalizes() {
+        let policy = Policy::default();
+        let to = vec![
+            "Ada Lovelace <ada@example.org>".to_string(),
+            "bücher@münchen.example".to_string(),
+        ];
+        assert_eq!(
+            validate(&to, &policy).unwrap(),
+            vec![
+                "Ada Lovelace <ada@example.org>".to_string(),
+                "bücher@xn--mnchen-3ya.example".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_reports_each_rejection() {
+        let policy = Policy {
+            blocked_tlds: HashSet::from(["zip".to_string()]),
+            disposable_domains: parse_domain_list("# comment\nmailinator.com\n"),
+        };
+        let to = vec![
+            "ok@example.org".to_string(),
+            "not an address".to_string(),
+            "root@localhost".to_string(),
+            "x@files.zip".to_string(),
+            "y@eu.mailinator.com".to_string(),
+        ];
+        let rejected = validate(&to, &policy).unwrap_err();
+        let reasons: Vec<(&str, &str)> = rejected
+            .iter()
+            .map(|r| (r.address.as_str(), r.reason.as_str()))
+            .collect();
+
+        assert_eq!(reasons.len(), 4, "{reasons:?}");
+        assert_eq!(reasons[0].0, "not an address");
+        assert!(reasons[0].1.starts_with("not a valid address"));
+        assert_eq!(reasons[1], ("root@localhost", "domain must be fully qualified"));
+        assert_eq!(reasons[2], ("x@files.zip", "top-level domain .zip is blocked"));
+        assert_eq!(
+            reasons[3],
+            ("y@eu.mailinator.com", "mailinator.com is a disposable email domain")
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use lettre::message::{
+    MultiPart, SinglePart,
+    header::{ContentDisposition, ContentTransferEncoding},
+};
+use openssl::{
+    hash::MessageDigest,
+    nid::Nid,
+    pkcs7::{Pkcs7, Pkcs7Flags},
+    pkey::{PKey, Private},
+    stack::Stack,
+    x509::X509,
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    ApiError, AppState, api_error,
+    mime::{Part, content_type, entity_bytes, with_part},
+    now_millis,
+};
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CertificateRequest {
+    /// PEM certificate, optionally followed by intermediates.
+    certificate: String,
+    private_key: String,
+    #[serde(default)]
+    passphrase: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct CertificateResponse {
+    domain: String,
+    subject: String,
+    not_after: String,
+    fingerprint: String,
+}
+
+/// A domain's signing certificate, its chain and private key.
+pub(crate) struct Signer {
+    cert: X509,
+    chain: Stack<X509>,
+    key: PKey<Private>,
+}
+
+// ── Database ────────────────────────────────────────────────────────────────
+
+pub(crate) fn init_db(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS smime_certs (
+            domain TEXT PRIMARY KEY,
+            certificate TEXT NOT NULL,
+            private_key TEXT NOT NULL,
+            passphrase TEXT,
+            created_at INTEGER NOT NULL
+        );",
+    )
+    .expect("failed to initialize smime_certs table");
+}
+
+pub(crate) fn delete_domain(conn: &Connection, domain: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM smime_certs WHERE domain = ?1", [domain]).map(|_| ())
+}
+
+/// Loads the signing certificate for `domain`, failing if none is configured.
+pub(crate) fn load_signer(conn: &Connection, domain: &str) -> Result<Signer, String> {
+    let row: Option<(String, String, Option<String>)> = conn
+        .query_row(
+            "SELECT certificate, private_key, passphrase FROM smime_certs WHERE domain = ?1",
+            [domain],
+            |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+        )
+        .optional()
+        .map_err(|e| format!("db error: {e}"))?;
+
+    let (certificate, private_key, passphrase) =
+        row.ok_or_else(|| format!("no S/MIME certificate configured for '{domain}'"))?;
+    parse_signer(&certificate, &private_key, passphrase.as_deref())
+}
+
+fn parse_signer(
+    certificate: &str,
+    private_key: &str,
+    passphrase: Option<&str>,
+) -> Result<Signer, String> {
+    let mut certs = X509::stack_from_pem(certificate.as_bytes())
+        .map_err(|e| format!("invalid certificate: {e}"))?
+        .into_iter();
+    let cert = certs.next().ok_or("invalid certificate: no certificate found")?;
+
+    let mut chain = Stack::new().map_err(|e| format!("openssl: {e}"))?;
+    for intermediate in certs {
+        chain.push(intermediate).map_err(|e| format!("openssl: {e}"))?;
+    }
+
+    let key = match passphrase {
+        Some(p) if !p.is_empty() => {
+            PKey::private_key_from_pem_passphrase(private_key.as_bytes(), p.as_bytes())
+        }
+        _ => PKey::private_key_from_pem(private_key.as_bytes()),
+    }
+    .map_err(|e| format!("invalid private key: {e}"))?;
+
+    let matches = cert
+        .public_key()
+        .map(|public| public.public_eq(&key))
+        .unwrap_or(false);
+    if !matches {
+        return Err("private key does not match certificate".into());
+    }
+
+    Ok(Signer { cert, chain, key })
+}
+
+// ── S/MIME (RFC 8551) ───────────────────────────────────────────────────────
+
+/// Wraps `part` in `multipart/signed` with a detached PKCS #7 signature.
+pub(crate) fn sign(part: Part, signer: &Signer) -> Result<Part, String> {
+    let data = entity_bytes(&part);
+    let signature = Pkcs7::sign(
+        &signer.cert,
+        &signer.key,
+        &signer.chain,
+        &data,
+        Pkcs7Flags::DETACHED | Pkcs7Flags::BINARY,
+    )
+    .and_then(|p7| p7.to_der())
+    .map_err(|e| format!("smime sign: {e}"))?;
+
+    let signed = with_part(
+        MultiPart::signed("application/pkcs7-signature".into(), "sha-256".into()),
+        part,
+    )
+    .singlepart(
+        SinglePart::builder()
+            .header(content_type(
+                "application/pkcs7-signature; name=\"smime.p7s\"",
+            ))
+            .header(ContentTransferEncoding::Base64)
+            .header(ContentDisposition::attachment("smime.p7s"))
+            .body(signature),
+    );
+    Ok(Part::Multi(signed))
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+fn describe(domain: String, cert: &X509) -> CertificateResponse {
+    let subject = cert
+        .subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .chain(cert.subject_name().entries_by_nid(Nid::PKCS9_EMAILADDRESS))
+        .filter_map(|e| e.data().as_utf8().ok().map(|s| s.to_string()))
+        .next()
+        .unwrap_or_default();
+    let fingerprint = cert
+        .digest(MessageDigest::sha256())
+        .map(|d| d.iter().map(|b| format!("{b:02X}")).collect())
+        .unwrap_or_default();
+
+    CertificateResponse {
+        domain,
+        subject,
+        not_after: cert.not_after().to_string(),
+        fingerprint,
+    }
+}
+
+pub(crate) async fn put_certificate_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+    Json(payload): Json<CertificateRequest>,
+) -> Result<Json<CertificateResponse>, ApiError> {
+    let domain = domain.to_lowercase();
+    let signer = parse_signer(
+        &payload.certificate,
+        &payload.private_key,
+        payload.passphrase.as_deref(),
+    )
+    .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+
+    let db = state.db.lock().await;
+    let exists: bool = db
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM domains WHERE domain = ?1",
+            [&domain],
+            |r| r.get(0),
+        )
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+    if !exists {
+        return Err(api_error(StatusCode::NOT_FOUND, "domain not found"));
+    }
+
+    db.execute(
+        "INSERT INTO smime_certs (domain, certificate, private_key, passphrase, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(domain) DO UPDATE SET
+            certificate = excluded.certificate,
+            private_key = excluded.private_key,
+            passphrase = excluded.passphrase,
+            created_at = excluded.created_at",
+        rusqlite::params![
+            &domain,
+            &payload.certificate,
+            &payload.private_key,
+            &payload.passphrase,
+            now_millis()
+        ],
+    )
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+
+    let response = describe(domain, &signer.cert);
+    info!(domain = response.domain, subject = response.subject, "S/MIME certificate set");
+    Ok(Json(response))
+}
+
+pub(crate) async fn get_certificate_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Result<Json<CertificateResponse>, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let certificate: String = db
+        .query_row(
+            "SELECT certificate FROM smime_certs WHERE domain = ?1",
+            [&domain],
+            |r| r.get(0),
+        )
+        .map_err(|_| api_error(StatusCode::NOT_FOUND, "no certificate for domain"))?;
+    let cert = X509::from_pem(certificate.as_bytes())
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("stored certificate: {e}")))?;
+
+    Ok(Json(describe(domain, &cert)))
+}
+
+pub(crate) async fn delete_certificate_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let deleted = db
+        .execute("DELETE FROM smime_certs WHERE domain = ?1", [&domain])
+        .unwrap_or(0);
+
+    if deleted == 0 {
+        Err(api_error(StatusCode::NOT_FOUND, "no certificate for domain"))
+    } else {
+        info!(domain, "S/MIME certificate removed");
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        rsa::Rsa,
+        x509::{X509NameBuilder, store::X509StoreBuilder},
+    };
+
+    fn self_signed() -> (String, String) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "example.com").unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder
+            .set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(30).unwrap()).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+        let cert = String::from_utf8(builder.build().to_pem().unwrap()).unwrap();
+        let key = String::from_utf8(key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        (cert, key)
+    }
+
+    #[test]
+    fn test_parse_signer_rejects_mismatched_key() {
+        let (cert, _) = self_signed();
+        let (_, other_key) = self_signed();
+        let err = parse_signer(&cert, &other_key, None).err().unwrap();
+        assert!(err.contains("does not match"), "{err}");
+    }
+
+    #[test]
+    fn test_sign_verifies() {
+        let (cert_pem, key_pem) = self_signed();
+        let signer = parse_signer(&cert_pem, &key_pem, None).unwrap();
+
+        let inner = Part::Single(SinglePart::plain(String::from("signed body")));
+        let expected = entity_bytes(&inner);
+        let Part::Multi(signed) = sign(inner, &signer).unwrap() else {
+            panic!("expected multipart");
+        };
+
+        let raw = String::from_utf8(signed.formatted()).unwrap();
+        assert!(raw.contains("protocol=\"application/pkcs7-signature\""), "{raw}");
+        assert!(raw.contains(std::str::from_utf8(&expected).unwrap()));
+
+        let b64: String = raw
+            .split("Content-Disposition: attachment; filename=\"smime.p7s\"\r\n\r\n")
+            .nth(1)
+            .unwrap()
+            .lines()
+            .take_while(|l| !l.starts_with("--"))
+            .collect();
+        let der = openssl::base64::decode_block(b64.trim()).unwrap();
+        let p7 = Pkcs7::from_der(&der).unwrap();
+
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(X509::from_pem(cert_pem.as_bytes()).unwrap()).unwrap();
+        let store = store.build();
+        p7.verify(
+            &Stack::new().unwrap(),
+            &store,
+            Some(&expected),
+            None,
+            Pkcs7Flags::BINARY,
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,714 @@
+//! An SMTP submission server (RFC 6409) for tools that cannot call the HTTP
+//! API. Clients authenticate with a domain token as the password; accepted
+//! messages go into `email_queue` as-is.
+
+use std::{sync::Arc, time::Duration};
+
+use axum::http::StatusCode;
+use openssl::{
+    asn1::Asn1Time,
+    base64,
+    bn::BigNum,
+    hash::MessageDigest,
+    pkey::PKey,
+    rsa::Rsa,
+    x509::{X509, X509NameBuilder},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::Semaphore,
+    time::timeout,
+};
+use tokio_native_tls::{TlsAcceptor, native_tls};
+use tracing::{debug, error, info, warn};
+
+use crate::{AppState, authorize_sender, config, raw, recipients, suppressions};
+
+/// How long a client may stay silent before it is disconnected.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const MAX_RECIPIENTS: usize = 100;
+/// Longest command or text line, CRLF included (RFC 5321 §4.5.3.1).
+const MAX_LINE: u64 = 1000;
+/// Connections served at once; more are turned away with a 421.
+const MAX_SESSIONS: usize = 100;
+
+// ── Config ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone)]
+pub(crate) struct Settings {
+    pub(crate) bind: String,
+    /// Name announced in the greeting and EHLO reply.
+    pub(crate) hostname: String,
+    /// Only offer AUTH once the connection is encrypted.
+    pub(crate) require_tls: bool,
+    /// Largest message accepted, in bytes.
+    pub(crate) max_size: usize,
+    /// PEM certificate chain and PKCS #8 key used for STARTTLS.
+    cert_pem: Vec<u8>,
+    key_pem: Vec<u8>,
+}
+
+impl Settings {
+    /// Reads the `MAYL_SUBMISSION_*` settings; `None` unless
+    /// `MAYL_SUBMISSION_ENABLED` is set. Without a configured certificate a
+    /// self-signed one is generated, as Bridge does.
+    pub(crate) fn load(source: &config::Source, server_host: &str) -> Option<Self> {
+        if !source.parse("MAYL_SUBMISSION_ENABLED", false) {
+            return None;
+        }
+        let hostname = source.or("MAYL_SUBMISSION_HOSTNAME", "localhost");
+        let read = |key: &str| {
+            let path = source.opt(key)?;
+            std::fs::read(&path)
+                .map_err(|e| source.invalid(key, format!("failed to read '{path}': {e}")))
+                .ok()
+        };
+        let (cert_pem, key_pem) = match (read("MAYL_SUBMISSION_TLS_CERT"), read("MAYL_SUBMISSION_TLS_KEY")) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => self_signed(&hostname).expect("failed to generate a submission certificate"),
+            _ => {
+                source.invalid(
+                    "MAYL_SUBMISSION_TLS_CERT",
+                    "MAYL_SUBMISSION_TLS_CERT and MAYL_SUBMISSION_TLS_KEY must be set together",
+                );
+                (Vec::new(), Vec::new())
+            }
+        };
+
+        Some(Self {
+            bind: format!(
+                "{}:{}",
+                source.or("MAYL_SUBMISSION_HOST", server_host),
+                source.parse("MAYL_SUBMISSION_PORT", 587)
+            ),
+            hostname,
+            require_tls: source.parse("MAYL_SUBMISSION_REQUIRE_TLS", true),
+            max_size: source.parse("MAYL_SUBMISSION_MAX_SIZE", raw::MAX_SIZE),
+            cert_pem,
+            key_pem,
+        })
+    }
+
+    fn acceptor(&self) -> Result<TlsAcceptor, String> {
+        let identity = native_tls::Identity::from_pkcs8(&self.cert_pem, &self.key_pem)
+            .map_err(|e| format!("submission certificate: {e}"))?;
+        let acceptor = native_tls::TlsAcceptor::new(identity).map_err(|e| format!("submission tls: {e}"))?;
+        Ok(TlsAcceptor::from(acceptor))
+    }
+}
+
+/// A ten-year self-signed RSA certificate for `hostname`, as PEM.
+fn self_signed(hostname: &str) -> Result<(Vec<u8>, Vec<u8>), openssl::error::ErrorStack> {
+    let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", hostname)?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(127, openssl::bn::MsbOption::MAYBE_ZERO, false)?;
+    let mut cert = X509::builder()?;
+    cert.set_version(2)?;
+    let serial = serial.to_asn1_integer()?;
+    let (not_before, not_after) = (Asn1Time::days_from_now(0)?, Asn1Time::days_from_now(3650)?);
+    cert.set_serial_number(&serial)?;
+    cert.set_subject_name(&name)?;
+    cert.set_issuer_name(&name)?;
+    cert.set_pubkey(&key)?;
+    cert.set_not_before(&not_before)?;
+    cert.set_not_after(&not_after)?;
+    cert.sign(&key, MessageDigest::sha256())?;
+
+    Ok((cert.build().to_pem()?, key.private_key_to_pem_pkcs8()?))
+}
+
+// ── Session ─────────────────────────────────────────────────────────────────
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// What a connection has established so far.
+#[derive(Default)]
+struct Session {
+    tls: bool,
+    /// The token the client authenticated with.
+    token: Option<String>,
+    /// The domain it authorizes, and the envelope sender once `MAIL` passed.
+    domain: Option<String>,
+    mail_from: Option<String>,
+    rcpt_to: Vec<String>,
+}
+
+impl Session {
+    fn reset(&mut self) {
+        self.mail_from = None;
+        self.rcpt_to.clear();
+    }
+}
+
+/// Why a connection ended early.
+enum Disconnect {
+    Quit,
+    Io(String),
+}
+
+struct Connection {
+    stream: BufReader<Box<dyn Io>>,
+}
+
+impl Connection {
+    /// Reads one line. One over `MAX_LINE` is refused and ends the
+    /// connection, since the rest of it cannot be told apart from commands.
+    async fn read_line(&mut self) -> Result<Vec<u8>, Disconnect> {
+        let mut line = Vec::new();
+        let n = timeout(IDLE_TIMEOUT, (&mut self.stream).take(MAX_LINE).read_until(b'\n', &mut line))
+            .await
+            .map_err(|_| Disconnect::Io("timed out".into()))?
+            .map_err(|e| Disconnect::Io(e.to_string()))?;
+        if n == 0 {
+            return Err(Disconnect::Io("connection closed".into()));
+        }
+        if !line.ends_with(b"\n") && n as u64 == MAX_LINE {
+            self.reply("500 5.5.2 Line too long").await?;
+            return Err(Disconnect::Io("line too long".into()));
+        }
+        Ok(line)
+    }
+
+    /// A command or SASL line, without its line ending.
+    async fn read_text(&mut self) -> Result<String, Disconnect> {
+        let line = self.read_line().await?;
+        Ok(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    async fn reply(&mut self, text: &str) -> Result<(), Disconnect> {
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{text}\r\n").as_bytes())
+            .await
+            .map_err(|e| Disconnect::Io(e.to_string()))?;
+        stream.flush().await.map_err(|e| Disconnect::Io(e.to_string()))
+    }
+
+    /// Reads a `DATA` body up to the lone `.`, undoing dot-stuffing. Returns
+    /// `None` when it exceeds `max_size`; the rest is still consumed.
+    async fn read_data(&mut self, max_size: usize) -> Result<Option<Vec<u8>>, Disconnect> {
+        let mut data = Vec::new();
+        let mut too_big = false;
+        loop {
+            let line = self.read_line().await?;
+            let content = line.strip_suffix(b"\n").unwrap_or(&line);
+            let content = content.strip_suffix(b"\r").unwrap_or(content);
+            if content == b"." {
+                return Ok((!too_big).then_some(data));
+            }
+            if too_big {
+                continue;
+            }
+            data.extend_from_slice(content.strip_prefix(b".").unwrap_or(content));
+            data.extend_from_slice(b"\r\n");
+            too_big = data.len() > max_size;
+        }
+    }
+}
+
+/// The address inside `FROM:<...>` / `TO:<...>`, and what follows it.
+fn path_arg<'a>(args: &'a str, keyword: &str) -> Option<(&'a str, &'a str)> {
+    let rest = args.get(..keyword.len())?.eq_ignore_ascii_case(keyword).then(|| &args[keyword.len()..])?;
+    let rest = rest.trim_start().strip_prefix('<')?;
+    let end = rest.find('>')?;
+    Some((rest[..end].trim(), rest[end + 1..].trim()))
+}
+
+fn decode(b64: &str) -> Option<String> {
+    base64::decode_block(b64.trim())
+        .ok()
+        .and_then(|raw| String::from_utf8(raw).ok())
+}
+
+/// Reads the SASL exchange for `AUTH PLAIN` or `AUTH LOGIN` and returns the
+/// password, or the reply to send when the exchange is malformed.
+async fn read_credentials(conn: &mut Connection, args: &str) -> Result<Result<String, &'static str>, Disconnect> {
+    let (mechanism, initial) = args.split_once(' ').unwrap_or((args, ""));
+    let next = async |conn: &mut Connection, prompt: &str| -> Result<Option<String>, Disconnect> {
+        conn.reply(&format!("334 {prompt}")).await?;
+        let line = conn.read_text().await?;
+        Ok((line != "*").then_some(line))
+    };
+
+    match mechanism.to_uppercase().as_str() {
+        "PLAIN" => {
+            let response = if initial.is_empty() {
+                match next(conn, "").await? {
+                    Some(r) => r,
+                    None => return Ok(Err("501 5.0.0 Authentication cancelled")),
+                }
+            } else {
+                initial.to_string()
+            };
+            // authzid NUL authcid NUL passwd
+            let password = decode(&response).and_then(|r| r.splitn(3, '\0').nth(2).map(str::to_string));
+            Ok(password.ok_or("501 5.5.2 Malformed AUTH PLAIN response"))
+        }
+        "LOGIN" => {
+            if initial.is_empty() && next(conn, "VXNlcm5hbWU6").await?.is_none() {
+                return Ok(Err("501 5.0.0 Authentication cancelled"));
+            }
+            // The username is not checked: the token alone identifies the domain
+            match next(conn, "UGFzc3dvcmQ6").await? {
+                Some(r) => Ok(decode(&r).ok_or("501 5.5.2 Malformed AUTH LOGIN response")),
+                None => Ok(Err("501 5.0.0 Authentication cancelled")),
+            }
+        }
+        _ => Ok(Err("504 5.5.4 Unrecognized authentication mechanism")),
+    }
+}
+
+/// Queues an accepted message and returns its queue id, or the reply to
+/// reject it with.
+async fn accept(state: &AppState, session: &Session, raw: Vec<u8>) -> Result<String, String> {
+    let domain = session.domain.as_deref().unwrap_or_default();
+    let from = session.mail_from.as_deref().unwrap_or_default();
+    let id = uuid::Uuid::new_v4().to_string();
+
+    let content = raw::prepare(&id, domain, from, &raw).map_err(|(status, err)| match status {
+        StatusCode::FORBIDDEN => format!("550 5.7.1 {}", err.0.error),
+        _ => format!("554 5.6.0 {}", err.0.error),
+    })?;
+    raw::enqueue(state, domain, &id, from, &session.rcpt_to, &content, true)
+        .await
+        .map_err(|e| {
+            error!("submission queue insert: {e}");
+            "451 4.3.0 Could not queue message".to_string()
+        })?;
+    info!(domain, id, "queued message from SMTP submission");
+    Ok(id)
+}
+
+/// Runs one SMTP conversation to completion.
+async fn session(
+    state: &AppState,
+    settings: &Settings,
+    acceptor: &TlsAcceptor,
+    stream: TcpStream,
+) -> Result<(), Disconnect> {
+    let mut conn = Connection {
+        stream: BufReader::new(Box::new(stream)),
+    };
+    let mut session = Session::default();
+    conn.reply(&format!("220 {} ESMTP mayl", settings.hostname)).await?;
+
+    loop {
+        let line = conn.read_text().await?;
+        let (verb, args) = line.split_once(' ').unwrap_or((&line, ""));
+        let args = args.trim();
+        let auth_offered = session.tls || !settings.require_tls;
+
+        match verb.to_uppercase().as_str() {
+            "EHLO" => {
+                session.reset();
+                let mut lines = vec![settings.hostname.clone()];
+                if !session.tls {
+                    lines.push("STARTTLS".into());
+                }
+                if auth_offered {
+                    lines.push("AUTH PLAIN LOGIN".into());
+                }
+                lines.extend([
+                    format!("SIZE {}", settings.max_size),
+                    "8BITMIME".into(),
+                    "ENHANCEDSTATUSCODES".into(),
+                    "PIPELINING".into(),
+                ]);
+                let last = lines.len() - 1;
+                for (i, text) in lines.iter().enumerate() {
+                    let sep = if i == last { ' ' } else { '-' };
+                    conn.reply(&format!("250{sep}{text}")).await?;
+                }
+            }
+            "HELO" => {
+                session.reset();
+                conn.reply(&format!("250 {}", settings.hostname)).await?;
+            }
+            "STARTTLS" if !session.tls => {
+                conn.reply("220 2.0.0 Ready to start TLS").await?;
+                // Anything pipelined before the handshake is discarded
+                let plain = conn.stream.into_inner();
+                let tls = acceptor
+                    .accept(plain)
+                    .await
+                    .map_err(|e| Disconnect::Io(format!("tls handshake: {e}")))?;
+                conn.stream = BufReader::new(Box::new(tls));
+                session = Session {
+                    tls: true,
+                    ..Default::default()
+                };
+            }
+            "AUTH" if !auth_offered => conn.reply("530 5.7.0 Must issue a STARTTLS command first").await?,
+            "AUTH" if session.token.is_some() => conn.reply("503 5.5.1 Already authenticated").await?,
+            "AUTH" => {
+                let token = match read_credentials(&mut conn, args).await? {
+                    Ok(token) => token,
+                    Err(reply) => {
+                        conn.reply(reply).await?;
+                        continue;
+                    }
+                };
+                let domain: Option<String> = {
+                    let db = state.db.lock().await;
+                    db.query_row("SELECT domain FROM domains WHERE token = ?1", [&token], |r| r.get(0))
+                        .ok()
+                };
+                match domain {
+                    Some(domain) => {
+                        debug!(domain, "SMTP client authenticated");
+                        session.token = Some(token);
+                        session.domain = Some(domain);
+                        conn.reply("235 2.7.0 Authentication successful").await?;
+                    }
+                    None => conn.reply("535 5.7.8 Authentication credentials invalid").await?,
+                }
+            }
+            "MAIL" => {
+                let Some(token) = &session.token else {
+                    conn.reply("530 5.7.0 Authentication required").await?;
+                    continue;
+                };
+                if session.mail_from.is_some() {
+                    conn.reply("503 5.5.1 Nested MAIL command").await?;
+                    continue;
+                }
+                let Some((from, params)) = path_arg(args, "FROM:") else {
+                    conn.reply("501 5.5.4 Syntax: MAIL FROM:<address>").await?;
+                    continue;
+                };
+                let size = params
+                    .split_whitespace()
+                    .find_map(|p| p.get(..5).filter(|k| k.eq_ignore_ascii_case("SIZE=")).map(|_| &p[5..]))
+                    .and_then(|s| s.parse::<usize>().ok());
+                if size.is_some_and(|s| s > settings.max_size) {
+                    conn.reply("552 5.3.4 Message size exceeds fixed limit").await?;
+                    continue;
+                }
+                let authorized = {
+                    let db = state.db.lock().await;
+                    authorize_sender(&db, token, from)
+                };
+                match authorized {
+                    Ok((domain, _)) => {
+                        session.domain = Some(domain);
+                        session.mail_from = Some(from.to_string());
+                        conn.reply("250 2.1.0 Ok").await?;
+                    }
+                    Err((_, err)) => conn.reply(&format!("550 5.7.1 {}", err.0.error)).await?,
+                }
+            }
+            "RCPT" => {
+                if session.mail_from.is_none() {
+                    conn.reply("503 5.5.1 Need MAIL command").await?;
+                    continue;
+                }
+                let Some((to, _)) = path_arg(args, "TO:") else {
+                    conn.reply("501 5.5.4 Syntax: RCPT TO:<address>").await?;
+                    continue;
+                };
+                if session.rcpt_to.len() >= MAX_RECIPIENTS {
+                    conn.reply("452 4.5.3 Too many recipients").await?;
+                    continue;
+                }
+                let to = match recipients::validate(&[to.to_string()], &state.config.recipient_policy) {
+                    Ok(mut valid) => valid.remove(0),
+                    Err(rejected) => {
+                        conn.reply(&format!("550 5.1.3 {}", rejected[0].reason)).await?;
+                        continue;
+                    }
+                };
+                let suppressed = {
+                    let db = state.db.lock().await;
+                    let domain = session.domain.as_deref().unwrap_or_default();
+                    suppressions::partition(&db, domain, std::slice::from_ref(&to))
+                        .map(|(_, suppressed)| !suppressed.is_empty())
+                };
+                match suppressed {
+                    Ok(false) => {
+                        session.rcpt_to.push(to);
+                        conn.reply("250 2.1.5 Ok").await?;
+                    }
+                    Ok(true) => conn.reply("550 5.7.1 Recipient is on the suppression list").await?,
+                    Err(e) => conn.reply(&format!("550 5.1.3 {e}")).await?,
+                }
+            }
+            "DATA" => {
+                if session.rcpt_to.is_empty() {
+                    conn.reply("503 5.5.1 Need RCPT command").await?;
+                    continue;
+                }
+                conn.reply("354 End data with <CR><LF>.<CR><LF>").await?;
+                let reply = match conn.read_data(settings.max_size).await? {
+                    None => "552 5.3.4 Message size exceeds fixed limit".to_string(),
+                    Some(raw) => match accept(state, &session, raw).await {
+                        Ok(id) => format!("250 2.0.0 Ok: queued as {id}"),
+                        Err(reply) => reply,
+                    },
+                };
+                session.reset();
+                conn.reply(&reply).await?;
+            }
+            "RSET" => {
+                session.reset();
+                conn.reply("250 2.0.0 Ok").await?;
+            }
+            "NOOP" => conn.reply("250 2.0.0 Ok").await?,
+            "VRFY" => conn.reply("252 2.5.0 Cannot VRFY user").await?,
+            "QUIT" => {
+                conn.reply("221 2.0.0 Bye").await?;
+                return Err(Disconnect::Quit);
+            }
+            _ => conn.reply("502 5.5.2 Command not recognized").await?,
+        }
+    }
+}
+
+pub(crate) async fn serve(state: Arc<AppState>, settings: Settings) {
+    let acceptor = match settings.acceptor() {
+        Ok(a) => Arc::new(a),
+        Err(e) => {
+            error!("SMTP submission disabled: {e}");
+            return;
+        }
+    };
+    let listener = match TcpListener::bind(&settings.bind).await {
+        Ok(l) => l,
+        Err(e) => {
+            error!(bind = settings.bind, "SMTP submission disabled: {e}");
+            return;
+        }
+    };
+    info!("SMTP submission listening on {}", settings.bind);
+
+    let settings = Arc::new(settings);
+    let sessions = Arc::new(Semaphore::new(MAX_SESSIONS));
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("SMTP accept: {e}");
+                continue;
+            }
+        };
+        let Ok(permit) = Arc::clone(&sessions).try_acquire_owned() else {
+            warn!(%peer, "SMTP submission at {MAX_SESSIONS} sessions, turning a client away");
+            tokio::spawn(async move {
+                let busy = b"421 4.3.2 Too many connections, try again later\r\n";
+                let _ = timeout(Duration::from_secs(10), stream.write_all(busy)).await;
+            });
+            continue;
+        };
+        let (state, settings, acceptor) = (Arc::clone(&state), Arc::clone(&settings), Arc::clone(&acceptor));
+        tokio::spawn(async move {
+            if let Err(Disconnect::Io(e)) = session(&state, &settings, &acceptor, stream).await {
+                debug!(%peer, "SMTP session ended: {e}");
+            }
+            drop(permit);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lettre::{
+        AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+        transport::smtp::{
+            authentication::Credentials,
+            client::{Tls, TlsParameters},
+        },
+    };
+    use rusqlite::Connection as Db;
+    use tokio::sync::{Mutex, RwLock};
+
+    #[test]
+    fn test_path_arg() {
+        assert_eq!(
+            path_arg("FROM:<app@example.com> SIZE=10", "FROM:"),
+            Some(("app@example.com", "SIZE=10"))
+        );
+        assert_eq!(path_arg("to: <ada@example.org>", "TO:"), Some(("ada@example.org", "")));
+        assert_eq!(path_arg("FROM:app@example.com", "FROM:"), None);
+    }
+
+    #[tokio::test]
+    async fn test_submission_queues_message() {
+        let db = Db::open_in_memory().unwrap();
+        crate::init_db(&db);
+        crate::seed_domains(&db, &["example.com".into(), "other.com".into()]);
+        let token: String = db
+            .query_row("SELECT token FROM domains WHERE domain = 'example.com'", [], |r| r.get(0))
+            .unwrap();
+        db.execute(
+            "INSERT INTO suppressions (domain, address, reason, created_at)
+             VALUES ('example.com', 'gone@example.org', 'manual', 0)",
+            [],
+        )
+        .unwrap();
+
+        let state = Arc::new(AppState {
+            db: Mutex::new(db),
+            config: crate::Config::defaults(),
+            smtp_creds: RwLock::new(crate::SmtpCredentials {
+                user: String::new(),
+                pass: String::new(),
+            }),
+            events: crate::events::channel(),
+            breakers: Default::default(),
+            readiness: Mutex::new(None),
+        });
+        let _ = crate::dkim::put_dkim_handler(
+            axum::extract::State(Arc::clone(&state)),
+            axum::extract::Path("example.com".into()),
+            axum::Json(Default::default()),
+        )
+        .await
+        .unwrap();
+        let (cert_pem, key_pem) = self_signed("localhost").unwrap();
+        let settings = Settings {
+            bind: "127.0.0.1:0".into(),
+            hostname: "localhost".into(),
+            require_tls: true,
+            max_size: 1024,
+            cert_pem,
+            key_pem,
+        };
+        let acceptor = Arc::new(settings.acceptor().unwrap());
+        let listener = TcpListener::bind(&settings.bind).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = Arc::clone(&state);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let _ = session(&server, &settings, &acceptor, stream).await;
+            }
+        });
+
+        let mailer = |password: &str| {
+            let tls = TlsParameters::builder("localhost".into())
+                .dangerous_accept_invalid_certs(true)
+                .build()
+                .unwrap();
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+                .port(port)
+                .tls(Tls::Required(tls))
+                .credentials(Credentials::new("example.com".into(), password.into()))
+                .build()
+        };
+        let message = |from: &str, to: &str, body: &str| {
+            lettre::Message::builder()
+                .from(from.parse().unwrap())
+                .to(to.parse().unwrap())
+                .subject("Backup finished")
+                .body(body.to_string())
+                .unwrap()
+        };
+
+        let sent = mailer(&token)
+            .send(message("cron@example.com", "ada@example.org", "All good."))
+            .await
+            .unwrap();
+        let reply = sent.message().collect::<Vec<_>>().join(" ");
+        let id = reply.rsplit_once("queued as ").unwrap().1.to_string();
+
+        let (to, subject, body, raw): (String, String, String, Vec<u8>) = state
+            .db
+            .lock()
+            .await
+            .query_row(
+                "SELECT to_addrs, subject, body, raw FROM email_queue WHERE id = ?1",
+                [&id],
+                |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(to, r#"["ada@example.org"]"#);
+        assert_eq!(subject, "Backup finished");
+        assert_eq!(body.trim(), "All good.");
+        assert!(String::from_utf8(raw.clone()).unwrap().contains("\r\nAll good."));
+
+        // Relaying the queued message signs it with the domain's DKIM key.
+        let relay = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_port = relay.local_addr().unwrap().port();
+        let captured = tokio::spawn(async move {
+            let (socket, _) = relay.accept().await.unwrap();
+            let (read, mut write) = socket.into_split();
+            let mut lines = BufReader::new(read).lines();
+            write.write_all(b"220 relay ready\r\n").await.unwrap();
+            let mut data = String::new();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let reply: &[u8] = match line.to_uppercase().as_str() {
+                    "DATA" => b"354 go ahead\r\n",
+                    "QUIT" => b"221 bye\r\n",
+                    _ => b"250 ok\r\n",
+                };
+                write.write_all(reply).await.unwrap();
+                if line.eq_ignore_ascii_case("DATA") {
+                    while let Some(line) = lines.next_line().await.unwrap()
+                        && line != "."
+                    {
+                        data.push_str(&line);
+                        data.push_str("\r\n");
+                    }
+                    write.write_all(b"250 sent\r\n").await.unwrap();
+                }
+            }
+            data
+        });
+        state
+            .db
+            .lock()
+            .await
+            .execute_batch(&format!(
+                "INSERT INTO smtp_upstreams (name, host, port, tls, user, pass, verify_tls, created_at)
+                 VALUES ('relay', '127.0.0.1', {relay_port}, 'none', '', '', 0, 0);
+                 INSERT INTO domain_upstreams (domain, position, upstream) VALUES ('example.com', 0, 'relay');"
+            ))
+            .unwrap();
+        let content = crate::EmailContent {
+            raw: Some(raw),
+            ..Default::default()
+        };
+        let to = ["ada@example.org".to_string()];
+        crate::send_email(&state, &id, "cron@example.com", &to, &content).await.unwrap();
+        let relayed = captured.await.unwrap();
+        assert!(relayed.starts_with("DKIM-Signature: v=1; a=rsa-sha256; d=example.com; s=mayl;"), "{relayed}");
+        assert!(relayed.contains("\r\nAll good."), "{relayed}");
+
+        let refused = [
+            // Wrong password
+            mailer("nope").send(message("cron@example.com", "ada@example.org", "x")).await,
+            // Sender outside the token's domain
+            mailer(&token).send(message("cron@other.com", "ada@example.org", "x")).await,
+            // Suppressed recipient
+            mailer(&token).send(message("cron@example.com", "gone@example.org", "x")).await,
+            // Over MAYL_SUBMISSION_MAX_SIZE
+            mailer(&token).send(message("cron@example.com", "ada@example.org", &"x".repeat(2000))).await,
+        ];
+        for result in refused {
+            assert!(result.unwrap_err().is_permanent());
+        }
+        let queued: i64 = state
+            .db
+            .lock()
+            .await
+            .query_row("SELECT COUNT(*) FROM email_queue", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(queued, 1);
+
+        // A line over MAX_LINE is refused and the connection closed
+        let mut client = BufReader::new(TcpStream::connect(("127.0.0.1", port)).await.unwrap());
+        let mut greeting = String::new();
+        client.read_line(&mut greeting).await.unwrap();
+        let long = format!("NOOP {}\r\n", "x".repeat(MAX_LINE as usize));
+        client.get_mut().write_all(long.as_bytes()).await.unwrap();
+        let mut rest = String::new();
+        client.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, "500 5.5.2 Line too long\r\n");
+    }
+}
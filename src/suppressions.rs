@@ -0,0 +1,285 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{ApiError, AppState, api_error, normalize_address, now_millis};
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SuppressionRequest {
+    address: String,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct SuppressionEntry {
+    address: String,
+    reason: String,
+    created_at: i64,
+}
+
+// ── Database ────────────────────────────────────────────────────────────────
+
+pub(crate) fn init_db(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS suppressions (
+            domain TEXT NOT NULL,
+            address TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (domain, address)
+        );",
+    )
+    .expect("failed to initialize suppressions table");
+}
+
+pub(crate) fn delete_domain(conn: &Connection, domain: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM suppressions WHERE domain = ?1", [domain]).map(|_| ())
+}
+
+/// Suppresses `address` (already normalized) for `domain`. An existing entry
+/// keeps its original reason and timestamp.
+fn add(conn: &Connection, domain: &str, address: &str, reason: &str) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO suppressions (domain, address, reason, created_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(domain, address) DO NOTHING",
+        rusqlite::params![domain, address, reason, now_millis()],
+    )
+}
+
+/// Splits `to` into deliverable and suppressed recipients, keeping the
+/// original spelling of each.
+pub(crate) fn partition(
+    conn: &Connection,
+    domain: &str,
+    to: &[String],
+) -> Result<(Vec<String>, Vec<String>), String> {
+    let mut stmt = conn
+        .prepare_cached("SELECT COUNT(*) > 0 FROM suppressions WHERE domain = ?1 AND address = ?2")
+        .map_err(|e| format!("db error: {e}"))?;
+
+    let mut allowed = Vec::with_capacity(to.len());
+    let mut suppressed = Vec::new();
+    for addr in to {
+        let address = normalize_address(addr)?;
+        let hit: bool = stmt
+            .query_row([domain, &address], |r| r.get(0))
+            .map_err(|e| format!("db error: {e}"))?;
+        if hit {
+            suppressed.push(addr.clone());
+        } else {
+            allowed.push(addr.clone());
+        }
+    }
+    Ok((allowed, suppressed))
+}
+
+/// The recipients a permanent SMTP failure applies to: those the server
+/// named in its reply, or the only recipient when the reply's status says
+/// its mailbox or domain is bad. Refusals of the sender, the size or the
+/// content, and failed logins, are not the recipient's fault and suppress
+/// nobody.
+fn bounced_recipients(to: &[String], error: &str) -> Vec<String> {
+    let error = error.to_lowercase();
+    let addresses: Vec<String> = to.iter().filter_map(|a| normalize_address(a).ok()).collect();
+    let named: Vec<String> = addresses
+        .iter()
+        .filter(|a| error.contains(a.as_str()))
+        .cloned()
+        .collect();
+
+    if named.is_empty() && addresses.len() == 1 && bad_recipient_status(&error) {
+        addresses
+    } else {
+        named
+    }
+}
+
+/// RFC 3463 `5.1.x` details that blame the recipient: bad mailbox, bad
+/// system, bad syntax, moved, and a domain that accepts no mail (RFC 7505).
+/// The rest are about the sender or say nothing.
+const BAD_RECIPIENT_DETAILS: [&str; 5] = ["1", "2", "3", "6", "10"];
+
+fn bad_recipient_status(reply: &str) -> bool {
+    reply
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_ascii_digit()))
+        .any(|word| match word.split('.').collect::<Vec<_>>()[..] {
+            ["5", "1", detail] => BAD_RECIPIENT_DETAILS.contains(&detail),
+            _ => false,
+        })
+}
+
+/// Suppresses an address that bounced, so later sends skip it instead of
+/// bouncing again.
+pub(crate) fn suppress_bounced(conn: &Connection, domain: &str, address: &str, reason: &str) {
+    match add(conn, domain, address, &format!("bounce: {reason}")) {
+        Ok(_) => info!(domain, address, "suppressed after permanent failure"),
+        Err(e) => warn!(domain, address, "failed to record suppression: {e}"),
+    }
+}
+
+/// Suppresses the recipients a permanent SMTP failure applies to, and
+/// returns them as they are written in `to`.
+pub(crate) fn record_bounce(conn: &Connection, domain: &str, to: &[String], error: &str) -> Vec<String> {
+    let bounced = bounced_recipients(to, error);
+    for address in &bounced {
+        suppress_bounced(conn, domain, address, error);
+    }
+    to.iter()
+        .filter(|a| normalize_address(a).is_ok_and(|a| bounced.contains(&a)))
+        .cloned()
+        .collect()
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+pub(crate) async fn list_suppressions_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Json<Vec<SuppressionEntry>> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let mut stmt = db
+        .prepare(
+            "SELECT address, reason, created_at FROM suppressions
+             WHERE domain = ?1 ORDER BY address",
+        )
+        .unwrap();
+    let entries: Vec<SuppressionEntry> = stmt
+        .query_map([&domain], |row| {
+            Ok(SuppressionEntry {
+                address: row.get(0)?,
+                reason: row.get(1)?,
+                created_at: row.get(2)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Json(entries)
+}
+
+pub(crate) async fn add_suppression_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+    Json(payload): Json<SuppressionRequest>,
+) -> Result<(StatusCode, Json<SuppressionEntry>), ApiError> {
+    let domain = domain.to_lowercase();
+    let address =
+        normalize_address(&payload.address).map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    let reason = payload.reason.unwrap_or_else(|| "manual".into());
+
+    let db = state.db.lock().await;
+    let exists: bool = db
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM domains WHERE domain = ?1",
+            [&domain],
+            |r| r.get(0),
+        )
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+    if !exists {
+        return Err(api_error(StatusCode::NOT_FOUND, "domain not found"));
+    }
+
+    let inserted = add(&db, &domain, &address, &reason)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+    if inserted == 0 {
+        return Err(api_error(StatusCode::CONFLICT, "address already suppressed"));
+    }
+
+    info!(domain, address, "suppression added");
+    Ok((
+        StatusCode::CREATED,
+        Json(SuppressionEntry {
+            address,
+            reason,
+            created_at: now_millis(),
+        }),
+    ))
+}
+
+pub(crate) async fn delete_suppression_handler(
+    State(state): State<Arc<AppState>>,
+    Path((domain, address)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    let domain = domain.to_lowercase();
+    let address = address.to_lowercase();
+    let db = state.db.lock().await;
+    let deleted = db
+        .execute(
+            "DELETE FROM suppressions WHERE domain = ?1 AND address = ?2",
+            [&domain, &address],
+        )
+        .unwrap_or(0);
+
+    if deleted == 0 {
+        Err(api_error(StatusCode::NOT_FOUND, "address not suppressed"))
+    } else {
+        info!(domain, address, "suppression removed");
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        add(&conn, "example.com", "gone@example.org", "manual").unwrap();
+        add(&conn, "other.com", "alive@example.org", "manual").unwrap();
+
+        let to = vec!["Gone <GONE@example.org>".to_string(), "alive@example.org".to_string()];
+        let (allowed, suppressed) = partition(&conn, "example.com", &to).unwrap();
+        assert_eq!(allowed, vec!["alive@example.org"]);
+        assert_eq!(suppressed, vec!["Gone <GONE@example.org>"]);
+    }
+
+    #[test]
+    fn test_bounced_recipients() {
+        let to = vec!["a@example.org".to_string(), "B <b@example.org>".to_string()];
+        assert_eq!(
+            bounced_recipients(&to, "permanent error (550): <B@example.org>: user unknown"),
+            vec!["b@example.org"]
+        );
+        // Ambiguous with several recipients: suppress nobody
+        assert!(bounced_recipients(&to, "permanent error (550): 5.1.1 user unknown").is_empty());
+        assert_eq!(
+            bounced_recipients(&to[..1], "permanent error (550): 5.1.1 user unknown"),
+            vec!["a@example.org"]
+        );
+        for reply in [
+            "permanent error (550): mailbox unavailable",
+            "permanent error (553): 5.1.8 sender domain does not exist",
+            "permanent error (550): 5.1.0 <app@example.com>: sender rejected",
+            "permanent error (552): 5.3.4 message too big",
+            "permanent error (554): 5.7.1 message rejected as spam",
+            "permanent error (535): 5.7.8 authentication failed",
+        ] {
+            assert!(bounced_recipients(&to[..1], reply).is_empty(), "{reply}");
+        }
+    }
+
+    #[test]
+    fn test_record_bounce() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        let to = vec!["a@example.org".to_string(), "B <b@example.org>".to_string()];
+        let bounced = record_bounce(&conn, "example.com", &to, "permanent error (550): <b@example.org>: no such user");
+        assert_eq!(bounced, vec!["B <b@example.org>"]);
+        let (allowed, _) = partition(&conn, "example.com", &to).unwrap();
+        assert_eq!(allowed, vec!["a@example.org"]);
+    }
+}
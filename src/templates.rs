@@ -0,0 +1,557 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use maud::{DOCTYPE, html};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{ApiError, AppState, EmailContent, api_error, now_millis};
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreateTemplateRequest {
+    id: String,
+    #[serde(flatten)]
+    version: TemplateVersionRequest,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct TemplateVersionRequest {
+    subject: String,
+    body: String,
+    html: Option<String>,
+    sample_data: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TemplateVersionCreated {
+    id: String,
+    version: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TemplateListEntry {
+    id: String,
+    latest_version: i64,
+    created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TemplateVersion {
+    version: i64,
+    subject: String,
+    body: String,
+    html: Option<String>,
+    sample_data: Option<Value>,
+    created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TemplateDetail {
+    id: String,
+    created_at: i64,
+    versions: Vec<TemplateVersion>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct PreviewRequest {
+    version: Option<i64>,
+    data: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PreviewQuery {
+    version: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PreviewResponse {
+    version: i64,
+    subject: String,
+    text: String,
+    html: Option<String>,
+}
+
+// ── Rendering ───────────────────────────────────────────────────────────────
+
+/// Substitutes `{{ name }}` placeholders with values from `data`. Dotted names
+/// walk into nested objects. A missing variable is an error rather than an
+/// empty string so that a half-rendered mail never goes out.
+pub(crate) fn render(src: &str, data: &Value, escape: bool) -> Result<String, String> {
+    let mut out = String::with_capacity(src.len());
+    let mut rest = src;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| "unclosed '{{' in template".to_string())?;
+        let key = after[..end].trim();
+
+        let value = lookup(data, key).ok_or_else(|| format!("missing template variable '{key}'"))?;
+        let text = match value {
+            Value::String(s) => s.clone(),
+            Value::Null => String::new(),
+            other => other.to_string(),
+        };
+
+        if escape {
+            out.push_str(&escape_html(&text));
+        } else {
+            out.push_str(&text);
+        }
+        rest = &after[end + 2..];
+    }
+
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn lookup<'a>(data: &'a Value, key: &str) -> Option<&'a Value> {
+    if key.is_empty() {
+        return None;
+    }
+    key.split('.').try_fold(data, |v, part| v.get(part))
+}
+
+pub(crate) fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// ── Database ────────────────────────────────────────────────────────────────
+
+pub(crate) fn init_db(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS templates (
+            id TEXT PRIMARY KEY,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS template_versions (
+            template_id TEXT NOT NULL REFERENCES templates(id) ON DELETE CASCADE,
+            version INTEGER NOT NULL,
+            subject TEXT NOT NULL,
+            body TEXT NOT NULL,
+            html TEXT,
+            sample_data TEXT,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (template_id, version)
+        );",
+    )
+    .expect("failed to initialize template tables");
+}
+
+/// Appends a new version. Sample data carries over from the previous version
+/// when the request does not supply any.
+fn insert_version(
+    conn: &Connection,
+    id: &str,
+    req: &TemplateVersionRequest,
+) -> rusqlite::Result<i64> {
+    let sample_data = req.sample_data.as_ref().map(|v| v.to_string());
+    conn.query_row(
+        "INSERT INTO template_versions (template_id, version, subject, body, html, sample_data, created_at)
+         SELECT ?1, COALESCE(MAX(version), 0) + 1, ?2, ?3, ?4,
+                COALESCE(?5, (SELECT sample_data FROM template_versions
+                              WHERE template_id = ?1 ORDER BY version DESC LIMIT 1)),
+                ?6
+         FROM template_versions WHERE template_id = ?1
+         RETURNING version",
+        rusqlite::params![id, &req.subject, &req.body, &req.html, sample_data, now_millis()],
+        |r| r.get(0),
+    )
+}
+
+fn load_version(
+    conn: &Connection,
+    id: &str,
+    version: Option<i64>,
+) -> rusqlite::Result<Option<TemplateVersion>> {
+    conn.query_row(
+        "SELECT version, subject, body, html, sample_data, created_at
+         FROM template_versions
+         WHERE template_id = ?1 AND (?2 IS NULL OR version = ?2)
+         ORDER BY version DESC LIMIT 1",
+        rusqlite::params![id, version],
+        version_from_row,
+    )
+    .optional()
+}
+
+fn version_from_row(row: &rusqlite::Row) -> rusqlite::Result<TemplateVersion> {
+    let sample_data: Option<String> = row.get(4)?;
+    Ok(TemplateVersion {
+        version: row.get(0)?,
+        subject: row.get(1)?,
+        body: row.get(2)?,
+        html: row.get(3)?,
+        sample_data: sample_data.and_then(|s| serde_json::from_str(&s).ok()),
+        created_at: row.get(5)?,
+    })
+}
+
+fn render_version(tv: &TemplateVersion, data: &Value) -> Result<EmailContent, String> {
+    Ok(EmailContent {
+        subject: render(&tv.subject, data, false)?,
+        body: render(&tv.body, data, false)?,
+        html: tv
+            .html
+            .as_deref()
+            .map(|h| render(h, data, true))
+            .transpose()?,
+        ..Default::default()
+    })
+}
+
+/// Renders the requested (or latest) version of a stored template for sending.
+pub(crate) fn render_stored(
+    conn: &Connection,
+    id: &str,
+    version: Option<i64>,
+    data: &Value,
+) -> Result<EmailContent, String> {
+    let tv = load_version(conn, id, version)
+        .map_err(|e| format!("db error: {e}"))?
+        .ok_or_else(|| match version {
+            Some(v) => format!("template '{id}' has no version {v}"),
+            None => format!("unknown template '{id}'"),
+        })?;
+    render_version(&tv, data)
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+fn valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn db_error(e: rusqlite::Error) -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}"))
+}
+
+pub(crate) async fn create_template_handler(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreateTemplateRequest>,
+) -> Result<(StatusCode, Json<TemplateVersionCreated>), ApiError> {
+    let id = payload.id.trim().to_string();
+    if !valid_id(&id) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "template id must be non-empty and contain only letters, digits, '-', '_' or '.'",
+        ));
+    }
+
+    let mut db = state.db.lock().await;
+    let tx = db.transaction().map_err(db_error)?;
+    tx.execute(
+        "INSERT INTO templates (id, created_at) VALUES (?1, ?2)",
+        rusqlite::params![&id, now_millis()],
+    )
+    .map_err(|_| api_error(StatusCode::CONFLICT, "template already exists"))?;
+    let version = insert_version(&tx, &id, &payload.version).map_err(db_error)?;
+    tx.commit().map_err(db_error)?;
+
+    info!(template = id, version, "template created");
+    Ok((
+        StatusCode::CREATED,
+        Json(TemplateVersionCreated { id, version }),
+    ))
+}
+
+pub(crate) async fn create_version_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Json(payload): Json<TemplateVersionRequest>,
+) -> Result<(StatusCode, Json<TemplateVersionCreated>), ApiError> {
+    let db = state.db.lock().await;
+    let exists: bool = db
+        .query_row("SELECT COUNT(*) > 0 FROM templates WHERE id = ?1", [&id], |r| r.get(0))
+        .map_err(db_error)?;
+    if !exists {
+        return Err(api_error(StatusCode::NOT_FOUND, "template not found"));
+    }
+
+    let version = insert_version(&db, &id, &payload).map_err(db_error)?;
+
+    info!(template = id, version, "template version added");
+    Ok((
+        StatusCode::CREATED,
+        Json(TemplateVersionCreated { id, version }),
+    ))
+}
+
+fn list_templates(conn: &Connection) -> Vec<TemplateListEntry> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT t.id, COALESCE(MAX(v.version), 0), t.created_at
+             FROM templates t LEFT JOIN template_versions v ON v.template_id = t.id
+             GROUP BY t.id ORDER BY t.id",
+        )
+        .unwrap();
+    stmt.query_map([], |row| {
+        Ok(TemplateListEntry {
+            id: row.get(0)?,
+            latest_version: row.get(1)?,
+            created_at: row.get(2)?,
+        })
+    })
+    .unwrap()
+    .filter_map(|r| r.ok())
+    .collect()
+}
+
+pub(crate) async fn list_templates_handler(
+    State(state): State<Arc<AppState>>,
+) -> Json<Vec<TemplateListEntry>> {
+    let db = state.db.lock().await;
+    Json(list_templates(&db))
+}
+
+pub(crate) async fn get_template_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<TemplateDetail>, ApiError> {
+    let db = state.db.lock().await;
+    let created_at: i64 = db
+        .query_row("SELECT created_at FROM templates WHERE id = ?1", [&id], |r| r.get(0))
+        .optional()
+        .map_err(db_error)?
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "template not found"))?;
+
+    let mut stmt = db
+        .prepare(
+            "SELECT version, subject, body, html, sample_data, created_at
+             FROM template_versions WHERE template_id = ?1 ORDER BY version",
+        )
+        .map_err(db_error)?;
+    let versions: Vec<TemplateVersion> = stmt
+        .query_map([&id], version_from_row)
+        .map_err(db_error)?
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Ok(Json(TemplateDetail {
+        id,
+        created_at,
+        versions,
+    }))
+}
+
+pub(crate) async fn delete_template_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let db = state.db.lock().await;
+    let tx = db.unchecked_transaction().map_err(db_error)?;
+    tx.execute("DELETE FROM template_versions WHERE template_id = ?1", [&id])
+        .map_err(db_error)?;
+    let deleted = tx
+        .execute("DELETE FROM templates WHERE id = ?1", [&id])
+        .map_err(db_error)?;
+    if deleted == 0 {
+        return Err(api_error(StatusCode::NOT_FOUND, "template not found"));
+    }
+    tx.commit().map_err(db_error)?;
+
+    info!(template = id, "template deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn preview(
+    state: &AppState,
+    id: &str,
+    version: Option<i64>,
+    data: Option<Value>,
+) -> Result<PreviewResponse, ApiError> {
+    let tv = {
+        let db = state.db.lock().await;
+        load_version(&db, id, version)
+            .map_err(db_error)?
+            .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "template version not found"))?
+    };
+
+    let data = data
+        .or_else(|| tv.sample_data.clone())
+        .unwrap_or(Value::Null);
+    let content =
+        render_version(&tv, &data).map_err(|e| api_error(StatusCode::UNPROCESSABLE_ENTITY, e))?;
+
+    Ok(PreviewResponse {
+        version: tv.version,
+        subject: content.subject,
+        text: content.body,
+        html: content.html,
+    })
+}
+
+pub(crate) async fn preview_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    payload: Option<Json<PreviewRequest>>,
+) -> Result<Json<PreviewResponse>, ApiError> {
+    let Json(payload) = payload.unwrap_or_default();
+    preview(&state, &id, payload.version, payload.data)
+        .await
+        .map(Json)
+}
+
+pub(crate) async fn preview_page_handler(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(query): Query<PreviewQuery>,
+) -> (StatusCode, maud::Markup) {
+    let result = preview(&state, &id, query.version, None).await;
+    let latest = {
+        let db = state.db.lock().await;
+        list_templates(&db)
+            .into_iter()
+            .find(|t| t.id == id)
+            .map(|t| t.latest_version)
+            .unwrap_or(0)
+    };
+
+    let status = match &result {
+        Ok(_) => StatusCode::OK,
+        Err((status, _)) => *status,
+    };
+
+    let page = html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { "mayl · " (id) }
+                style {
+                    (maud::PreEscaped("
+                        * { margin: 0; padding: 0; box-sizing: border-box; }
+                        body { font-family: system-ui, -apple-system, sans-serif; background: #0a0a0a; color: #e0e0e0; padding: 2rem; }
+                        .container { max-width: 860px; margin: 0 auto; }
+                        h1 { font-size: 1.5rem; margin-bottom: 0.5rem; color: #fff; font-family: monospace; }
+                        a { color: #6cb6ff; }
+                        .versions { margin-bottom: 1.5rem; font-size: 0.875rem; color: #888; }
+                        .versions a, .versions strong { margin-right: 0.5rem; font-family: monospace; }
+                        .card { background: #161616; border: 1px solid #2a2a2a; border-radius: 8px; padding: 1.25rem; margin-bottom: 1rem; }
+                        .card h2 { font-size: 0.875rem; text-transform: uppercase; letter-spacing: 0.05em; color: #888; margin-bottom: 0.75rem; }
+                        .subject { font-size: 1.125rem; color: #fff; }
+                        pre { white-space: pre-wrap; font-size: 0.875rem; color: #ccc; }
+                        iframe { width: 100%; height: 480px; border: 0; border-radius: 4px; background: #fff; }
+                        .empty { color: #555; font-style: italic; font-size: 0.875rem; }
+                        .err { color: #f85149; font-family: monospace; font-size: 0.875rem; }
+                    "))
+                }
+            }
+            body {
+                .container {
+                    h1 { (id) }
+                    p.versions {
+                        a href="/" { "← dashboard" }
+                        " versions: "
+                        @for v in 1..=latest {
+                            @if result.as_ref().is_ok_and(|p| p.version == v) {
+                                strong { "v" (v) }
+                            } @else {
+                                a href={ "/templates/" (id) "/preview?version=" (v) } { "v" (v) }
+                            }
+                        }
+                    }
+                    @match &result {
+                        Ok(p) => {
+                            .card {
+                                h2 { "Subject" }
+                                p.subject { (p.subject) }
+                            }
+                            .card {
+                                h2 { "HTML" }
+                                @if let Some(h) = &p.html {
+                                    iframe sandbox="" srcdoc=(h) {}
+                                } @else {
+                                    p.empty { "no HTML part" }
+                                }
+                            }
+                            .card {
+                                h2 { "Text" }
+                                pre { (p.text) }
+                            }
+                        }
+                        Err((_, Json(e))) => {
+                            .card {
+                                h2 { "Error" }
+                                p.err { (e.error) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    (status, page)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_substitutes_and_escapes() {
+        let data = json!({"name": "<Ada>", "order": {"id": 42}});
+        assert_eq!(
+            render("Hi {{ name }}, order {{order.id}}", &data, false).unwrap(),
+            "Hi <Ada>, order 42"
+        );
+        assert_eq!(
+            render("<p>{{name}}</p>", &data, true).unwrap(),
+            "<p>&lt;Ada&gt;</p>"
+        );
+        assert!(render("{{ missing }}", &data, false).is_err());
+        assert!(render("{{ name", &data, false).is_err());
+    }
+
+    #[test]
+    fn test_template_versions() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute("INSERT INTO templates (id, created_at) VALUES ('welcome', 0)", [])
+            .unwrap();
+
+        let req = |subject: &str| TemplateVersionRequest {
+            subject: subject.into(),
+            body: "Hello {{name}}".into(),
+            html: None,
+            sample_data: None,
+        };
+        assert_eq!(insert_version(&conn, "welcome", &req("v1 {{name}}")).unwrap(), 1);
+        assert_eq!(insert_version(&conn, "welcome", &req("v2 {{name}}")).unwrap(), 2);
+
+        let data = json!({"name": "Ada"});
+        let latest = render_stored(&conn, "welcome", None, &data).unwrap();
+        assert_eq!(latest.subject, "v2 Ada");
+        let pinned = render_stored(&conn, "welcome", Some(1), &data).unwrap();
+        assert_eq!(pinned.subject, "v1 Ada");
+        assert!(render_stored(&conn, "welcome", Some(3), &data).is_err());
+        assert!(render_stored(&conn, "nope", None, &data).is_err());
+    }
+}
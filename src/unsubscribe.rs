@@ -0,0 +1,355 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use maud::{DOCTYPE, Markup, html};
+use openssl::{base64, hash::MessageDigest, memcmp, pkey::PKey, rand::rand_bytes, sign::Signer};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{ApiError, AppState, api_error, normalize_address, now_millis};
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct UnsubscribeQuery {
+    list: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct UnsubscribeEntry {
+    list: String,
+    address: String,
+    created_at: i64,
+}
+
+// ── Database ────────────────────────────────────────────────────────────────
+
+pub(crate) fn init_db(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS unsubscribes (
+            domain TEXT NOT NULL,
+            list TEXT NOT NULL,
+            address TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            PRIMARY KEY (domain, list, address)
+        );",
+    )
+    .expect("failed to initialize unsubscribes table");
+}
+
+pub(crate) fn delete_domain(conn: &Connection, domain: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM unsubscribes WHERE domain = ?1", [domain]).map(|_| ())
+}
+
+/// The key unsubscribe links are signed with, generated on first use and
+/// kept in the `config` table so links survive restarts.
+fn secret(conn: &Connection) -> Result<Vec<u8>, String> {
+    let stored: Option<String> = conn
+        .query_row(
+            "SELECT value FROM config WHERE key = 'unsubscribe_secret'",
+            [],
+            |r| r.get(0),
+        )
+        .optional()
+        .map_err(|e| format!("db error: {e}"))?;
+    if let Some(s) = stored {
+        return base64::decode_block(&s).map_err(|e| format!("unsubscribe secret: {e}"));
+    }
+
+    let mut key = vec![0u8; 32];
+    rand_bytes(&mut key).map_err(|e| format!("unsubscribe secret: {e}"))?;
+    conn.execute(
+        "INSERT INTO config (key, value) VALUES ('unsubscribe_secret', ?1)",
+        [base64::encode_block(&key)],
+    )
+    .map_err(|e| format!("db error: {e}"))?;
+    Ok(key)
+}
+
+/// Splits `to` into recipients still subscribed to `list` and those who
+/// unsubscribed, keeping the original spelling of each.
+pub(crate) fn partition(
+    conn: &Connection,
+    domain: &str,
+    list: &str,
+    to: &[String],
+) -> Result<(Vec<String>, Vec<String>), String> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT COUNT(*) > 0 FROM unsubscribes WHERE domain = ?1 AND list = ?2 AND address = ?3",
+        )
+        .map_err(|e| format!("db error: {e}"))?;
+
+    let mut allowed = Vec::with_capacity(to.len());
+    let mut unsubscribed = Vec::new();
+    for addr in to {
+        let address = normalize_address(addr)?;
+        let hit: bool = stmt
+            .query_row([domain, list, &address], |r| r.get(0))
+            .map_err(|e| format!("db error: {e}"))?;
+        if hit {
+            unsubscribed.push(addr.clone());
+        } else {
+            allowed.push(addr.clone());
+        }
+    }
+    Ok((allowed, unsubscribed))
+}
+
+// ── Tokens ──────────────────────────────────────────────────────────────────
+
+fn base64url(data: &[u8]) -> String {
+    base64::encode_block(data)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+fn from_base64url(s: &str) -> Option<Vec<u8>> {
+    let mut padded = s.replace('-', "+").replace('_', "/");
+    while !padded.len().is_multiple_of(4) {
+        padded.push('=');
+    }
+    base64::decode_block(&padded).ok()
+}
+
+fn mac(secret: &[u8], payload: &[u8]) -> Result<Vec<u8>, String> {
+    let key = PKey::hmac(secret).map_err(|e| format!("hmac: {e}"))?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(|e| format!("hmac: {e}"))?;
+    signer
+        .sign_oneshot_to_vec(payload)
+        .map_err(|e| format!("hmac: {e}"))
+}
+
+/// `base64url(domain \n list \n address) . base64url(HMAC-SHA256)`
+fn sign_token(secret: &[u8], domain: &str, list: &str, address: &str) -> Result<String, String> {
+    let payload = format!("{domain}\n{list}\n{address}");
+    let tag = mac(secret, payload.as_bytes())?;
+    Ok(format!("{}.{}", base64url(payload.as_bytes()), base64url(&tag)))
+}
+
+/// The `(domain, list, address)` a token was issued for, if its signature holds.
+fn verify_token(secret: &[u8], token: &str) -> Option<(String, String, String)> {
+    let (payload, tag) = token.split_once('.')?;
+    let payload = from_base64url(payload)?;
+    let tag = from_base64url(tag)?;
+    let expected = mac(secret, &payload).ok()?;
+    if tag.len() != expected.len() || !memcmp::eq(&tag, &expected) {
+        return None;
+    }
+
+    let payload = String::from_utf8(payload).ok()?;
+    let mut parts = payload.splitn(3, '\n');
+    Some((
+        parts.next()?.to_string(),
+        parts.next()?.to_string(),
+        parts.next()?.to_string(),
+    ))
+}
+
+/// The one-click unsubscribe URL for `addr` on `list`.
+pub(crate) fn link(
+    conn: &Connection,
+    public_url: &str,
+    domain: &str,
+    list: &str,
+    addr: &str,
+) -> Result<String, String> {
+    let address = normalize_address(addr)?;
+    let token = sign_token(&secret(conn)?, domain, list, &address)?;
+    Ok(format!("{public_url}/unsubscribe/{token}"))
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+fn page(title: &str, body: Markup) -> Markup {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                meta name="viewport" content="width=device-width, initial-scale=1";
+                title { (title) }
+                style {
+                    "body { font-family: -apple-system, Segoe UI, Helvetica, Arial, sans-serif; max-width: 480px; margin: 4rem auto; padding: 0 1rem; color: #222; }"
+                    "button { font-size: 1rem; padding: 0.5rem 1.25rem; cursor: pointer; }"
+                }
+            }
+            body {
+                h1 { (title) }
+                (body)
+            }
+        }
+    }
+}
+
+fn invalid_link() -> (StatusCode, Markup) {
+    (
+        StatusCode::NOT_FOUND,
+        page(
+            "Invalid link",
+            html! { p { "This unsubscribe link is not valid." } },
+        ),
+    )
+}
+
+async fn resolve_token(state: &AppState, token: &str) -> Option<(String, String, String)> {
+    let db = state.db.lock().await;
+    let secret = secret(&db).ok()?;
+    verify_token(&secret, token)
+}
+
+/// A confirmation page for people who open the link in a browser. Link
+/// scanners follow GETs, so only the POST unsubscribes.
+pub(crate) async fn unsubscribe_page_handler(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> Result<Markup, (StatusCode, Markup)> {
+    let (_, list, address) = resolve_token(&state, &token)
+        .await
+        .ok_or_else(invalid_link)?;
+
+    Ok(page(
+        "Unsubscribe",
+        html! {
+            p { "Stop sending " strong { (list) } " mail to " strong { (address) } "?" }
+            form method="post" {
+                input type="hidden" name="List-Unsubscribe" value="One-Click";
+                button type="submit" { "Unsubscribe" }
+            }
+        },
+    ))
+}
+
+/// RFC 8058 one-click endpoint; mail clients POST `List-Unsubscribe=One-Click`.
+pub(crate) async fn unsubscribe_handler(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> Result<Markup, (StatusCode, Markup)> {
+    let (domain, list, address) = resolve_token(&state, &token)
+        .await
+        .ok_or_else(invalid_link)?;
+
+    let db = state.db.lock().await;
+    db.execute(
+        "INSERT INTO unsubscribes (domain, list, address, created_at) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT(domain, list, address) DO NOTHING",
+        rusqlite::params![&domain, &list, &address, now_millis()],
+    )
+    .map_err(|_| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            page("Something went wrong", html! { p { "Please try again later." } }),
+        )
+    })?;
+
+    info!(domain, list, address, "unsubscribed");
+    Ok(page(
+        "Unsubscribed",
+        html! { p { (address) " will no longer receive " strong { (list) } " mail." } },
+    ))
+}
+
+pub(crate) async fn list_unsubscribes_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> Json<Vec<UnsubscribeEntry>> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let mut stmt = db
+        .prepare(
+            "SELECT list, address, created_at FROM unsubscribes
+             WHERE domain = ?1 AND (?2 IS NULL OR list = ?2)
+             ORDER BY list, address",
+        )
+        .unwrap();
+    let entries: Vec<UnsubscribeEntry> = stmt
+        .query_map(rusqlite::params![&domain, &query.list], |row| {
+            Ok(UnsubscribeEntry {
+                list: row.get(0)?,
+                address: row.get(1)?,
+                created_at: row.get(2)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Json(entries)
+}
+
+pub(crate) async fn delete_unsubscribe_handler(
+    State(state): State<Arc<AppState>>,
+    Path((domain, list, address)): Path<(String, String, String)>,
+) -> Result<StatusCode, ApiError> {
+    let domain = domain.to_lowercase();
+    let address = address.to_lowercase();
+    let db = state.db.lock().await;
+    let deleted = db
+        .execute(
+            "DELETE FROM unsubscribes WHERE domain = ?1 AND list = ?2 AND address = ?3",
+            [&domain, &list, &address],
+        )
+        .unwrap_or(0);
+
+    if deleted == 0 {
+        Err(api_error(StatusCode::NOT_FOUND, "address is not unsubscribed"))
+    } else {
+        info!(domain, list, address, "resubscribed");
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_roundtrip() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+
+        let url = link(&conn, "https://mail.example.com", "example.com", "news", "Ada <ADA@example.org>")
+            .unwrap();
+        let token = url.strip_prefix("https://mail.example.com/unsubscribe/").unwrap();
+        assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)), "{token}");
+
+        // The secret is persisted, so the token still verifies later
+        let secret = secret(&conn).unwrap();
+        assert_eq!(
+            verify_token(&secret, token),
+            Some(("example.com".into(), "news".into(), "ada@example.org".into()))
+        );
+
+        let (payload, tag) = token.split_once('.').unwrap();
+        let forged = sign_token(b"other secret", "example.com", "news", "ada@example.org").unwrap();
+        assert_eq!(verify_token(&secret, &forged), None);
+        assert_eq!(verify_token(&secret, &format!("{payload}x.{tag}")), None);
+    }
+
+    #[test]
+    fn test_partition() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        conn.execute(
+            "INSERT INTO unsubscribes VALUES ('example.com', 'news', 'gone@example.org', 0)",
+            [],
+        )
+        .unwrap();
+
+        let to = vec!["gone@example.org".to_string(), "stay@example.org".to_string()];
+        let (allowed, unsubscribed) = partition(&conn, "example.com", "news", &to).unwrap();
+        assert_eq!(allowed, vec!["stay@example.org"]);
+        assert_eq!(unsubscribed, vec!["gone@example.org"]);
+
+        // Other lists are unaffected
+        let (allowed, _) = partition(&conn, "example.com", "digest", &to).unwrap();
+        assert_eq!(allowed.len(), 2);
+    }
+}
@@ -0,0 +1,977 @@
+//! Named SMTP relays, for running several accounts (one Bridge login per
+//! brand, say) side by side. A domain mapped to upstreams sends through the
+//! first of them that is healthy; every other domain uses `MAYL_SMTP_HOST`
+//! with the `POST /smtp` credentials.
+//!
+//! Each relay has a circuit breaker. After `MAYL_FAILOVER_THRESHOLD`
+//! consecutive connection or authentication failures it opens and mail
+//! moves on to the next upstream in the domain's list. Once
+//! `MAYL_FAILOVER_COOLDOWN_SECONDS` have passed the relay is tried again,
+//! and a success closes the breaker so mail returns to it.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use lettre::{
+    AsyncSmtpTransport, Tokio1Executor,
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Tls, TlsParameters},
+    },
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{ApiError, AppState, api_error, config, now_millis};
+
+/// The name of the configured relay, usable in a domain's upstream list.
+pub(crate) const DEFAULT: &str = "default";
+
+/// How long `Upstream::check` waits for the relay.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// SMTP replies that say the relay itself is unusable rather than the
+/// message: service unavailable and the authentication failures.
+const RELAY_FAILURE_CODES: [u16; 5] = [421, 454, 530, 534, 535];
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TlsMode {
+    /// Plain connection upgraded with STARTTLS, which must succeed.
+    #[default]
+    StartTls,
+    /// TLS from the first byte (SMTPS, usually port 465).
+    Tls,
+    None,
+}
+
+impl TlsMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            TlsMode::StartTls => "starttls",
+            TlsMode::Tls => "tls",
+            TlsMode::None => "none",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "starttls" => Some(TlsMode::StartTls),
+            "tls" => Some(TlsMode::Tls),
+            "none" => Some(TlsMode::None),
+            _ => None,
+        }
+    }
+}
+
+/// A relay mail can be sent through.
+#[derive(Debug, Clone)]
+pub(crate) struct Upstream {
+    pub(crate) name: String,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) tls: TlsMode,
+    pub(crate) user: String,
+    pub(crate) pass: String,
+    /// Check the relay's certificate. Off by default, since Bridge presents
+    /// a self-signed one.
+    pub(crate) verify_tls: bool,
+}
+
+impl Upstream {
+    pub(crate) fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, String> {
+        let tls_params = || {
+            TlsParameters::builder(self.host.clone())
+                .dangerous_accept_invalid_certs(!self.verify_tls)
+                .build()
+                .map_err(|e| format!("TLS parameters for '{}': {e}", self.name))
+        };
+        let tls = match self.tls {
+            TlsMode::StartTls => Tls::Required(tls_params()?),
+            TlsMode::Tls => Tls::Wrapper(tls_params()?),
+            TlsMode::None => Tls::None,
+        };
+
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.host)
+            .port(self.port)
+            .tls(tls);
+        if !self.user.is_empty() {
+            builder = builder.credentials(Credentials::new(self.user.clone(), self.pass.clone()));
+        }
+        Ok(builder.build())
+    }
+
+    /// Connects, logs in when there are credentials and sends NOOP: what
+    /// sending would do, short of a message.
+    pub(crate) async fn check(&self) -> Result<(), CheckError> {
+        let unreachable = |e: String| CheckError {
+            refused: false,
+            message: format!("cannot reach SMTP relay {}:{}: {e}", self.host, self.port),
+        };
+        let transport = self.transport().map_err(unreachable)?;
+        match tokio::time::timeout(CHECK_TIMEOUT, transport.test_connection()).await {
+            Ok(Ok(true)) => Ok(()),
+            Ok(Ok(false)) => Err(unreachable("NOOP failed".into())),
+            Ok(Err(e)) if e.status().is_some() => Err(CheckError {
+                refused: true,
+                message: format!("SMTP relay {}:{} refused the login: {e}", self.host, self.port),
+            }),
+            Ok(Err(e)) => Err(unreachable(e.to_string())),
+            Err(_) => Err(unreachable(format!("no answer within {}s", CHECK_TIMEOUT.as_secs()))),
+        }
+    }
+}
+
+/// A failed `Upstream::check`. `refused` when the relay answered but turned
+/// the login down, as opposed to not answering at all.
+#[derive(Debug)]
+pub(crate) struct CheckError {
+    pub(crate) refused: bool,
+    pub(crate) message: String,
+}
+
+impl std::fmt::Display for CheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// A refused login is the caller's mistake (`400`); an unreachable relay is
+/// `502`.
+pub(crate) fn check_error(e: CheckError) -> ApiError {
+    let status = if e.refused {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::BAD_GATEWAY
+    };
+    api_error(status, e.message)
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct UpstreamRequest {
+    host: String,
+    port: u16,
+    #[serde(default)]
+    tls: TlsMode,
+    #[serde(default)]
+    user: String,
+    /// Kept from the stored upstream when omitted on `PUT`.
+    pass: Option<String>,
+    #[serde(default)]
+    verify_tls: bool,
+}
+
+impl UpstreamRequest {
+    fn upstream(&self, name: &str, pass: String) -> Upstream {
+        Upstream {
+            name: name.to_string(),
+            host: self.host.trim().to_string(),
+            port: self.port,
+            tls: self.tls,
+            user: self.user.clone(),
+            pass,
+            verify_tls: self.verify_tls,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct VerifyQuery {
+    /// Log in to the relay with the new settings before saving them.
+    verify: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreateUpstreamRequest {
+    name: String,
+    #[serde(flatten)]
+    settings: UpstreamRequest,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct UpstreamEntry {
+    name: String,
+    host: String,
+    port: u16,
+    tls: TlsMode,
+    user: String,
+    verify_tls: bool,
+    /// Domains that send through this upstream.
+    domains: Vec<String>,
+    created_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct DomainUpstreamRequest {
+    /// Tried in order, each taking over while those before it are failing.
+    #[serde(default)]
+    upstreams: Vec<String>,
+    /// Shorthand for a list of one.
+    upstream: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DomainUpstreamResponse {
+    domain: String,
+    /// Empty when the domain uses the default relay.
+    upstreams: Vec<String>,
+}
+
+// ── Circuit Breakers ────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Failover {
+    /// Consecutive failures that open a relay's breaker.
+    pub(crate) threshold: u32,
+    /// How long an open breaker keeps mail away from its relay.
+    pub(crate) cooldown: Duration,
+}
+
+impl Failover {
+    pub(crate) fn load(source: &config::Source) -> Self {
+        let threshold = source.parse("MAYL_FAILOVER_THRESHOLD", 3);
+        if threshold == 0 {
+            source.invalid("MAYL_FAILOVER_THRESHOLD", "must be at least 1");
+        }
+        Self {
+            threshold,
+            cooldown: Duration::from_secs(source.parse("MAYL_FAILOVER_COOLDOWN_SECONDS", 60)),
+        }
+    }
+}
+
+/// Whether `e` means the relay could not be reached or would not let us
+/// in, as opposed to a refusal of this particular message.
+pub(crate) fn is_relay_failure(e: &lettre::transport::smtp::Error) -> bool {
+    match e.status() {
+        Some(code) => RELAY_FAILURE_CODES.contains(&u16::from(code)),
+        None => !e.is_client(),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<Instant>,
+    last_error: Option<String>,
+}
+
+/// Breaker state per relay, kept in memory: a restart gives every relay a
+/// fresh start.
+#[derive(Debug, Default)]
+pub(crate) struct Breakers(std::sync::Mutex<HashMap<String, Breaker>>);
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BreakerStatus {
+    name: String,
+    /// `closed`, `open`, or `half_open` once the cooldown is over and the
+    /// next message will try the relay again.
+    pub(crate) state: &'static str,
+    consecutive_failures: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_in_seconds: Option<u64>,
+}
+
+impl Breakers {
+    /// Whether mail may be sent through `name`: its breaker is closed, or
+    /// open with the cooldown over.
+    pub(crate) fn available(&self, name: &str) -> bool {
+        let breakers = self.0.lock().unwrap();
+        breakers
+            .get(name)
+            .and_then(|b| b.open_until)
+            .is_none_or(|until| Instant::now() >= until)
+    }
+
+    /// Records a connection or authentication failure, returning whether
+    /// the breaker is now open. A failed retry after the cooldown reopens it
+    /// straight away.
+    pub(crate) fn failure(&self, name: &str, error: &str, failover: &Failover) -> bool {
+        let mut breakers = self.0.lock().unwrap();
+        let breaker = breakers.entry(name.to_string()).or_default();
+        breaker.failures += 1;
+        breaker.last_error = Some(error.to_string());
+        if breaker.failures < failover.threshold {
+            return false;
+        }
+        if breaker.open_until.is_none() {
+            warn!(upstream = name, failures = breaker.failures, "SMTP upstream failing, breaker opened");
+        }
+        breaker.open_until = Some(Instant::now() + failover.cooldown);
+        true
+    }
+
+    /// Records that `name` accepted a connection, closing its breaker.
+    pub(crate) fn success(&self, name: &str) {
+        let mut breakers = self.0.lock().unwrap();
+        if let Some(breaker) = breakers.remove(name)
+            && breaker.open_until.is_some()
+        {
+            info!(upstream = name, "SMTP upstream recovered, breaker closed");
+        }
+    }
+
+    pub(crate) fn status(&self, names: &[String]) -> Vec<BreakerStatus> {
+        let breakers = self.0.lock().unwrap();
+        let now = Instant::now();
+        names
+            .iter()
+            .map(|name| {
+                let breaker = breakers.get(name);
+                let open_until = breaker.and_then(|b| b.open_until);
+                BreakerStatus {
+                    name: name.clone(),
+                    state: match open_until {
+                        None => "closed",
+                        Some(until) if now < until => "open",
+                        Some(_) => "half_open",
+                    },
+                    consecutive_failures: breaker.map_or(0, |b| b.failures),
+                    last_error: breaker.and_then(|b| b.last_error.clone()),
+                    retry_in_seconds: open_until
+                        .filter(|until| now < *until)
+                        .map(|until| (until - now).as_secs_f64().ceil() as u64),
+                }
+            })
+            .collect()
+    }
+}
+
+// ── Database ────────────────────────────────────────────────────────────────
+
+pub(crate) fn init_db(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS smtp_upstreams (
+            name TEXT PRIMARY KEY,
+            host TEXT NOT NULL,
+            port INTEGER NOT NULL,
+            tls TEXT NOT NULL,
+            user TEXT NOT NULL,
+            pass TEXT NOT NULL,
+            verify_tls INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS domain_upstreams (
+            domain TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            upstream TEXT NOT NULL,
+            PRIMARY KEY (domain, position)
+        );",
+    )
+    .expect("failed to initialize smtp_upstreams tables");
+}
+
+/// Drops a deleted domain's relay list. The relays themselves are shared
+/// and stay.
+pub(crate) fn delete_domain(conn: &Connection, domain: &str) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM domain_upstreams WHERE domain = ?1", [domain]).map(|_| ())
+}
+
+const COLUMNS: &str = "name, host, port, tls, user, pass, verify_tls";
+
+fn upstream_from_row(row: &rusqlite::Row) -> rusqlite::Result<Upstream> {
+    Ok(Upstream {
+        name: row.get(0)?,
+        host: row.get(1)?,
+        port: row.get(2)?,
+        tls: TlsMode::parse(&row.get::<_, String>(3)?).unwrap_or_default(),
+        user: row.get(4)?,
+        pass: row.get(5)?,
+        verify_tls: row.get(6)?,
+    })
+}
+
+fn load(conn: &Connection, name: &str) -> rusqlite::Result<Option<Upstream>> {
+    conn.query_row(
+        &format!("SELECT {COLUMNS} FROM smtp_upstreams WHERE name = ?1"),
+        [name],
+        upstream_from_row,
+    )
+    .optional()
+}
+
+fn domain_list(conn: &Connection, domain: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT upstream FROM domain_upstreams WHERE domain = ?1 ORDER BY position")?;
+    stmt.query_map([domain], |r| r.get(0))?.collect()
+}
+
+/// The relays for `domain` in the order to try them, with `default`
+/// standing in for the configured relay. Unmapped domains get just that.
+pub(crate) fn for_domain(conn: &Connection, domain: &str, default: &Upstream) -> Result<Vec<Upstream>, String> {
+    let db_error = |e| format!("db error: {e}");
+    let names = domain_list(conn, domain).map_err(db_error)?;
+    if names.is_empty() {
+        return Ok(vec![default.clone()]);
+    }
+    names
+        .iter()
+        .filter_map(|name| match name.as_str() {
+            DEFAULT => Some(Ok(default.clone())),
+            name => load(conn, name).map_err(db_error).transpose(),
+        })
+        .collect()
+}
+
+/// The relays some domain sends through: those in domain lists, and
+/// `default` when it is listed or a domain has no list.
+pub(crate) fn in_use(conn: &Connection, default: &Upstream) -> rusqlite::Result<Vec<Upstream>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT m.upstream FROM domain_upstreams m JOIN domains d ON d.domain = m.domain
+         ORDER BY m.upstream",
+    )?;
+    let names: Vec<String> = stmt.query_map([], |r| r.get(0))?.collect::<rusqlite::Result<_>>()?;
+    let unmapped: bool = conn.query_row(
+        "SELECT NOT EXISTS (SELECT 1 FROM domain_upstreams)
+             OR EXISTS (SELECT 1 FROM domains WHERE domain NOT IN (SELECT domain FROM domain_upstreams))",
+        [],
+        |r| r.get(0),
+    )?;
+
+    let mut upstreams = Vec::new();
+    if unmapped || names.iter().any(|n| n == DEFAULT) {
+        upstreams.push(default.clone());
+    }
+    for name in names.iter().filter(|n| *n != DEFAULT) {
+        upstreams.extend(load(conn, name)?);
+    }
+    Ok(upstreams)
+}
+
+/// Every relay mail can go through, the configured one first.
+pub(crate) fn names(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM smtp_upstreams ORDER BY name")?;
+    let mut names = vec![DEFAULT.to_string()];
+    for name in stmt.query_map([], |r| r.get(0))? {
+        names.push(name?);
+    }
+    Ok(names)
+}
+
+fn mapped_domains(conn: &Connection, name: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT m.domain FROM domain_upstreams m JOIN domains d ON d.domain = m.domain
+         WHERE m.upstream = ?1 ORDER BY m.domain",
+    )?;
+    stmt.query_map([name], |r| r.get(0))?.collect()
+}
+
+fn entry(conn: &Connection, upstream: Upstream) -> rusqlite::Result<UpstreamEntry> {
+    let created_at = conn.query_row(
+        "SELECT created_at FROM smtp_upstreams WHERE name = ?1",
+        [&upstream.name],
+        |r| r.get(0),
+    )?;
+    Ok(UpstreamEntry {
+        domains: mapped_domains(conn, &upstream.name)?,
+        name: upstream.name,
+        host: upstream.host,
+        port: upstream.port,
+        tls: upstream.tls,
+        user: upstream.user,
+        verify_tls: upstream.verify_tls,
+        created_at,
+    })
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name != DEFAULT
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn save(conn: &Connection, name: &str, req: UpstreamRequest, pass: String) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO smtp_upstreams (name, host, port, tls, user, pass, verify_tls, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT(name) DO UPDATE SET host = excluded.host, port = excluded.port, tls = excluded.tls,
+             user = excluded.user, pass = excluded.pass, verify_tls = excluded.verify_tls",
+        rusqlite::params![name, req.host.trim(), req.port, req.tls.as_str(), req.user, pass, req.verify_tls, now_millis()],
+    )
+}
+
+fn db_error(e: rusqlite::Error) -> ApiError {
+    api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}"))
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+/// Adds an upstream once a login with its settings works, unless
+/// `?verify=false`.
+pub(crate) async fn create_upstream_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<VerifyQuery>,
+    Json(payload): Json<CreateUpstreamRequest>,
+) -> Result<(StatusCode, Json<UpstreamEntry>), ApiError> {
+    let name = payload.name.trim().to_lowercase();
+    if !valid_name(&name) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            "name must be letters, digits, '-' or '_', and not 'default'",
+        ));
+    }
+    if payload.settings.host.trim().is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "host is required"));
+    }
+
+    let pass = payload.settings.pass.clone().unwrap_or_default();
+    if query.verify.unwrap_or(true) {
+        payload.settings.upstream(&name, pass.clone()).check().await.map_err(check_error)?;
+    }
+
+    let db = state.db.lock().await;
+    if load(&db, &name).map_err(db_error)?.is_some() {
+        return Err(api_error(StatusCode::CONFLICT, "upstream already exists"));
+    }
+    save(&db, &name, payload.settings, pass).map_err(db_error)?;
+
+    info!(name, "SMTP upstream added");
+    let upstream = load(&db, &name).map_err(db_error)?.expect("just saved");
+    let entry = entry(&db, upstream).map_err(db_error)?;
+    drop(db);
+    *state.readiness.lock().await = None;
+    Ok((StatusCode::CREATED, Json(entry)))
+}
+
+pub(crate) async fn list_upstreams_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<UpstreamEntry>>, ApiError> {
+    let db = state.db.lock().await;
+    let upstreams: Vec<Upstream> = db
+        .prepare(&format!("SELECT {COLUMNS} FROM smtp_upstreams ORDER BY name"))
+        .and_then(|mut stmt| stmt.query_map([], upstream_from_row)?.collect())
+        .map_err(db_error)?;
+    let entries = upstreams
+        .into_iter()
+        .map(|u| entry(&db, u))
+        .collect::<rusqlite::Result<_>>()
+        .map_err(db_error)?;
+    Ok(Json(entries))
+}
+
+/// Replaces an upstream's settings; the password is kept when omitted. The
+/// new settings are checked as on create.
+pub(crate) async fn update_upstream_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    Query(query): Query<VerifyQuery>,
+    Json(payload): Json<UpstreamRequest>,
+) -> Result<Json<UpstreamEntry>, ApiError> {
+    let name = name.to_lowercase();
+    if payload.host.trim().is_empty() {
+        return Err(api_error(StatusCode::BAD_REQUEST, "host is required"));
+    }
+
+    let existing = load(&*state.db.lock().await, &name)
+        .map_err(db_error)?
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "upstream not found"))?;
+    let pass = payload.pass.clone().unwrap_or(existing.pass);
+    if query.verify.unwrap_or(true) {
+        payload.upstream(&name, pass.clone()).check().await.map_err(check_error)?;
+    }
+
+    // It may have been deleted while the relay was checked.
+    let db = state.db.lock().await;
+    if load(&db, &name).map_err(db_error)?.is_none() {
+        return Err(api_error(StatusCode::NOT_FOUND, "upstream not found"));
+    }
+    save(&db, &name, payload, pass).map_err(db_error)?;
+
+    info!(name, "SMTP upstream updated");
+    let upstream = load(&db, &name).map_err(db_error)?.expect("just saved");
+    let entry = entry(&db, upstream).map_err(db_error)?;
+    drop(db);
+    *state.readiness.lock().await = None;
+    Ok(Json(entry))
+}
+
+pub(crate) async fn delete_upstream_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let name = name.to_lowercase();
+    let db = state.db.lock().await;
+    let domains = mapped_domains(&db, &name).map_err(db_error)?;
+    if !domains.is_empty() {
+        return Err(api_error(
+            StatusCode::CONFLICT,
+            format!("upstream is used by {}", domains.join(", ")),
+        ));
+    }
+
+    let deleted = db
+        .execute("DELETE FROM smtp_upstreams WHERE name = ?1", [&name])
+        .map_err(db_error)?;
+    if deleted == 0 {
+        return Err(api_error(StatusCode::NOT_FOUND, "upstream not found"));
+    }
+    db.execute("DELETE FROM domain_upstreams WHERE upstream = ?1", [&name])
+        .map_err(db_error)?;
+
+    info!(name, "SMTP upstream deleted");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn check_domain(conn: &Connection, domain: &str) -> Result<(), ApiError> {
+    let exists: bool = conn
+        .query_row("SELECT COUNT(*) > 0 FROM domains WHERE domain = ?1", [domain], |r| r.get(0))
+        .map_err(db_error)?;
+    if exists {
+        Ok(())
+    } else {
+        Err(api_error(StatusCode::NOT_FOUND, "domain not found"))
+    }
+}
+
+/// Sets the relays a domain sends through, in the order to try them.
+pub(crate) async fn put_domain_upstream_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+    Json(payload): Json<DomainUpstreamRequest>,
+) -> Result<Json<DomainUpstreamResponse>, ApiError> {
+    let domain = domain.to_lowercase();
+    let upstreams: Vec<String> = match (payload.upstream, payload.upstreams.is_empty()) {
+        (Some(upstream), true) => vec![upstream],
+        (None, false) => payload.upstreams,
+        _ => return Err(api_error(StatusCode::BAD_REQUEST, "give either upstream or upstreams")),
+    }
+    .iter()
+    .map(|u| u.trim().to_lowercase())
+    .collect();
+    if let Some((i, dup)) = upstreams.iter().enumerate().find(|(i, u)| upstreams[..*i].contains(u)) {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("upstream '{dup}' is listed twice (entry {})", i + 1),
+        ));
+    }
+
+    {
+        let mut db = state.db.lock().await;
+        check_domain(&db, &domain)?;
+        for upstream in &upstreams {
+            if upstream != DEFAULT && load(&db, upstream).map_err(db_error)?.is_none() {
+                return Err(api_error(StatusCode::BAD_REQUEST, format!("unknown upstream '{upstream}'")));
+            }
+        }
+
+        let tx = db.transaction().map_err(db_error)?;
+        tx.execute("DELETE FROM domain_upstreams WHERE domain = ?1", [&domain])
+            .map_err(db_error)?;
+        for (position, upstream) in upstreams.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO domain_upstreams (domain, position, upstream) VALUES (?1, ?2, ?3)",
+                rusqlite::params![domain, position, upstream],
+            )
+            .map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)?;
+    }
+    *state.readiness.lock().await = None;
+
+    info!(domain, upstreams = upstreams.join(", "), "domain mapped to SMTP upstreams");
+    Ok(Json(DomainUpstreamResponse { domain, upstreams }))
+}
+
+pub(crate) async fn get_domain_upstream_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Result<Json<DomainUpstreamResponse>, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    check_domain(&db, &domain)?;
+    let upstreams = domain_list(&db, &domain).map_err(db_error)?;
+    Ok(Json(DomainUpstreamResponse { domain, upstreams }))
+}
+
+/// Returns the domain to the default relay.
+pub(crate) async fn delete_domain_upstream_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let deleted = db
+        .execute("DELETE FROM domain_upstreams WHERE domain = ?1", [&domain])
+        .map_err(db_error)?;
+    drop(db);
+    if deleted == 0 {
+        Err(api_error(StatusCode::NOT_FOUND, "domain has no upstream"))
+    } else {
+        *state.readiness.lock().await = None;
+        info!(domain, "domain returned to the default SMTP relay");
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request<T: serde::de::DeserializeOwned>(body: serde_json::Value) -> T {
+        serde_json::from_value(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_upstreams() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+        crate::seed_domains(&conn, &["example.com".into(), "other.com".into()]);
+        let state = crate::app_state(crate::Config::defaults(), conn);
+
+        let (status, Json(created)) = create_upstream_handler(
+            State(Arc::clone(&state)),
+            Query(VerifyQuery { verify: Some(false) }),
+            Json(request(serde_json::json!({
+                "name": "Brand-A", "host": "bridge-a", "port": 1025, "user": "a@proton.me", "pass": "secret"
+            }))),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created.name, "brand-a");
+        assert_eq!(created.tls, TlsMode::StartTls);
+
+        let (status, _) = create_upstream_handler(
+            State(Arc::clone(&state)),
+            Query(VerifyQuery { verify: Some(false) }),
+            Json(request(serde_json::json!({ "name": "default", "host": "x", "port": 25 }))),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = put_domain_upstream_handler(
+            State(Arc::clone(&state)),
+            Path("example.com".into()),
+            Json(request(serde_json::json!({ "upstream": "brand-b" }))),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = put_domain_upstream_handler(
+            State(Arc::clone(&state)),
+            Path("example.com".into()),
+            Json(request(serde_json::json!({ "upstreams": ["brand-a", "Brand-A"] }))),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let Json(mapped) = put_domain_upstream_handler(
+            State(Arc::clone(&state)),
+            Path("example.com".into()),
+            Json(request(serde_json::json!({ "upstreams": ["Brand-A", "default"] }))),
+        )
+        .await
+        .unwrap();
+        assert_eq!(mapped.upstreams, vec!["brand-a", "default"]);
+
+        // Updating without a password keeps the stored one.
+        let Json(updated) = update_upstream_handler(
+            State(Arc::clone(&state)),
+            Path("brand-a".into()),
+            Query(VerifyQuery { verify: Some(false) }),
+            Json(request(serde_json::json!({ "host": "bridge-a", "port": 1465, "tls": "tls", "user": "a@proton.me" }))),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.domains, vec!["example.com"]);
+
+        {
+            let db = state.db.lock().await;
+            let default = Upstream {
+                name: DEFAULT.into(),
+                host: "localhost".into(),
+                port: 1025,
+                tls: TlsMode::StartTls,
+                user: String::new(),
+                pass: String::new(),
+                verify_tls: false,
+            };
+            let chain = for_domain(&db, "example.com", &default).unwrap();
+            let names: Vec<&str> = chain.iter().map(|u| u.name.as_str()).collect();
+            assert_eq!(names, vec!["brand-a", "default"]);
+            assert_eq!((chain[0].port, chain[0].tls), (1465, TlsMode::Tls));
+            assert_eq!(chain[0].pass, "secret");
+            chain[0].transport().unwrap();
+            let chain = for_domain(&db, "other.com", &default).unwrap();
+            assert_eq!(chain.len(), 1);
+            assert_eq!(chain[0].name, DEFAULT);
+        }
+
+        let (status, _) = delete_upstream_handler(State(Arc::clone(&state)), Path("brand-a".into()))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::CONFLICT);
+        delete_domain_upstream_handler(State(Arc::clone(&state)), Path("example.com".into()))
+            .await
+            .unwrap();
+        let status = delete_upstream_handler(State(Arc::clone(&state)), Path("brand-a".into()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    /// A plain-text SMTP relay on localhost that accepts only `good` /
+    /// `secret` over AUTH PLAIN.
+    async fn stand_in() -> Upstream {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let (read, mut write) = socket.into_split();
+                let mut lines = BufReader::new(read).lines();
+                write.write_all(b"220 stand-in ready\r\n").await.unwrap();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let reply: &[u8] = match line.split(' ').next().unwrap().to_uppercase().as_str() {
+                        "EHLO" => b"250-stand-in\r\n250 AUTH PLAIN\r\n",
+                        // base64 of "\0good\0secret"
+                        "AUTH" if line.ends_with("AGdvb2QAc2VjcmV0") => b"235 ok\r\n",
+                        "AUTH" => b"535 bad credentials\r\n",
+                        "QUIT" => b"221 bye\r\n",
+                        _ => b"250 ok\r\n",
+                    };
+                    write.write_all(reply).await.unwrap();
+                }
+            }
+        });
+        Upstream {
+            name: "stand-in".into(),
+            host: "127.0.0.1".into(),
+            port,
+            tls: TlsMode::None,
+            user: "good".into(),
+            pass: "secret".into(),
+            verify_tls: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let upstream = stand_in().await;
+        upstream.check().await.unwrap();
+
+        let wrong = Upstream {
+            pass: "guess".into(),
+            ..upstream.clone()
+        };
+        let e = wrong.check().await.unwrap_err();
+        assert!(e.refused, "{e}");
+
+        let gone = Upstream {
+            port: 1,
+            ..upstream
+        };
+        let e = gone.check().await.unwrap_err();
+        assert!(!e.refused, "{e}");
+    }
+
+    #[tokio::test]
+    async fn test_upstream_checked_before_saving() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::init_db(&conn);
+        crate::seed_domains(&conn, &["example.com".into()]);
+        let mut config = crate::Config::defaults();
+        config.smtp_host = "127.0.0.1".into();
+        config.smtp_port = 1;
+        let state = crate::app_state(config, conn);
+        let relay = stand_in().await;
+        let create = |pass: &str, verify| {
+            create_upstream_handler(
+                State(Arc::clone(&state)),
+                Query(VerifyQuery { verify }),
+                Json(request(serde_json::json!({
+                    "name": "brand-a", "host": relay.host, "port": relay.port, "tls": "none",
+                    "user": relay.user, "pass": pass,
+                }))),
+            )
+        };
+
+        let (status, _) = create("guess", None).await.unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(load(&*state.db.lock().await, "brand-a").unwrap().is_none());
+        let (status, _) = create("secret", None).await.unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, _) = update_upstream_handler(
+            State(Arc::clone(&state)),
+            Path("brand-a".into()),
+            Query(VerifyQuery { verify: None }),
+            Json(request(serde_json::json!({ "host": relay.host, "port": 1, "tls": "none", "user": relay.user }))),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+        // Readiness covers the relays domains use: only brand-a while every
+        // domain is mapped to it, the unreachable default once one is not.
+        let Json(mapped) = put_domain_upstream_handler(
+            State(Arc::clone(&state)),
+            Path("example.com".into()),
+            Json(request(serde_json::json!({ "upstream": "brand-a" }))),
+        )
+        .await
+        .unwrap();
+        assert_eq!(mapped.upstreams, vec!["brand-a"]);
+        let (status, Json(ready)) = crate::ready_handler(State(Arc::clone(&state))).await;
+        assert_eq!(status, StatusCode::OK, "{:?}", ready.error);
+        assert_eq!(ready.upstreams, vec!["brand-a"]);
+
+        crate::seed_domains(&*state.db.lock().await, &["other.com".into()]);
+        *state.readiness.lock().await = None;
+        let (status, Json(ready)) = crate::ready_handler(State(Arc::clone(&state))).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ready.upstreams, vec![DEFAULT, "brand-a"]);
+        assert!(ready.error.unwrap().starts_with("default: "));
+    }
+
+    #[test]
+    fn test_breakers() {
+        let failover = Failover {
+            threshold: 2,
+            cooldown: Duration::from_secs(60),
+        };
+        let breakers = Breakers::default();
+        let names = vec![DEFAULT.to_string(), "backup".to_string()];
+
+        assert!(!breakers.failure(DEFAULT, "connection refused", &failover));
+        assert!(breakers.available(DEFAULT));
+        breakers.success(DEFAULT);
+        assert!(!breakers.failure(DEFAULT, "connection refused", &failover));
+        assert!(breakers.failure(DEFAULT, "connection refused", &failover));
+        assert!(!breakers.available(DEFAULT));
+        assert!(breakers.available("backup"));
+
+        let status = breakers.status(&names);
+        assert_eq!((status[0].state, status[0].consecutive_failures), ("open", 2));
+        assert_eq!(status[0].retry_in_seconds, Some(60));
+        assert_eq!(status[1].state, "closed");
+
+        // Once the cooldown is over the relay is tried again; failing reopens
+        // it at once and succeeding closes it.
+        let failover = Failover {
+            cooldown: Duration::ZERO,
+            ..failover
+        };
+        assert!(breakers.failure(DEFAULT, "connection refused", &failover));
+        assert!(breakers.available(DEFAULT));
+        assert_eq!(breakers.status(&names)[0].state, "half_open");
+        breakers.success(DEFAULT);
+        let status = breakers.status(&names);
+        assert_eq!((status[0].state, status[0].consecutive_failures), ("closed", 0));
+    }
+}
@@ -0,0 +1,158 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use rusqlite::{Connection, OptionalExtension};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{ApiError, AppState, api_error, dns};
+
+pub(crate) const ACTIVE: &str = "active";
+pub(crate) const PENDING: &str = "pending_verification";
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+/// The TXT record a domain owner publishes to prove control.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Challenge {
+    name: String,
+    value: String,
+}
+
+impl Challenge {
+    pub(crate) fn for_domain(domain: &str, challenge: &str) -> Self {
+        Self {
+            name: format!("_mayl-challenge.{domain}"),
+            value: format!("mayl-verification={challenge}"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct VerificationResponse {
+    domain: String,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    record: Option<Challenge>,
+}
+
+// ── Verification ────────────────────────────────────────────────────────────
+
+pub(crate) fn new_challenge() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// The domain's status and outstanding challenge, if it is registered.
+fn load(conn: &Connection, domain: &str) -> rusqlite::Result<Option<(String, Option<String>)>> {
+    conn.query_row(
+        "SELECT status, challenge FROM domains WHERE domain = ?1",
+        [domain],
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )
+    .optional()
+}
+
+/// Whether `challenge.name` has a TXT record equal to `challenge.value`.
+async fn published(resolver: SocketAddr, challenge: &Challenge) -> Result<bool, String> {
+    let records = dns::lookup(resolver, &challenge.name, dns::TYPE_TXT).await?;
+    Ok(records
+        .iter()
+        .any(|r| matches!(r, dns::Record::Txt(t) if t.trim() == challenge.value)))
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+pub(crate) async fn get_verification_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Result<Json<VerificationResponse>, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let (status, challenge) = load(&db, &domain)
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "domain not found"))?;
+
+    Ok(Json(VerificationResponse {
+        record: challenge.map(|c| Challenge::for_domain(&domain, &c)),
+        domain,
+        status,
+    }))
+}
+
+/// Looks up the challenge record and activates the domain once it matches.
+pub(crate) async fn verify_domain_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Result<Json<VerificationResponse>, ApiError> {
+    let domain = domain.to_lowercase();
+    let (status, challenge) = {
+        let db = state.db.lock().await;
+        load(&db, &domain)
+            .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?
+            .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "domain not found"))?
+    };
+
+    let Some(challenge) = challenge.filter(|_| status == PENDING) else {
+        return Ok(Json(VerificationResponse {
+            domain,
+            status,
+            record: None,
+        }));
+    };
+
+    // Resolve without holding the database lock
+    let record = Challenge::for_domain(&domain, &challenge);
+    let found = published(state.config.dns_resolver, &record)
+        .await
+        .map_err(|e| api_error(StatusCode::BAD_GATEWAY, e))?;
+    if !found {
+        return Err(api_error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("TXT record {} does not contain \"{}\"", record.name, record.value),
+        ));
+    }
+
+    let db = state.db.lock().await;
+    db.execute(
+        "UPDATE domains SET status = ?2, challenge = NULL WHERE domain = ?1 AND challenge = ?3",
+        rusqlite::params![&domain, ACTIVE, &challenge],
+    )
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+
+    info!(domain, "domain verified");
+    Ok(Json(VerificationResponse {
+        domain,
+        status: ACTIVE.into(),
+        record: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::tests::{stub_server, txt_rdata};
+
+    #[tokio::test]
+    async fn test_published() {
+        let challenge = Challenge::for_domain("example.com", "abc123");
+        let resolver = stub_server(vec![
+            (
+                "_mayl-challenge.example.com".into(),
+                dns::TYPE_TXT,
+                txt_rdata(&["google-site-verification=zzz"]),
+            ),
+            (
+                "_mayl-challenge.example.com".into(),
+                dns::TYPE_TXT,
+                txt_rdata(&["mayl-verification=", "abc123"]),
+            ),
+        ]);
+        assert!(published(resolver, &challenge).await.unwrap());
+        let other = Challenge::for_domain("example.com", "wrong");
+        assert!(!published(resolver, &other).await.unwrap());
+    }
+}
@@ -0,0 +1,601 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use openssl::{hash::MessageDigest, pkey::PKey, rand::rand_bytes, sign::Signer};
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use crate::{ApiError, AppState, api_error, now_millis};
+
+/// Attempts before a delivery is given up on.
+const MAX_ATTEMPTS: i64 = 8;
+const FIRST_RETRY_MS: i64 = 30_000;
+const MAX_RETRY_MS: i64 = 3_600_000;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Finished deliveries are kept this long for the delivery log.
+const LOG_RETENTION_MS: i64 = 30 * 24 * 3_600_000;
+
+// ── Models ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Event {
+    Sent,
+    Retrying,
+    Failed,
+    /// A DSN reported the message undeliverable after it was sent.
+    Bounced,
+}
+
+impl Event {
+    fn as_str(self) -> &'static str {
+        match self {
+            Event::Sent => "sent",
+            Event::Retrying => "retrying",
+            Event::Failed => "failed",
+            Event::Bounced => "bounced",
+        }
+    }
+}
+
+/// The message an event is about.
+pub(crate) struct EmailEvent<'a> {
+    pub(crate) id: &'a str,
+    pub(crate) from: &'a str,
+    pub(crate) to: &'a [String],
+    pub(crate) error: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct WebhookRequest {
+    url: String,
+    /// Events to deliver; all of them when empty.
+    #[serde(default)]
+    events: Vec<Event>,
+    /// Signing secret; generated when omitted.
+    secret: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct WebhookEntry {
+    id: String,
+    url: String,
+    events: Vec<Event>,
+    /// Only returned when the webhook is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+    created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DeliveryEntry {
+    id: String,
+    event: String,
+    status: String,
+    attempts: i64,
+    response_status: Option<u16>,
+    last_error: Option<String>,
+    created_at: i64,
+    next_attempt_at: Option<i64>,
+    delivered_at: Option<i64>,
+    payload: serde_json::Value,
+}
+
+// ── Database ────────────────────────────────────────────────────────────────
+
+pub(crate) fn init_db(conn: &Connection) {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS webhooks (
+            id TEXT PRIMARY KEY,
+            domain TEXT NOT NULL,
+            url TEXT NOT NULL,
+            secret TEXT NOT NULL,
+            events TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS webhook_deliveries (
+            id TEXT PRIMARY KEY,
+            webhook_id TEXT NOT NULL,
+            event TEXT NOT NULL,
+            payload TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending',
+            attempts INTEGER NOT NULL DEFAULT 0,
+            response_status INTEGER,
+            last_error TEXT,
+            created_at INTEGER NOT NULL,
+            next_attempt_at INTEGER NOT NULL,
+            delivered_at INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS idx_webhooks_domain ON webhooks(domain);
+        CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due ON webhook_deliveries(status, next_attempt_at);",
+    )
+    .expect("failed to initialize webhook tables");
+}
+
+/// Removes a deleted domain's webhooks and their delivery log.
+pub(crate) fn delete_domain(conn: &Connection, domain: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM webhook_deliveries WHERE webhook_id IN (SELECT id FROM webhooks WHERE domain = ?1)",
+        [domain],
+    )?;
+    conn.execute("DELETE FROM webhooks WHERE domain = ?1", [domain])?;
+    Ok(())
+}
+
+/// Queues a delivery of `event` to every webhook of `domain` subscribed to
+/// it. Failures are logged; they never fail the send itself.
+pub(crate) fn emit(conn: &Connection, domain: &str, event: Event, email: &EmailEvent) {
+    let now = now_millis();
+    let payload = serde_json::json!({
+        "event": event,
+        "message_id": email.id,
+        "domain": domain,
+        "from": email.from,
+        "to": email.to,
+        "error": email.error,
+        "timestamp": now,
+    })
+    .to_string();
+
+    let result = conn
+        .prepare_cached("SELECT id, events FROM webhooks WHERE domain = ?1")
+        .and_then(|mut stmt| {
+            let hooks = stmt
+                .query_map([domain], |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            for (webhook_id, events) in hooks {
+                let events: Vec<Event> = serde_json::from_str(&events).unwrap_or_default();
+                if !events.is_empty() && !events.contains(&event) {
+                    continue;
+                }
+                conn.execute(
+                    "INSERT INTO webhook_deliveries (id, webhook_id, event, payload, created_at, next_attempt_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?5)",
+                    rusqlite::params![
+                        uuid::Uuid::new_v4().to_string(),
+                        webhook_id,
+                        event.as_str(),
+                        &payload,
+                        now
+                    ],
+                )?;
+            }
+            Ok(())
+        });
+    if let Err(e) = result {
+        error!(domain, event = event.as_str(), "failed to queue webhook deliveries: {e}");
+    }
+}
+
+// ── Delivery ────────────────────────────────────────────────────────────────
+
+/// `sha256=<hex HMAC-SHA256 of "{timestamp}.{body}">`, so a captured request
+/// cannot be replayed with a different timestamp.
+pub(crate) fn signature(secret: &str, timestamp: i64, body: &str) -> Result<String, String> {
+    let key = PKey::hmac(secret.as_bytes()).map_err(|e| format!("hmac: {e}"))?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).map_err(|e| format!("hmac: {e}"))?;
+    let tag = signer
+        .sign_oneshot_to_vec(format!("{timestamp}.{body}").as_bytes())
+        .map_err(|e| format!("hmac: {e}"))?;
+    Ok(format!("sha256={}", hex(&tag)))
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Delay before the attempt after `attempts` failures: 30s doubling to 1h.
+fn backoff_ms(attempts: i64) -> i64 {
+    let exp = (attempts - 1).clamp(0, 20) as u32;
+    FIRST_RETRY_MS.saturating_mul(1 << exp).min(MAX_RETRY_MS)
+}
+
+struct Due {
+    id: String,
+    event: String,
+    payload: String,
+    attempts: i64,
+    url: String,
+    secret: String,
+}
+
+/// POSTs a signed JSON body, returning the response status on success.
+/// Inbound mail routes are signed the same way as delivery events.
+pub(crate) async fn post_signed(
+    client: &reqwest::Client,
+    url: &str,
+    secret: &str,
+    event: &str,
+    delivery_id: &str,
+    body: &str,
+) -> Result<u16, (Option<u16>, String)> {
+    let timestamp = now_millis() / 1000;
+    let signature = signature(secret, timestamp, body).map_err(|e| (None, e))?;
+    let response = client
+        .post(url)
+        .timeout(REQUEST_TIMEOUT)
+        .header("Content-Type", "application/json")
+        .header("X-Mayl-Event", event)
+        .header("X-Mayl-Delivery", delivery_id)
+        .header("X-Mayl-Timestamp", timestamp.to_string())
+        .header("X-Mayl-Signature", signature)
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| (None, e.to_string()))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(status.as_u16())
+    } else {
+        Err((Some(status.as_u16()), format!("endpoint returned {status}")))
+    }
+}
+
+/// Attempts every delivery that is due and reschedules or gives up on the
+/// ones that fail. Returns how many were attempted.
+async fn deliver_due(db: &Mutex<Connection>, client: &reqwest::Client) -> usize {
+    let due: Vec<Due> = {
+        let db = db.lock().await;
+        let mut stmt = match db.prepare(
+            "SELECT d.id, d.event, d.payload, d.attempts, w.url, w.secret
+             FROM webhook_deliveries d JOIN webhooks w ON w.id = d.webhook_id
+             WHERE d.status = 'pending' AND d.next_attempt_at <= ?1
+             ORDER BY d.next_attempt_at LIMIT 20",
+        ) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("webhook worker prepare: {e}");
+                return 0;
+            }
+        };
+        stmt.query_map([now_millis()], |r| {
+            Ok(Due {
+                id: r.get(0)?,
+                event: r.get(1)?,
+                payload: r.get(2)?,
+                attempts: r.get(3)?,
+                url: r.get(4)?,
+                secret: r.get(5)?,
+            })
+        })
+        .map(|rows| rows.filter_map(|r| r.ok()).collect())
+        .unwrap_or_default()
+    };
+
+    for d in &due {
+        let result = post_signed(client, &d.url, &d.secret, &d.event, &d.id, &d.payload).await;
+        let attempts = d.attempts + 1;
+        let now = now_millis();
+        let db = db.lock().await;
+        let updated = match result {
+            Ok(code) => db.execute(
+                "UPDATE webhook_deliveries SET status = 'delivered', attempts = ?2, response_status = ?3,
+                 last_error = NULL, delivered_at = ?4 WHERE id = ?1",
+                rusqlite::params![&d.id, attempts, code, now],
+            ),
+            Err((code, e)) if attempts >= MAX_ATTEMPTS => {
+                warn!(delivery = d.id, url = d.url, "webhook delivery failed permanently: {e}");
+                db.execute(
+                    "UPDATE webhook_deliveries SET status = 'failed', attempts = ?2, response_status = ?3,
+                     last_error = ?4 WHERE id = ?1",
+                    rusqlite::params![&d.id, attempts, code, e],
+                )
+            }
+            Err((code, e)) => {
+                warn!(delivery = d.id, url = d.url, attempts, "webhook delivery failed: {e}");
+                db.execute(
+                    "UPDATE webhook_deliveries SET attempts = ?2, response_status = ?3, last_error = ?4,
+                     next_attempt_at = ?5 WHERE id = ?1",
+                    rusqlite::params![&d.id, attempts, code, e, now + backoff_ms(attempts)],
+                )
+            }
+        };
+        if let Err(e) = updated {
+            error!(delivery = d.id, "failed to record webhook delivery: {e}");
+        }
+    }
+    due.len()
+}
+
+pub(crate) async fn worker(state: Arc<AppState>) {
+    let poll_interval = Duration::from_secs(state.config.queue_poll_seconds);
+    let client = reqwest::Client::new();
+
+    loop {
+        tokio::time::sleep(poll_interval).await;
+        deliver_due(&state.db, &client).await;
+
+        let db = state.db.lock().await;
+        let _ = db.execute(
+            "DELETE FROM webhook_deliveries WHERE status != 'pending' AND created_at < ?1",
+            [now_millis() - LOG_RETENTION_MS],
+        );
+    }
+}
+
+// ── Handlers ────────────────────────────────────────────────────────────────
+
+pub(crate) async fn create_webhook_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+    Json(payload): Json<WebhookRequest>,
+) -> Result<(StatusCode, Json<WebhookEntry>), ApiError> {
+    let domain = domain.to_lowercase();
+    let url = reqwest::Url::parse(&payload.url)
+        .ok()
+        .filter(|u| matches!(u.scheme(), "http" | "https"))
+        .ok_or_else(|| api_error(StatusCode::BAD_REQUEST, "url must be an http(s) URL"))?;
+
+    let secret = match payload.secret.filter(|s| !s.is_empty()) {
+        Some(s) => s,
+        None => {
+            let mut raw = [0u8; 32];
+            rand_bytes(&mut raw).map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            hex(&raw)
+        }
+    };
+
+    let db = state.db.lock().await;
+    db.query_row("SELECT 1 FROM domains WHERE domain = ?1", [&domain], |_| Ok(()))
+        .optional()
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "domain not found"))?;
+
+    let entry = WebhookEntry {
+        id: uuid::Uuid::new_v4().to_string(),
+        url: url.to_string(),
+        events: payload.events,
+        secret: Some(secret),
+        created_at: now_millis(),
+    };
+    db.execute(
+        "INSERT INTO webhooks (id, domain, url, secret, events, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![
+            &entry.id,
+            &domain,
+            &entry.url,
+            &entry.secret,
+            serde_json::to_string(&entry.events).unwrap(),
+            entry.created_at
+        ],
+    )
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?;
+
+    info!(domain, id = entry.id, url = entry.url, "webhook created");
+    Ok((StatusCode::CREATED, Json(entry)))
+}
+
+pub(crate) async fn list_webhooks_handler(
+    State(state): State<Arc<AppState>>,
+    Path(domain): Path<String>,
+) -> Json<Vec<WebhookEntry>> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let mut stmt = db
+        .prepare("SELECT id, url, events, created_at FROM webhooks WHERE domain = ?1 ORDER BY created_at")
+        .unwrap();
+    let entries: Vec<WebhookEntry> = stmt
+        .query_map([&domain], |row| {
+            Ok(WebhookEntry {
+                id: row.get(0)?,
+                url: row.get(1)?,
+                events: serde_json::from_str(&row.get::<_, String>(2)?).unwrap_or_default(),
+                secret: None,
+                created_at: row.get(3)?,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Json(entries)
+}
+
+pub(crate) async fn delete_webhook_handler(
+    State(state): State<Arc<AppState>>,
+    Path((domain, id)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    let deleted = db
+        .execute("DELETE FROM webhooks WHERE domain = ?1 AND id = ?2", [&domain, &id])
+        .unwrap_or(0);
+
+    if deleted == 0 {
+        Err(api_error(StatusCode::NOT_FOUND, "webhook not found"))
+    } else {
+        let _ = db.execute("DELETE FROM webhook_deliveries WHERE webhook_id = ?1", [&id]);
+        info!(domain, id, "webhook deleted");
+        Ok(StatusCode::NO_CONTENT)
+    }
+}
+
+/// The delivery log of one webhook, newest first.
+pub(crate) async fn list_deliveries_handler(
+    State(state): State<Arc<AppState>>,
+    Path((domain, id)): Path<(String, String)>,
+) -> Result<Json<Vec<DeliveryEntry>>, ApiError> {
+    let domain = domain.to_lowercase();
+    let db = state.db.lock().await;
+    db.query_row(
+        "SELECT 1 FROM webhooks WHERE domain = ?1 AND id = ?2",
+        [&domain, &id],
+        |_| Ok(()),
+    )
+    .optional()
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, format!("db error: {e}")))?
+    .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "webhook not found"))?;
+
+    let mut stmt = db
+        .prepare(
+            "SELECT id, event, status, attempts, response_status, last_error, created_at,
+                    next_attempt_at, delivered_at, payload
+             FROM webhook_deliveries WHERE webhook_id = ?1
+             ORDER BY created_at DESC LIMIT 100",
+        )
+        .unwrap();
+    let entries: Vec<DeliveryEntry> = stmt
+        .query_map([&id], |row| {
+            let status: String = row.get(2)?;
+            Ok(DeliveryEntry {
+                id: row.get(0)?,
+                event: row.get(1)?,
+                attempts: row.get(3)?,
+                response_status: row.get(4)?,
+                last_error: row.get(5)?,
+                created_at: row.get(6)?,
+                next_attempt_at: (status == "pending").then(|| row.get(7)).transpose()?,
+                delivered_at: row.get(8)?,
+                payload: serde_json::from_str(&row.get::<_, String>(9)?).unwrap_or_default(),
+                status,
+            })
+        })
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .collect();
+
+    Ok(Json(entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, http::HeaderMap, routing::post};
+
+    fn add_webhook(conn: &Connection, id: &str, url: &str, events: &str) {
+        conn.execute(
+            "INSERT INTO webhooks VALUES (?1, 'example.com', ?2, 'secret', ?3, 0)",
+            [id, url, events],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_signature_and_backoff() {
+        // echo -n '1700000000.{}' | openssl dgst -sha256 -hmac secret
+        assert_eq!(
+            signature("secret", 1_700_000_000, "{}").unwrap(),
+            "sha256=b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"
+        );
+        assert_eq!(backoff_ms(1), 30_000);
+        assert_eq!(backoff_ms(2), 60_000);
+        assert_eq!(backoff_ms(MAX_ATTEMPTS), MAX_RETRY_MS);
+    }
+
+    #[test]
+    fn test_emit_filters_events() {
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        add_webhook(&conn, "all", "http://a.example/", "[]");
+        add_webhook(&conn, "failures", "http://b.example/", r#"["failed"]"#);
+
+        let to = vec!["ada@example.org".to_string()];
+        let email = EmailEvent {
+            id: "m1",
+            from: "app@example.com",
+            to: &to,
+            error: None,
+        };
+        emit(&conn, "example.com", Event::Sent, &email);
+        emit(&conn, "example.com", Event::Failed, &email);
+        emit(&conn, "other.com", Event::Failed, &email);
+
+        let mut rows: Vec<(String, String)> = conn
+            .prepare("SELECT webhook_id, event FROM webhook_deliveries ORDER BY webhook_id, event")
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        rows.sort();
+        assert_eq!(
+            rows,
+            vec![
+                ("all".into(), "failed".into()),
+                ("all".into(), "sent".into()),
+                ("failures".into(), "failed".into()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deliver_and_retry() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<(HeaderMap, String)>();
+        let app = Router::new()
+            .route(
+                "/ok",
+                post(move |headers: HeaderMap, body: String| {
+                    let tx = tx.clone();
+                    async move {
+                        tx.send((headers, body)).unwrap();
+                        StatusCode::NO_CONTENT
+                    }
+                }),
+            )
+            .route("/down", post(|| async { StatusCode::SERVICE_UNAVAILABLE }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let conn = Connection::open_in_memory().unwrap();
+        init_db(&conn);
+        add_webhook(&conn, "ok", &format!("http://{addr}/ok"), "[]");
+        add_webhook(&conn, "down", &format!("http://{addr}/down"), "[]");
+        let to = vec!["ada@example.org".to_string()];
+        emit(
+            &conn,
+            "example.com",
+            Event::Retrying,
+            &EmailEvent {
+                id: "m1",
+                from: "app@example.com",
+                to: &to,
+                error: Some("connection refused"),
+            },
+        );
+
+        let db = Mutex::new(conn);
+        assert_eq!(deliver_due(&db, &reqwest::Client::new()).await, 2);
+
+        let (headers, body) = rx.recv().await.unwrap();
+        let timestamp: i64 = headers["x-mayl-timestamp"].to_str().unwrap().parse().unwrap();
+        assert_eq!(
+            headers["x-mayl-signature"].to_str().unwrap(),
+            signature("secret", timestamp, &body).unwrap()
+        );
+        assert_eq!(headers["x-mayl-event"], "retrying");
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["message_id"], "m1");
+        assert_eq!(json["error"], "connection refused");
+
+        let conn = db.lock().await;
+        let rows: Vec<(String, String, i64, Option<u16>)> = conn
+            .prepare(
+                "SELECT webhook_id, status, attempts, response_status
+                 FROM webhook_deliveries ORDER BY webhook_id",
+            )
+            .unwrap()
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("down".into(), "pending".into(), 1, Some(503)),
+                ("ok".into(), "delivered".into(), 1, Some(204)),
+            ]
+        );
+        drop(conn);
+
+        // The failed delivery waits out its backoff
+        assert_eq!(deliver_due(&db, &reqwest::Client::new()).await, 0);
+    }
+}